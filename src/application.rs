@@ -1,5 +1,8 @@
-use std::collections::HashSet;
 use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use iced::window;
 
 use iced::keyboard;
 use iced::alignment::Horizontal;
@@ -11,6 +14,7 @@ use iced::widget::container;
 use iced::widget::Column;
 use iced::widget::button;
 use iced::widget::text;
+use iced::widget::toggler;
 use iced::Subscription;
 use iced::Background;
 use iced::Element;
@@ -25,9 +29,8 @@ use rand::seq::SliceRandom;
 use rfd::FileDialog;
 
 use crate::filemanager::download_thumbnail;
-use crate::audio::get_progress;
-use crate::downloader::convert_and_save_song;
-use crate::music::{Song, local_search, cloud_search};
+use crate::downloader::save_uploaded_song;
+use crate::music::{Song, provider_search, import_remote_playlist, LIKED_PLAYLIST_ID, MAX_RATING};
 use crate::filemanager::get_application_directory;
 use crate::widgets::playlist_name_widget;
 use crate::widgets::download_song_widget;
@@ -39,27 +42,76 @@ use crate::widgets::upload_album_entry;
 use crate::widgets::upload_artist_entry;
 use crate::widgets::upload_song_entry;
 use crate::widgets::container_field;
+use crate::widgets::error_widget;
+use crate::widgets::downloads_widget;
+use crate::widgets::playback_widget;
+use crate::widgets::import_widget;
+use crate::widgets::watch_folders_widget;
+use crate::widgets::unmatched_entries_widget;
+use crate::widgets::smart_playlist_editor;
+use crate::widgets::{equalizer_widget, preset_picker};
+use crate::widgets::{library_browser, library_controls, library_song_widget, statistics_widget};
 use crate::widgets::ResonateColour;
 use crate::filemanager::Database;
-use crate::downloader::download;
 use crate::widgets::search_bar;
 use crate::audio::AudioPlayer;
+use crate::audio::RepeatMode;
 use crate::music::Playlist;
+use crate::search::{is_url, merge_hits, ProviderRegistry, SearchHit, SearchSource};
+use crate::error::{ResonateError, ResonateResult};
+use crate::download_manager::{DownloadManager, DEFAULT_DOWNLOAD_LIMIT};
+use crate::tagger::retag_library;
+use crate::importer::{import_file, import_folder, is_local_id, ImportProgress};
+use crate::watcher::{FolderWatcher, WatchStatus, WATCH_FOLDERS_SETTING};
+use crate::loudness::{scan_loudness, Normalization, ReplayGain};
+use crate::equalizer::{format_gains, parse_gains, EqPreset, EqualizerSettings, PresetChoice, MAX_BAND_GAIN};
+use crate::library::{DownloadFilter, LibraryEntry, LibraryView, SortColumn};
+use crate::statistics::Statistics;
+use crate::smart_playlist::{Rule, RuleField, RuleOperator, SmartPlaylist, SmartSort};
+use crate::playlist_file::{export_playlist, import_playlist, PlaylistEntry, PlaylistFormat};
 use crate::utility::*;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Message {
     Search,
     SearchBarInput(String),
-    SearchResults(Vec<Song>),
+    SearchResults(Vec<SearchHit>),
+    SearchFailed(ResonateError),
     DumpDB,
-    ToggleYTSearch(bool),
-    Download(Song, PathBuf, Playlist),
+    ToggleProvider(SearchSource, bool),
+    Download(Song, Playlist),
+    // The id the search hit had, the imported song and the playlist it was added to
+    FileImported(String, Song, Playlist),
     SuccessfulDownload(Song),
+    DownloadFailed(Song, ResonateError),
+    CancelDownload(Song),
+    RetryDownload(Song),
+    ClearDownloads,
+    SetDownloadLimit(usize),
     SearchPlaylists,
     NewPlaylist,
     CreateNewPlaylist,
+    CreateSmartPlaylist,
     OpenPlaylist(Playlist),
+    EditPlaylist,
+    RenamePlaylist,
+    DeletePlaylist,
+    RemoveSongFromPlaylist(Song),
+    MoveSongInPlaylist(usize, usize),
+    SetRuleMatch(bool),
+    AddRule,
+    RemoveRule(usize),
+    SetRuleField(usize, RuleField),
+    SetRuleOperator(usize, RuleOperator),
+    SetRuleValue(usize, String),
+    SetSmartSort(SmartSort),
+    SetSmartDescending(bool),
+    SetSmartLimit(String),
+    SaveRules,
+    RateSong(Song, u8),
+    FavouriteSong(Song, bool),
+    RateCurrent(u8),
+    ToggleFavouriteCurrent,
     AddSongs,
     Homepage,
     Play(Song),
@@ -67,26 +119,95 @@ pub enum Message {
     Resume,
     Queue(Song),
     Skip,
+    Previous,
+    PlayNext(Song),
+    RemoveFromQueue(usize),
+    MoveInQueue(usize, usize),
+    JumpToQueued(usize),
+    ClearQueue,
     ShuffleCurrent,
-    ProgressUpdate(f32),
-    Slow,
-    Normal,
-    Fast,
+    ProgressUpdate,
+    SeekDrag(f32),
+    SeekRelease,
+    SeekRelative(f32),
+    SetSpeed(f32),
+    SetPitch(f32),
+    ResetSpeed,
     UploadFile,
     FileSelected(Option<PathBuf>),
     NameChanged(String),
     ArtistChanged(String),
     AlbumChanged(String),
     AttemptAddingSong,
+    SongUploaded(Song),
     SetVolume(f32),
+    SetCrossfade(f32),
+    SetNormalization(Normalization),
+    ScanLoudness,
+    LoudnessScanned(Vec<(usize, ReplayGain)>),
+    LoudnessScanFailed(ResonateError),
+    OpenEqualizer,
+    SetEqualizerEnabled(bool),
+    SetBandGain(usize, f32),
+    ApplyPreset(String),
+    PresetNameInput(String),
+    SavePreset,
+    DeletePreset(String),
+    SetSongPreset(Song, PresetChoice),
+    SetPlaylistPreset(PresetChoice),
     PauseClicked,
-    DebugPrint(String),
-    SetLooping(bool),
+    SetRepeat(RepeatMode),
+    SetShuffle(bool),
     CheckAllIcons,
     DownloadThumbnail(Song),
-    ThumbnailDownloaded
+    ThumbnailDownloaded,
+    RetagLibrary,
+    OpenLibrary,
+    LibraryFilterInput(String),
+    SortLibrary(SortColumn),
+    SetDownloadFilter(DownloadFilter),
+    BrowseArtist(Option<String>),
+    BrowseAlbum(Option<usize>),
+    OpenStatistics,
+    QueueDownload(Song),
+    ImportLink,
+    LinkImported(Playlist),
+    LinkImportFailed(ResonateError),
+    LibraryRetagged(usize, Vec<ResonateError>),
+    ImportFolder,
+    ImportFolderSelected(Option<PathBuf>),
+    ImportFinished(ImportProgress),
+    DismissImport,
+    ExportPlaylist,
+    ExportPathSelected(Option<PathBuf>),
+    SetRelativeExport(bool),
+    ImportPlaylistFile,
+    PlaylistFileSelected(Option<PathBuf>),
+    PlaylistImported(Playlist, Vec<PlaylistEntry>),
+    SearchUnmatched(PlaylistEntry),
+    DismissUnmatched,
+    WatchFolder,
+    WatchFolderSelected(Option<PathBuf>),
+    UnwatchFolder(PathBuf),
+    Error(ResonateError),
+    DismissError(usize),
+    RestoreSession,
+    SaveSession,
+    CloseRequested(window::Id)
 }
 
+const DOWNLOAD_LIMIT_SETTING: &str = "download_limit";
+const CROSSFADE_SETTING: &str = "crossfade";
+const NORMALIZATION_SETTING: &str = "normalization";
+const EQUALIZER_ENABLED_SETTING: &str = "equalizer_enabled";
+const EQUALIZER_GAINS_SETTING: &str = "equalizer_gains";
+
+/// How often the progress bar is updated, which also drives other periodic checks.
+const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often the playback session is saved while the app is running.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
 // The underlying application state
 
 #[derive(Default, Clone, PartialEq)]
@@ -95,8 +216,12 @@ pub enum State {
     SearchPlaylists,
     Search,
     MakePlaylist,
+    EditPlaylist,
     Playlist,
-    UploadFile
+    UploadFile,
+    Library,
+    Statistics,
+    Equalizer
 }
 
 pub struct Application {
@@ -106,72 +231,297 @@ pub struct Application {
 
     // Backends
     database: AM<Database>,
-    buffer: AMV<Song>,
+    buffer: AMV<SearchHit>,
     playlist_buffer: Vec<Playlist>,
     search_bar: String,
     
     active_search_threads: usize,
-    use_online_search: bool,
+    providers: ProviderRegistry,
 
-    currently_download_songs: HashSet<Song>,
-    download_queue: Vec<Song>,
+    downloads: DownloadManager,
 
     // Targetted playlist
     target_playlist: Option<Playlist>,
+    // Unsaved rules of the smart playlist being edited, with the limit as typed
+    smart_draft: SmartPlaylist,
+    smart_limit: String,
 
     audio_player: AudioPlayer,
     progress: f32,
     progress_source: AM<f32>,
-    is_progress_running: bool,
+    seek_preview: Option<f32>,
+    last_session_save: Instant,
 
     // For file selection
     selected_file: Option<PathBuf>,
     selected_name: String,
     selected_artist: String,
     selected_album: String,
+
+    is_retagging: bool,
+    is_scanning_loudness: bool,
+    // Another scan was asked for while one was running, e.g. because more songs finished downloading
+    rescan_loudness: bool,
+    library: Vec<LibraryEntry>,
+    library_view: LibraryView,
+    statistics: Statistics,
+    // Name typed in for saving the equalizer's gains as a preset
+    preset_name: String,
+    is_importing_link: bool,
+    import_progress: Option<AM<ImportProgress>>,
+    // Playlist export and import
+    relative_export: bool,
+    // Entries of an imported playlist file that matched no song, with the playlist's id
+    unmatched_entries: Option<(usize, Vec<PlaylistEntry>)>,
+
+    // None if the platform's file watcher could not be started
+    watcher: Option<FolderWatcher>,
+    // As last seen, so that the missing count is only recounted when the watcher has done something
+    watch_status: WatchStatus,
+    missing_songs: usize,
+
+    // Errors waiting to be shown to the user
+    errors: Vec<ResonateError>
 }
 
-impl std::default::Default for Application {
-    fn default() -> Self {
-        let directory: PathBuf = get_application_directory().unwrap();
-        Self::new(Database::new(directory.clone()))
+/// The app, or the error that stopped it from starting. Nothing can run without a library or an
+/// output device, so failing to open either leaves only the error on screen.
+pub enum Resonate {
+    Running(Box<Application>),
+    Failed(ResonateError)
+}
+
+impl Resonate {
+    pub fn start() -> Self {
+        // Includes data.db still being locked by another instance after the busy timeout
+        let startup = get_application_directory()
+            .and_then(Database::new)
+            .and_then(|database| Ok((database, AudioPlayer::new()?)));
+
+        match startup {
+            Ok((database, audio_player)) => Self::Running(Box::new(Application::new(database, audio_player))),
+            Err(e) => {
+                println!("[STARTUP] {e}");
+                Self::Failed(e)
+            }
+        }
+    }
+
+    pub fn update(&mut self, message: Message) -> Task<Message> {
+        match self {
+            Self::Running(application) => application.update(message),
+            // There is nothing to go back to once the error is dismissed
+            Self::Failed(_) => match message {
+                Message::CloseRequested(_) | Message::DismissError(_) => iced::exit(),
+                _ => Task::none()
+            }
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let error = match self {
+            Self::Running(application) => return application.view(),
+            Self::Failed(error) => error
+        };
+
+        let page = Column::new()
+            .spacing(10)
+            .push(text("Resonate could not start").size(30).color(ResonateColour::text_emphasis()))
+            .push(error_widget(0, error));
+
+        Container::new(page)
+            .padding(20)
+            .style(|_theme| {
+                container::Style::default().background(
+                    Background::Color(Color::from_rgb(0.1f32, 0.1f32, 0.1f32))
+                )
+            })
+            .height(Length::Fill)
+            .width(Length::Fill)
+            .into()
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        match self {
+            Self::Running(application) => application.subscription(),
+            Self::Failed(_) => window::close_requests().map(Message::CloseRequested)
+        }
     }
 }
 
 impl Application {
-    pub fn new(database: Database) -> Self {
+    pub fn new(database: Database, audio_player: AudioPlayer) -> Self {
 
-        let audio_player = AudioPlayer::new().unwrap();
         let progress_source = audio_player.get_progress_source();
+        let directory = database.get_directory();
+        let download_limit = database.get_setting(DOWNLOAD_LIMIT_SETTING)
+            .ok()
+            .flatten()
+            .and_then(|limit| limit.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_DOWNLOAD_LIMIT);
+        if let Some(crossfade) = database.get_setting(CROSSFADE_SETTING).ok().flatten().and_then(|crossfade| crossfade.parse::<f32>().ok()) {
+            audio_player.set_crossfade(crossfade);
+        }
+        if let Some(normalization) = database.get_setting(NORMALIZATION_SETTING).ok().flatten().and_then(|normalization| Normalization::from_key(&normalization)) {
+            audio_player.set_normalization(normalization);
+        }
+        let mut equalizer = EqualizerSettings::default();
+        if let Some(enabled) = database.get_setting(EQUALIZER_ENABLED_SETTING).ok().flatten() {
+            equalizer.enabled = enabled == "true";
+        }
+        if let Some(gains) = database.get_setting(EQUALIZER_GAINS_SETTING).ok().flatten().and_then(|gains| parse_gains(&gains)) {
+            equalizer.gains = gains;
+        }
+        equalizer.presets.extend(database.retrieve_eq_presets().unwrap_or_default());
+        audio_player.set_equalizer(equalizer);
+        let watch_folders = database.get_setting(WATCH_FOLDERS_SETTING)
+            .ok()
+            .flatten()
+            .map(|folders| folders.lines().map(PathBuf::from).collect::<Vec<PathBuf>>())
+            .unwrap_or_default();
+        let missing_songs = database.count_missing_songs().unwrap_or_default();
+        let database = sync(database);
+
+        // Neither a missing watch folder nor a missing watcher should stop the app from starting
+        let mut errors = Vec::new();
+        let watcher = match FolderWatcher::new(database.clone()) {
+            Ok(mut watcher) => {
+                watch_folders.into_iter().for_each(|folder| if let Err(e) = watcher.watch(folder) { errors.push(e) });
+                Some(watcher)
+            }
+            Err(e) => { errors.push(e); None }
+        };
 
         Self {
             state: State::default(),
-            database: sync(database),
+            providers: ProviderRegistry::new(database.clone()),
+            downloads: DownloadManager::new(directory, download_limit),
+            database,
             buffer: sync(vec![]),
             search_bar: String::new(),
             active_search_threads: 0,
-            use_online_search: true,
-            currently_download_songs: HashSet::<Song>::new(),
-            download_queue: Vec::<Song>::new(),
             target_playlist: None,
+            smart_draft: SmartPlaylist::default(),
+            smart_limit: String::new(),
             playlist_buffer: Vec::new(),
             audio_player,
             progress: 0f32,
             progress_source,
-            is_progress_running: false,
+            seek_preview: None,
+            last_session_save: Instant::now(),
             selected_file: None,
             selected_name: String::new(),
             selected_album: String::new(),
             selected_artist: String::new(),
+            is_retagging: false,
+            is_scanning_loudness: false,
+            rescan_loudness: false,
+            library: Vec::new(),
+            library_view: LibraryView::default(),
+            statistics: Statistics::default(),
+            preset_name: String::new(),
+            is_importing_link: false,
+            import_progress: None,
+            relative_export: false,
+            unmatched_entries: None,
+            watcher,
+            watch_status: WatchStatus::default(),
+            missing_songs,
+            errors
         }
     }
 
     fn get_db_ref(&self) -> AM<Database> { self.database.clone() }
 
+    /// Changes the equalizer, storing its gains and whether it is enabled.
+    fn update_equalizer(&mut self, update: impl FnOnce(&mut EqualizerSettings)) -> Task<Message> {
+        let mut equalizer = self.audio_player.get_equalizer();
+        update(&mut equalizer);
+        let database = self.database.lock().unwrap();
+        let result = database.set_setting(EQUALIZER_ENABLED_SETTING, &equalizer.enabled.to_string())
+            .and_then(|()| database.set_setting(EQUALIZER_GAINS_SETTING, &format_gains(&equalizer.gains)));
+        self.audio_player.set_equalizer(equalizer);
+        match result {
+            Ok(()) => Task::none(),
+            Err(e) => Task::done(Message::Error(e))
+        }
+    }
+
+    /// Starts the scan that was asked for while the last one ran, if any.
+    fn finish_loudness_scan(&mut self) -> Task<Message> {
+        self.is_scanning_loudness = false;
+        match std::mem::take(&mut self.rescan_loudness) {
+            true => Task::done(Message::ScanLoudness),
+            false => Task::none()
+        }
+    }
+
+    fn update_missing_songs(&mut self) {
+        self.missing_songs = self.database.lock().unwrap().count_missing_songs().unwrap_or_default();
+    }
+
+    fn save_watch_folders(&self) -> ResonateResult<()> {
+        let folders = self.watcher.as_ref().map(|watcher| watcher.get_folders()).unwrap_or_default();
+        let value = folders.iter().map(|folder| folder.to_string_lossy().to_string()).collect::<Vec<String>>().join("\n");
+        self.database.lock().unwrap().set_setting(WATCH_FOLDERS_SETTING, &value)
+    }
+
+    fn record_download(&mut self, song: Song) -> ResonateResult<()> {
+        let database = self.database.lock().unwrap();
+        println!("[RUNTIME] About to add {}. Is_some: {}.", song.name, self.target_playlist.is_some());
+        if let Some(playlist) = self.target_playlist.as_mut().filter(|playlist| playlist.smart.is_none()) {
+            database.add_song_to_playlist(&song, playlist)?;
+        }
+        database.update(song)
+    }
+
+    /// Stores a song's rating and favourite, then updates every copy of the song on screen.
+    fn record_preference(&mut self, song: Song) -> ResonateResult<()> {
+        // Songs that were never cached have nowhere to keep a rating
+        if song.sql_id == 0 { return Ok(()) }
+        self.database.lock().unwrap().update_preference(&song)?;
+        self.update_song(song.sql_id, |s| { s.rating = song.rating; s.favourite = song.favourite; });
+
+        match self.target_playlist.as_mut() {
+            // Liking or unliking a song changes what Liked Songs holds
+            Some(playlist) if playlist.id == LIKED_PLAYLIST_ID => self.database.lock().unwrap().load_playlist(playlist),
+            _ => Ok(())
+        }
+    }
+
+    /// Applies a change to every copy of a song on screen and in the audio player.
+    fn update_song(&mut self, sql_id: usize, update: impl Fn(&mut Song)) {
+        self.buffer.lock().unwrap().iter_mut().filter(|hit| hit.song.sql_id == sql_id).for_each(|hit| update(&mut hit.song));
+        self.library.iter_mut().filter(|entry| entry.song.sql_id == sql_id).for_each(|entry| update(&mut entry.song));
+        if let Some(songs) = self.target_playlist.as_mut().and_then(|playlist| playlist.songs.as_mut()) {
+            songs.iter_mut().filter(|s| s.sql_id == sql_id).for_each(&update);
+        }
+        self.audio_player.update_song(sql_id, update);
+    }
+
+    /// Writes the plays counted by the audio player to the history.
+    fn record_plays(&mut self) -> ResonateResult<()> {
+        let database = self.database.lock().unwrap();
+        // Songs that were never cached have nothing to attach a play to
+        for play in self.audio_player.take_plays().into_iter().filter(|play| play.song.sql_id != 0) {
+            database.record_play(&play)?;
+            self.library.iter_mut().filter(|entry| entry.song.sql_id == play.song.sql_id).for_each(|entry| entry.plays += 1);
+        }
+        Ok(())
+    }
+
+    /// Puts a freshly uploaded song into the playlist that was open when it was added.
+    fn add_uploaded_song(&mut self, song: Song) -> ResonateResult<()> {
+        if let Some(playlist) = self.target_playlist.as_mut().filter(|playlist| playlist.smart.is_none()) {
+            self.database.lock().unwrap().add_song_to_playlist(&song, playlist)?;
+        }
+        Ok(())
+    }
+
     pub fn update(&mut self, message: Message) -> Task<Message> {
-        let task = match message {
+        match message {
             Message::Search => {
-                if self.search_bar.len() == 0 {
+                if self.search_bar.is_empty() {
                     let mut buf = self.buffer.lock().unwrap();
                     buf.clear();
                     Task::none()
@@ -179,24 +529,28 @@ impl Application {
                     if self.active_search_threads != 0 { return Task::none() }
                     let mut buf = self.buffer.lock().unwrap();
                     buf.clear();
-                    let mut tasks: Vec<Task<Message>> = Vec::new();
-                    tasks.push(Task::<Message>::future(local_search(self.search_bar.clone(), self.get_db_ref())).map(|msg| msg));
-                    if self.use_online_search { tasks.push(Task::<Message>::future(cloud_search(self.search_bar.clone(), self.get_db_ref())).map(|msg| msg)) }
-                    let task = Task::<Message>::batch(tasks);
+                    let tasks: Vec<Task<Message>> = self.providers.enabled()
+                        .into_iter()
+                        .map(|provider| Task::<Message>::future(provider_search(provider, self.search_bar.clone(), self.get_db_ref())))
+                        .collect();
+                    self.active_search_threads = tasks.len();
                     self.search_bar.clear();
-                    if self.use_online_search { self.active_search_threads = 2; }
-                    else { self.active_search_threads = 1; }
-                    task
+                    Task::<Message>::batch(tasks)
                 }
             }
 
-            Message::SearchResults(songs) => {
+            Message::SearchResults(hits) => {
                 let mut buf = self.buffer.lock().unwrap();
-                songs.into_iter().for_each(|song| buf.push(song));
+                merge_hits(&mut buf, hits);
                 if self.active_search_threads > 0 { self.active_search_threads -= 1; }
                 Task::<Message>::none()
             }
 
+            Message::SearchFailed(e) => {
+                if self.active_search_threads > 0 { self.active_search_threads -= 1; }
+                Task::done(Message::Error(e))
+            }
+
             Message::SearchBarInput(s) => {
                 self.search_bar = s;
                 Task::none()
@@ -206,82 +560,140 @@ impl Application {
                 let mut buf = self.buffer.lock().unwrap();
                 buf.clear();
                 let database = self.database.lock().unwrap();
-                database.retrieve_all_songs().into_iter().for_each(|song| buf.push(song));
-                Task::none()
+                match database.retrieve_all_songs() {
+                    Ok(songs) => { buf.extend(songs.into_iter().map(|song| SearchHit { song, sources: vec![SearchSource::Library] })); Task::none() }
+                    Err(e) => Task::done(Message::Error(e))
+                }
             }
 
-            Message::ToggleYTSearch(b) => {
-                self.use_online_search = b;
+            Message::ToggleProvider(source, enabled) => {
+                self.providers.set_enabled(source, enabled);
                 Task::none()
             }
 
-            Message::Download(s, d, mut p) => {
+            // Files found in a music folder are imported into the library rather than downloaded
+            Message::Download(s, p) if s.sql_id == 0 && s.file.is_some() => {
+                let database = self.get_db_ref();
+                Task::perform(async move {
+                    let result = tokio::task::spawn_blocking(move || match s.file.as_ref() {
+                        Some(file) => import_file(&database, file).map(|outcome| (s.id.clone(), outcome.into_song())),
+                        None => Err(ResonateError::Io(String::from("No file selected.")))
+                    }).await.unwrap_or_else(|e| Err(ResonateError::Io(e.to_string())));
+                    (result, p)
+                }, |(result, p)| match result {
+                    Ok((hit_id, song)) => Message::FileImported(hit_id, song, p),
+                    Err(e) => Message::Error(e)
+                })
+            }
+
+            Message::FileImported(hit_id, song, mut p) => {
+                self.update_missing_songs();
+
+                {
+                    let mut buf = self.buffer.lock().unwrap();
+                    buf.iter_mut().filter(|hit| hit.song.id == hit_id).for_each(|hit| hit.song = song.clone());
+                }
+
+                let database = self.database.lock().unwrap();
+                let playlist = match self.target_playlist.as_mut() {
+                    Some(playlist) if playlist.id == p.id => playlist,
+                    _ => &mut p
+                };
+                match database.add_song_to_playlist(&song, playlist) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::Download(s, mut p) => {
 
                 let update_playlist = match self.target_playlist.as_ref() {
                     Some(playlist) => playlist.id == p.id,
                     None => false
                 };
     
-                if self.currently_download_songs.contains(&s) || s.file.is_some() || self.download_queue.contains(&s) {
+                if self.downloads.is_pending(&s) || s.file.is_some() {
                     if s.file.is_some() {
                         let database = self.database.lock().unwrap();
-                        match update_playlist {
-                            true => database.add_song_to_playlist(&s, self.target_playlist.as_mut().unwrap()),
-                            false => database.add_song_to_playlist(&s, &mut p)
+                        let result = match (update_playlist, self.target_playlist.as_mut()) {
+                            (true, Some(playlist)) => database.add_song_to_playlist(&s, playlist),
+                            _ => database.add_song_to_playlist(&s, &mut p)
                         };
+                        if let Err(e) = result { return Task::done(Message::Error(e)) }
                     }
                     return Task::none()
                 }
 
-                if self.currently_download_songs.len() >= 4 {
-                    if !self.download_queue.contains(&s) { self.download_queue.push(s); }
-                    Task::none()
-                } else {
-                    self.currently_download_songs.insert(s.clone());
-                    println!("[DOWNLOADER] Downloading {}", s.name);
-                    Task::future(download(d, s)).map(|msg| msg)
-                }
+                self.downloads.enqueue(s)
             }
 
             // When a song is successfully downloaded, update the database and redraw
             Message::SuccessfulDownload(song) => {
                 println!("[RUNTIME] Received successful download of {}", song.name);
-                let mut songs_to_remove: Vec<Song> = Vec::new();
-                self.currently_download_songs.iter().for_each(|cs| if cs.sql_id == song.sql_id { songs_to_remove.push(cs.clone()); });
-                for song in songs_to_remove {
-                    println!("Dequeued {}", song.name);
-                    self.currently_download_songs.remove(&song);
-                }
+                let next = self.downloads.finish(&song);
 
                 // Update song view
-                let mut buf = self.buffer.lock().unwrap();
-                for s in buf.iter_mut() {
-                    if s.id == song.id {
-                        println!("[RUNTIME] Updated current view of {}", song.name);
-                        s.file = song.file.clone();
+                {
+                    let mut buf = self.buffer.lock().unwrap();
+                    for hit in buf.iter_mut() {
+                        if hit.song.id == song.id {
+                            println!("[RUNTIME] Updated current view of {}", song.name);
+                            hit.song.file = song.file.clone();
+                        }
                     }
                 }
+
+                self.library.iter_mut().filter(|entry| entry.song.sql_id == song.sql_id).for_each(|entry| entry.song.file = song.file.clone());
+
+                // Imported playlists list their songs before they are downloaded
+                if let Some(songs) = self.target_playlist.as_mut().and_then(|playlist| playlist.songs.as_mut()) {
+                    songs.iter_mut().filter(|s| s.sql_id == song.sql_id).for_each(|s| s.file = song.file.clone());
+                }
+
+                match self.record_download(song) {
+                    Ok(()) => Task::batch(vec![next, Task::done(Message::ScanLoudness)]),
+                    Err(e) => Task::batch(vec![next, Task::done(Message::Error(e))])
+                }
+            }
+
+            // The reason is shown in the downloads panel rather than as an error banner
+            Message::DownloadFailed(song, e) => {
+                println!("[RUNTIME] Download of {} failed: {e}", song.name);
+                self.downloads.fail(&song, e)
+            }
+
+            Message::CancelDownload(song) => {
+                self.downloads.cancel(&song);
+                Task::none()
+            }
+
+            Message::RetryDownload(song) | Message::QueueDownload(song) => self.downloads.enqueue(song),
+
+            Message::ClearDownloads => {
+                self.downloads.clear_finished();
+                Task::none()
+            }
+
+            Message::SetDownloadLimit(limit) => {
+                let task = self.downloads.set_limit(limit);
                 let database = self.database.lock().unwrap();
-                println!("[RUNTIME] About to add {}. Is_some: {}.", song.name, self.target_playlist.is_some());
-                database.add_song_to_playlist(&song, &mut self.target_playlist.as_mut().unwrap());
-                database.update(song);
-                let directory = database.get_directory();
-                
-                if self.download_queue.is_empty() { Task::none() } else {
-                    let song = self.download_queue.remove(0);
-                    self.currently_download_songs.insert(song.clone());
-                    Task::future(download(directory, song))
+                match database.set_setting(DOWNLOAD_LIMIT_SETTING, &self.downloads.get_limit().to_string()) {
+                    Ok(()) => task,
+                    Err(e) => Task::batch(vec![task, Task::done(Message::Error(e))])
                 }
             }
 
             Message::SearchPlaylists => {
                 println!("[RUNTIME] Searching {}", self.search_bar);
                 let database = self.database.lock().unwrap();
-                self.playlist_buffer = 
-                    if self.search_bar.len() > 0 { database.search_playlist_by_name(self.search_bar.clone()) } 
+                let playlists =
+                    if !self.search_bar.is_empty() { database.search_playlist_by_name(self.search_bar.clone()) }
                     else { database.dump_all_playlists() };
                 self.search_bar.clear();
-                Task::none()
+                match playlists {
+                    Ok(playlists) => { self.playlist_buffer = playlists; Task::none() }
+                    Err(e) => Task::done(Message::Error(e))
+                }
             }
 
             Message::NewPlaylist => {
@@ -293,18 +705,36 @@ impl Application {
 
             Message::CreateNewPlaylist => {
                 let database = self.database.lock().unwrap();
-                database.create_playlist(self.search_bar.clone());
+                let result = database.create_playlist(self.search_bar.clone());
                 self.search_bar.clear();
                 self.state = State::SearchPlaylists;
-                Task::none()
+                match result {
+                    Ok(_) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::CreateSmartPlaylist => {
+                let database = self.database.lock().unwrap();
+                let result = database.create_playlist(self.search_bar.clone())
+                    .and_then(|mut playlist| database.save_smart_playlist(&mut playlist, SmartPlaylist::default()).map(|_| playlist));
+                self.search_bar.clear();
+                self.state = State::SearchPlaylists;
+                // Straight to the rule editor, since a smart playlist without rules is the whole library
+                match result {
+                    Ok(playlist) => Task::done(Message::OpenPlaylist(playlist)).chain(Task::done(Message::EditPlaylist)),
+                    Err(e) => Task::done(Message::Error(e))
+                }
             }
 
             Message::Homepage => {
                 self.search_bar.clear();
                 self.state = State::SearchPlaylists;
                 let database = self.database.lock().unwrap();
-                self.playlist_buffer = database.dump_all_playlists();
-                Task::none()
+                match database.dump_all_playlists() {
+                    Ok(playlists) => { self.playlist_buffer = playlists; Task::none() }
+                    Err(e) => Task::done(Message::Error(e))
+                }
             }
 
             Message::OpenPlaylist(p) => {
@@ -312,12 +742,165 @@ impl Application {
                 buf.clear();
                 let database = self.database.lock().unwrap();
                 let mut playlist = p.clone();
-                database.load_playlist(&mut playlist);
+                if let Err(e) = database.load_playlist(&mut playlist) {
+                    return Task::done(Message::Error(e))
+                }
                 self.target_playlist = Some(playlist);
                 self.state = State::Playlist;
                 Task::none()
             }
 
+            Message::EditPlaylist => {
+                self.search_bar = match &self.target_playlist {
+                    Some(playlist) => playlist.name.clone(),
+                    None => String::new()
+                };
+                self.smart_draft = self.target_playlist.as_ref().and_then(|playlist| playlist.smart.clone()).unwrap_or_default();
+                self.smart_limit = self.smart_draft.limit.map(|limit| limit.to_string()).unwrap_or_default();
+                self.state = State::EditPlaylist;
+                Task::none()
+            }
+
+            Message::SetRuleMatch(match_all) => {
+                self.smart_draft.match_all = match_all;
+                Task::none()
+            }
+
+            Message::RateSong(song, rating) => match self.record_preference(Song { rating, ..song }) {
+                Ok(()) => Task::none(),
+                Err(e) => Task::done(Message::Error(e))
+            },
+
+            Message::FavouriteSong(song, favourite) => match self.record_preference(Song { favourite, ..song }) {
+                Ok(()) => Task::none(),
+                Err(e) => Task::done(Message::Error(e))
+            },
+
+            Message::RateCurrent(rating) => match self.audio_player.get_current() {
+                Some(song) => Task::done(Message::RateSong(song, rating)),
+                None => Task::none()
+            },
+
+            Message::ToggleFavouriteCurrent => match self.audio_player.get_current() {
+                Some(song) => { let favourite = !song.favourite; Task::done(Message::FavouriteSong(song, favourite)) }
+                None => Task::none()
+            },
+
+            Message::AddRule => {
+                self.smart_draft.rules.push(Rule::new(RuleField::Title));
+                Task::none()
+            }
+
+            Message::RemoveRule(index) => {
+                if index < self.smart_draft.rules.len() { self.smart_draft.rules.remove(index); }
+                Task::none()
+            }
+
+            Message::SetRuleField(index, field) => {
+                if let Some(rule) = self.smart_draft.rules.get_mut(index) {
+                    let value = if field.has_value() { rule.value.clone() } else { String::new() };
+                    *rule = Rule { value, ..Rule::new(field) };
+                }
+                Task::none()
+            }
+
+            Message::SetRuleOperator(index, operator) => {
+                if let Some(rule) = self.smart_draft.rules.get_mut(index) { rule.operator = operator; }
+                Task::none()
+            }
+
+            Message::SetRuleValue(index, value) => {
+                if let Some(rule) = self.smart_draft.rules.get_mut(index) { rule.value = value; }
+                Task::none()
+            }
+
+            Message::SetSmartSort(sort) => {
+                self.smart_draft.sort = sort;
+                Task::none()
+            }
+
+            Message::SetSmartDescending(descending) => {
+                self.smart_draft.descending = descending;
+                Task::none()
+            }
+
+            Message::SetSmartLimit(limit) => {
+                self.smart_limit = limit;
+                Task::none()
+            }
+
+            Message::SaveRules => {
+                let limit = match self.smart_limit.trim() {
+                    "" => None,
+                    limit => match limit.parse::<usize>() {
+                        Ok(limit) => Some(limit),
+                        Err(_) => return Task::done(Message::Error(ResonateError::InvalidRule(format!("\"{limit}\" is not a valid limit"))))
+                    }
+                };
+                let smart = SmartPlaylist { limit, ..self.smart_draft.clone() };
+
+                let database = self.database.lock().unwrap();
+                match self.target_playlist.as_mut() {
+                    Some(playlist) => match database.save_smart_playlist(playlist, smart) {
+                        Ok(()) => { self.state = State::Playlist; Task::none() }
+                        Err(e) => Task::done(Message::Error(e))
+                    },
+                    None => Task::none()
+                }
+            }
+
+            Message::RenamePlaylist => {
+                let name = self.search_bar.clone();
+                self.search_bar.clear();
+                self.state = State::Playlist;
+                if name.is_empty() { return Task::none() }
+
+                let database = self.database.lock().unwrap();
+                match self.target_playlist.as_mut() {
+                    Some(playlist) => match database.rename_playlist(playlist, name) {
+                        Ok(()) => Task::none(),
+                        Err(e) => Task::done(Message::Error(e))
+                    },
+                    None => Task::none()
+                }
+            }
+
+            Message::DeletePlaylist => {
+                let result = match self.target_playlist.take() {
+                    Some(playlist) => {
+                        let database = self.database.lock().unwrap();
+                        database.delete_playlist(&playlist)
+                    }
+                    None => Ok(())
+                };
+                match result {
+                    Ok(()) => Task::done(Message::Homepage),
+                    Err(e) => Task::batch(vec![Task::done(Message::Homepage), Task::done(Message::Error(e))])
+                }
+            }
+
+            Message::RemoveSongFromPlaylist(song) => {
+                let database = self.database.lock().unwrap();
+                match self.target_playlist.as_mut() {
+                    Some(playlist) => match database.remove_song_from_playlist(&song, playlist) {
+                        Ok(()) => Task::none(),
+                        Err(e) => Task::done(Message::Error(e))
+                    },
+                    None => Task::none()
+                }
+            }
+
+            Message::MoveSongInPlaylist(from, to) => {
+                let database = self.database.lock().unwrap();
+                match self.target_playlist.as_mut() {
+                    Some(playlist) => match database.move_song_in_playlist(playlist, from, to) {
+                        Ok(()) => Task::none(),
+                        Err(e) => Task::done(Message::Error(e))
+                    },
+                    None => Task::none()
+                }
+            }
+
             Message::AddSongs => {
                 self.state = State::Search;
                 self.search_bar.clear();
@@ -352,10 +935,42 @@ impl Application {
                 Task::none()
             }
 
+            Message::Previous => {
+                match self.audio_player.previous_song() {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::PlayNext(s) => {
+                self.audio_player.play_next(s);
+                Task::none()
+            }
+
+            Message::RemoveFromQueue(index) => {
+                self.audio_player.remove_from_queue(index);
+                Task::none()
+            }
+
+            Message::MoveInQueue(from, to) => {
+                self.audio_player.move_in_queue(from, to);
+                Task::none()
+            }
+
+            Message::JumpToQueued(index) => {
+                self.audio_player.jump_to(index);
+                Task::none()
+            }
+
+            Message::ClearQueue => {
+                self.audio_player.clear_queue();
+                Task::none()
+            }
+
             Message::ShuffleCurrent => {
                 let mut rng = rng();
                 let mut playlist = match &self.target_playlist {
-                    Some(p) => p.songs.as_ref().unwrap().clone(),
+                    Some(p) => p.songs.clone().unwrap_or_default(),
                     None => Vec::<Song>::new()
                 };
                 playlist.shuffle(&mut rng);
@@ -363,23 +978,63 @@ impl Application {
                 Task::none()
             }
             
-            Message::ProgressUpdate(v) => {
-                self.progress = v;
-                Task::<Message>::future(get_progress(self.progress_source.clone()))
+            Message::ProgressUpdate => {
+                self.progress = *self.progress_source.lock().unwrap();
+                let mut tasks = Vec::new();
+                tasks.extend(self.audio_player.take_errors().into_iter().map(|e| Task::done(Message::Error(e))));
+                if let Some(watcher) = self.watcher.as_ref() {
+                    tasks.extend(watcher.take_errors().into_iter().map(|e| Task::done(Message::Error(e))));
+                    let status = watcher.get_status();
+                    if status != self.watch_status {
+                        self.watch_status = status;
+                        self.update_missing_songs();
+                    }
+                }
+                if let Some(e) = self.record_plays().err() {
+                    tasks.push(Task::done(Message::Error(e)));
+                }
+                if self.last_session_save.elapsed() >= SESSION_SAVE_INTERVAL {
+                    tasks.push(Task::done(Message::SaveSession));
+                }
+                Task::batch(tasks)
+            }
+
+            Message::SeekDrag(v) => {
+                self.seek_preview = Some(v);
+                Task::none()
             }
 
-            Message::Slow => {
-                self.audio_player.slow();
+            Message::SeekRelease => {
+                let target = match self.seek_preview.take() {
+                    Some(target) => target,
+                    None => return Task::none()
+                };
+                match self.audio_player.seek(Duration::from_secs_f32(target)) {
+                    Ok(()) => { self.progress = target; Task::none() }
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::SeekRelative(offset) => {
+                match self.audio_player.seek_relative(offset) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::SetSpeed(speed) => {
+                self.audio_player.set_speed(speed);
                 Task::none()
             }
 
-            Message::Normal => {
-                self.audio_player.normal();
+            Message::SetPitch(semitones) => {
+                self.audio_player.set_pitch(semitones);
                 Task::none()
             }
 
-            Message::Fast => {
-                self.audio_player.fast();
+            Message::ResetSpeed => {
+                self.audio_player.set_speed(1f32);
+                self.audio_player.set_pitch(0f32);
                 Task::none()
             }
 
@@ -419,7 +1074,7 @@ impl Application {
                     return Task::none();
                 }
 
-                let mut song: Song = Song::new(
+                let song: Song = Song::new(
                     0, self.selected_name.clone(),
                     self.selected_artist.clone(),
                     self.selected_album.clone(),
@@ -431,23 +1086,162 @@ impl Application {
                 self.selected_file = None;
                 self.selected_album.clear();
 
+                self.state = State::Playlist;
+                let database = self.get_db_ref();
+                Task::perform(async move {
+                    tokio::task::spawn_blocking(move || save_uploaded_song(&database, song)).await
+                        .map_err(|e| ResonateError::Io(e.to_string()))?
+                }, |result| match result {
+                    Ok(song) => Message::SongUploaded(song),
+                    Err(e) => Message::Error(e)
+                })
+            }
+
+            Message::SongUploaded(song) => {
+                match self.add_uploaded_song(song) {
+                    Ok(()) => Task::done(Message::ScanLoudness),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::SetVolume(v) => {
+                self.audio_player.set_volume(v);
+                Task::none()
+            }
+
+            Message::SetCrossfade(seconds) => {
+                if seconds == self.audio_player.get_crossfade() { return Task::none() }
+                self.audio_player.set_crossfade(seconds);
                 let database = self.database.lock().unwrap();
-                convert_and_save_song(database.get_directory(), &mut song);
+                match database.set_setting(CROSSFADE_SETTING, &seconds.to_string()) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::SetNormalization(normalization) => {
+                self.audio_player.set_normalization(normalization);
+                let database = self.database.lock().unwrap();
+                match database.set_setting(NORMALIZATION_SETTING, normalization.key()) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::ScanLoudness => {
+                if self.is_scanning_loudness {
+                    self.rescan_loudness = true;
+                    return Task::none()
+                }
+                self.is_scanning_loudness = true;
+                let database = self.get_db_ref();
+                Task::perform(async move {
+                    tokio::task::spawn_blocking(move || scan_loudness(database)).await
+                        .unwrap_or_else(|e| Err(ResonateError::Io(e.to_string())))
+                }, |result| match result {
+                    Ok(gains) => Message::LoudnessScanned(gains),
+                    Err(e) => Message::LoudnessScanFailed(e)
+                })
+            }
 
-                println!("Song path: {}", song.file.as_ref().unwrap().display());
+            Message::LoudnessScanned(gains) => {
+                println!("[LOUDNESS] Updated the gain of {} songs", gains.len());
+                gains.into_iter().for_each(|(sql_id, gain)| self.update_song(sql_id, |song| song.gain = Some(gain)));
+                self.finish_loudness_scan()
+            }
 
-                database.add_song_to_cache(&mut song);
-                database.add_song_to_playlist(&song, self.target_playlist.as_mut().unwrap());
+            Message::LoudnessScanFailed(e) => {
+                Task::batch(vec![self.finish_loudness_scan(), Task::done(Message::Error(e))])
+            }
 
-                self.state = State::Playlist;
+            Message::OpenEqualizer => {
+                self.state = State::Equalizer;
                 Task::none()
             }
 
-            Message::SetVolume(v) => {
-                self.audio_player.set_volume(v);
+            Message::SetEqualizerEnabled(enabled) => self.update_equalizer(|equalizer| equalizer.enabled = enabled),
+
+            Message::SetBandGain(band, gain) => self.update_equalizer(|equalizer| {
+                if let Some(current) = equalizer.gains.get_mut(band) { *current = gain.clamp(-MAX_BAND_GAIN, MAX_BAND_GAIN); }
+            }),
+
+            Message::ApplyPreset(name) => self.update_equalizer(|equalizer| {
+                if let Some(gains) = equalizer.preset(&name).map(|preset| preset.gains) { equalizer.gains = gains; }
+            }),
+
+            Message::PresetNameInput(name) => {
+                self.preset_name = name;
+                Task::none()
+            }
+
+            Message::SavePreset => {
+                let name = self.preset_name.trim().to_string();
+                let mut equalizer = self.audio_player.get_equalizer();
+                // Built-in presets cannot be overwritten
+                if name.is_empty() || equalizer.preset(&name).is_some_and(|preset| preset.built_in) { return Task::none() }
+
+                let preset = EqPreset::new(name, equalizer.gains);
+                if let Err(e) = self.database.lock().unwrap().save_eq_preset(&preset) { return Task::done(Message::Error(e)) }
+                equalizer.presets.retain(|existing| existing.name != preset.name);
+                equalizer.presets.push(preset);
+                self.audio_player.set_equalizer(equalizer);
+                self.preset_name.clear();
                 Task::none()
             }
 
+            Message::DeletePreset(name) => {
+                let database = self.database.lock().unwrap();
+                if let Err(e) = database.delete_eq_preset(&name) { return Task::done(Message::Error(e)) }
+                let mut equalizer = self.audio_player.get_equalizer();
+                equalizer.presets.retain(|preset| preset.built_in || preset.name != name);
+                self.audio_player.set_equalizer(equalizer);
+
+                // Overrides that used the preset were deleted along with it
+                if let Some(song) = self.audio_player.get_current().filter(|song| song.equalizer.as_deref() == Some(&name)) {
+                    self.audio_player.update_song(song.sql_id, |song| song.equalizer = None);
+                }
+                match self.target_playlist.as_mut() {
+                    Some(playlist) => match database.load_playlist(playlist) {
+                        Ok(()) => Task::none(),
+                        Err(e) => Task::done(Message::Error(e))
+                    },
+                    None => Task::none()
+                }
+            }
+
+            Message::SetSongPreset(song, choice) => {
+                // Songs that were never cached have nowhere to keep an override
+                if song.sql_id == 0 { return Task::none() }
+                let preset = choice.into_override();
+                if let Err(e) = self.database.lock().unwrap().set_song_eq_preset(song.sql_id, preset.as_deref()) {
+                    return Task::done(Message::Error(e))
+                }
+                self.update_song(song.sql_id, |song| song.equalizer.clone_from(&preset));
+
+                // Without an override of its own the song falls back to its playlist's
+                let database = self.database.lock().unwrap();
+                match self.target_playlist.as_mut() {
+                    Some(playlist) => match database.load_playlist(playlist) {
+                        Ok(()) => Task::none(),
+                        Err(e) => Task::done(Message::Error(e))
+                    },
+                    None => Task::none()
+                }
+            }
+
+            Message::SetPlaylistPreset(choice) => {
+                let playlist = match self.target_playlist.as_mut() {
+                    Some(playlist) if playlist.id != LIKED_PLAYLIST_ID => playlist,
+                    _ => return Task::none()
+                };
+                let preset = choice.into_override();
+                let database = self.database.lock().unwrap();
+                match database.set_playlist_eq_preset(playlist.id, preset.as_deref()).and_then(|()| database.load_playlist(playlist)) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
             Message::PauseClicked => {
                 println!("PAUSE CLICKED");
                 if self.audio_player.is_paused() {
@@ -458,25 +1252,30 @@ impl Application {
                 Task::none()
             }
 
-            Message::DebugPrint(s) => {
-                println!("[DEBUG] {s}");
+            Message::SetRepeat(mode) => {
+                self.audio_player.set_repeat(mode);
                 Task::none()
             }
 
-            Message::SetLooping(b) => {
-                self.audio_player.set_looping(b);
+            Message::SetShuffle(b) => {
+                self.audio_player.set_shuffle(b);
                 Task::none()
             }
 
             Message::CheckAllIcons => {
                 println!("Copping icons.");
                 let database = self.database.lock().unwrap();
-                Task::batch(database.retrieve_all_songs().into_iter().filter_map(
+                let songs = match database.retrieve_all_songs() {
+                    Ok(songs) => songs,
+                    Err(e) => return Task::done(Message::Error(e))
+                };
+                Task::batch(songs.into_iter().filter_map(
                     |song|
-                    match song.file.as_ref() {
-                        Some(f) => {
-                            let thumbnail_path = f.parent().unwrap().join(format!("{}.png", song.id));
-                            if thumbnail_path.exists() || song.id == song.name {
+                    match song.file.as_ref().and_then(|f| f.parent()) {
+                        Some(directory) => {
+                            let thumbnail_path = directory.join(format!("{}.png", song.id));
+                            // Local files have no YouTube thumbnail; older uploads used their name as an id
+                            if thumbnail_path.exists() || is_local_id(&song.id) || song.id == song.name {
                                 None
                             } else {
                                 Some(Task::done(Message::DownloadThumbnail(song)))
@@ -495,31 +1294,311 @@ impl Application {
             Message::ThumbnailDownloaded => {
                 Task::none()
             }
-        };
 
-        match self.is_progress_running {
-            true => task,
-            false => {
-                self.is_progress_running = true;
-                Task::batch( vec![ task, Task::<Message>::future(get_progress(self.progress_source.clone())) ])
+            // Imports the YouTube Music playlist or album pasted into the search bar
+            Message::ImportLink => {
+                if self.is_importing_link || !is_url(&self.search_bar) { return Task::none() }
+                self.is_importing_link = true;
+                Task::perform(import_remote_playlist(self.search_bar.clone(), self.get_db_ref()), |result| match result {
+                    Ok(playlist) => Message::LinkImported(playlist),
+                    Err(e) => Message::LinkImportFailed(e)
+                })
+            }
+
+            Message::LinkImported(playlist) => {
+                self.is_importing_link = false;
+                self.search_bar.clear();
+                let downloads: Vec<Task<Message>> = playlist.songs.iter()
+                    .flatten()
+                    .filter(|song| song.file.is_none())
+                    .cloned()
+                    .collect::<Vec<Song>>()
+                    .into_iter()
+                    .map(|song| self.downloads.enqueue(song))
+                    .collect();
+                Task::batch(downloads).chain(Task::done(Message::OpenPlaylist(playlist)))
+            }
+
+            Message::LinkImportFailed(e) => {
+                self.is_importing_link = false;
+                Task::done(Message::Error(e))
+            }
+
+            Message::OpenLibrary => {
+                let database = self.database.lock().unwrap();
+                self.library = match database.retrieve_library() {
+                    Ok(library) => library,
+                    Err(e) => return Task::done(Message::Error(e))
+                };
+                // Downloads started here should not end up in whichever playlist was last open
+                self.target_playlist = None;
+                self.state = State::Library;
+                Task::none()
+            }
+
+            Message::OpenStatistics => {
+                let database = self.database.lock().unwrap();
+                self.statistics = match database.retrieve_statistics() {
+                    Ok(statistics) => statistics,
+                    Err(e) => return Task::done(Message::Error(e))
+                };
+                self.state = State::Statistics;
+                Task::none()
+            }
+
+            Message::LibraryFilterInput(filter) => {
+                self.library_view.filter = filter;
+                Task::none()
+            }
+
+            Message::SortLibrary(column) => {
+                self.library_view.sort_by(column);
+                Task::none()
+            }
+
+            Message::SetDownloadFilter(filter) => {
+                self.library_view.downloaded = filter;
+                Task::none()
+            }
+
+            Message::BrowseArtist(artist) => {
+                self.library_view.browse_artist(artist);
+                Task::none()
+            }
+
+            Message::BrowseAlbum(album) => {
+                self.library_view.album = album;
+                Task::none()
+            }
+
+            Message::RetagLibrary => {
+                if self.is_retagging { return Task::none() }
+                let database = self.database.lock().unwrap();
+                let songs = match database.retrieve_all_songs() {
+                    Ok(songs) => songs,
+                    Err(e) => return Task::done(Message::Error(e))
+                };
+                let directory = database.get_directory();
+                self.is_retagging = true;
+                Task::perform(async move {
+                    tokio::task::spawn_blocking(move || retag_library(&directory, songs)).await
+                        .unwrap_or_else(|e| (0, vec![ResonateError::Tag(e.to_string())]))
+                }, |(tagged, errors)| Message::LibraryRetagged(tagged, errors))
             }
-        }
 
+            Message::ImportFolder => {
+                if self.import_progress.as_ref().is_some_and(|progress| !progress.lock().unwrap().finished) {
+                    return Task::none()
+                }
+                Task::<Message>::perform(async {
+                    FileDialog::new().pick_folder()
+                }, Message::ImportFolderSelected)
+            }
+
+            Message::ImportFolderSelected(folder) => {
+                let folder = match folder {
+                    Some(folder) => folder,
+                    None => return Task::none()
+                };
+                let progress = sync(ImportProgress::default());
+                self.import_progress = Some(progress.clone());
+                let database = self.get_db_ref();
+                Task::perform(async move {
+                    tokio::task::spawn_blocking(move || import_folder(database, folder, progress)).await
+                        .unwrap_or_else(|e| ImportProgress {
+                            failed: vec![(PathBuf::new(), ResonateError::Io(e.to_string()))],
+                            finished: true,
+                            ..Default::default()
+                        })
+                }, Message::ImportFinished)
+            }
+
+            Message::ImportFinished(summary) => {
+                println!("[IMPORT] Imported {}, skipped {}, failed {}", summary.imported, summary.skipped, summary.failed.len());
+                if let Some(progress) = self.import_progress.as_ref() {
+                    *progress.lock().unwrap() = summary;
+                }
+                // Imported files may have been relinked to songs that were missing
+                self.update_missing_songs();
+                Task::done(Message::ScanLoudness)
+            }
+
+            Message::DismissImport => {
+                self.import_progress = None;
+                Task::none()
+            }
+
+            Message::ExportPlaylist => {
+                let name = match self.target_playlist.as_ref() {
+                    Some(playlist) => playlist.name.clone(),
+                    None => return Task::none()
+                };
+                Task::<Message>::perform(async move {
+                    FileDialog::new()
+                        .add_filter("M3U8", &["m3u8"])
+                        .add_filter("PLS", &["pls"])
+                        .add_filter("XSPF", &["xspf"])
+                        .set_file_name(format!("{name}.m3u8"))
+                        .save_file()
+                }, Message::ExportPathSelected)
+            }
+
+            Message::ExportPathSelected(path) => {
+                let (mut path, playlist) = match (path, self.target_playlist.as_ref()) {
+                    (Some(path), Some(playlist)) => (path, playlist),
+                    _ => return Task::none()
+                };
+                // Some platforms' dialogs drop the extension, which picks the format
+                if PlaylistFormat::from_path(&path).is_none() { path.set_extension("m3u8"); }
+                match export_playlist(playlist, &path, self.relative_export) {
+                    Ok(()) => { println!("[PLAYLIST] Exported {} to {}", playlist.name, path.display()); Task::none() }
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::SetRelativeExport(relative) => {
+                self.relative_export = relative;
+                Task::none()
+            }
+
+            Message::ImportPlaylistFile => Task::<Message>::perform(async {
+                FileDialog::new()
+                    .add_filter("Playlists", &["m3u", "m3u8", "pls", "xspf"])
+                    .pick_file()
+            }, Message::PlaylistFileSelected),
+
+            Message::PlaylistFileSelected(path) => {
+                let path = match path {
+                    Some(path) => path,
+                    None => return Task::none()
+                };
+                let database = self.get_db_ref();
+                Task::perform(async move {
+                    tokio::task::spawn_blocking(move || import_playlist(&database, &path)).await
+                        .map_err(|e| ResonateError::Io(e.to_string()))?
+                }, |result| match result {
+                    Ok((playlist, unmatched)) => Message::PlaylistImported(playlist, unmatched),
+                    Err(e) => Message::Error(e)
+                })
+            }
+
+            Message::PlaylistImported(playlist, unmatched) => {
+                // Files the playlist pointed to may have been relinked to songs that were missing
+                self.update_missing_songs();
+                if !unmatched.is_empty() { self.unmatched_entries = Some((playlist.id, unmatched)); }
+                Task::done(Message::OpenPlaylist(playlist))
+            }
+
+            // Searches for an entry, so that adding a result puts it in the imported playlist
+            Message::SearchUnmatched(entry) => {
+                self.state = State::Search;
+                self.search_bar = entry.query();
+                Task::done(Message::Search)
+            }
+
+            Message::DismissUnmatched => {
+                self.unmatched_entries = None;
+                Task::none()
+            }
+
+            Message::WatchFolder => Task::<Message>::perform(async {
+                FileDialog::new().pick_folder()
+            }, Message::WatchFolderSelected),
+
+            Message::WatchFolderSelected(folder) => {
+                let (folder, watcher) = match (folder, self.watcher.as_mut()) {
+                    (Some(folder), Some(watcher)) => (folder, watcher),
+                    _ => return Task::none()
+                };
+                match watcher.watch(folder).and_then(|()| self.save_watch_folders()) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::UnwatchFolder(folder) => {
+                let watcher = match self.watcher.as_mut() {
+                    Some(watcher) => watcher,
+                    None => return Task::none()
+                };
+                match watcher.unwatch(&folder).and_then(|()| self.save_watch_folders()) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::LibraryRetagged(tagged, errors) => {
+                self.is_retagging = false;
+                println!("[TAGGER] Tagged {tagged} files, {} failed", errors.len());
+                match errors.first() {
+                    Some(e) => Task::done(Message::Error(ResonateError::Tag(format!("{} of {} files could not be tagged, e.g. {e}", errors.len(), tagged + errors.len())))),
+                    None => Task::none()
+                }
+            }
+
+            Message::Error(e) => {
+                println!("[ERROR] {e}");
+                self.errors.push(e);
+                Task::none()
+            }
+
+            Message::DismissError(index) => {
+                if index < self.errors.len() { self.errors.remove(index); }
+                Task::none()
+            }
+
+            Message::RestoreSession => {
+                let session = {
+                    let database = self.database.lock().unwrap();
+                    database.load_session()
+                };
+                let result = match session {
+                    Ok(Some(session)) => self.audio_player.restore(session),
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e)
+                };
+                match result {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::SaveSession => {
+                self.last_session_save = Instant::now();
+                let database = self.database.lock().unwrap();
+                match database.save_session(&self.audio_player.session()) {
+                    Ok(()) => Task::none(),
+                    Err(e) => Task::done(Message::Error(e))
+                }
+            }
+
+            Message::CloseRequested(_) => {
+                let database = self.database.lock().unwrap();
+                if let Err(e) = database.save_session(&self.audio_player.session()) {
+                    println!("[SESSION] Failed to save session: {e}");
+                }
+                iced::exit()
+            }
+        }
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
 
         let widgets = match self.state {
             State::SearchPlaylists => {
-                let widgets = Column::new()
+                let mut widgets = Column::new()
                     .spacing(10)
-                    .push(playlist_search_bar(String::from("Search..."), &self.search_bar));
+                    .push(playlist_search_bar(String::from("Search, or paste a YouTube Music playlist or album link..."), &self.search_bar, self.is_retagging, self.is_importing_link));
+
+                if let Some(watcher) = self.watcher.as_ref() {
+                    widgets = widgets.push(watch_folders_widget(watcher.get_folders(), self.watch_status.clone(), self.missing_songs));
+                }
 
                 let mut playlist_list = Column::new().spacing(10);
 
-                let p = 
-                    if self.playlist_buffer.len() > 0 { self.playlist_buffer.clone() }
-                    else { let db = self.database.lock().unwrap(); db.dump_all_playlists() };
+                let p =
+                    if !self.playlist_buffer.is_empty() { self.playlist_buffer.clone() }
+                    else { let db = self.database.lock().unwrap(); db.dump_all_playlists().unwrap_or_default() };
 
                 for playlist in p.into_iter() {
                     playlist_list = playlist_list.push(playlist_widget(playlist.clone()))
@@ -530,16 +1609,11 @@ impl Application {
 
             State::Search => {
                 let buf = self.buffer.lock().unwrap();
-                let dir = {
-                    let db = self.database.lock().unwrap();
-                    db.get_directory()
-                };
                 let songs: Vec<Element<Message>> = buf
                     .iter()
-                    .map(|song| {
-                        let is_downloading = self.currently_download_songs.contains(&song);
-                        let is_queued = !is_downloading && self.download_queue.contains(&song);
-                        download_song_widget(song.clone(), dir.clone(), is_downloading, is_queued, self.target_playlist.as_ref().unwrap().clone())
+                    .map(|hit| {
+                        let status = self.downloads.status_of(&hit.song);
+                        download_song_widget(hit.song.clone(), &hit.sources, status, self.target_playlist.as_ref().unwrap().clone())
                     })
                     .collect();
 
@@ -555,7 +1629,7 @@ impl Application {
                     .push(text(name).size(50).color(ResonateColour::text_emphasis()))
                     .push(button("Back to Playlist")
                         .on_press(Message::OpenPlaylist(playlist)))
-                    .push(search_bar("Search...".to_string(), &self.search_bar, self.use_online_search));
+                    .push(search_bar("Search...".to_string(), &self.search_bar, self.providers.sources()));
 
                 let mut song_columns: Column<Message> = Column::new().spacing(10);
                 for song in songs { song_columns = song_columns.push(song); }
@@ -567,7 +1641,40 @@ impl Application {
 
             State::MakePlaylist => {
                 Column::new()
-                    .push(playlist_name_widget(String::from("Enter Playlist Name"), &self.search_bar))
+                    .spacing(10)
+                    .push(playlist_name_widget(String::from("Enter Playlist Name"), &self.search_bar, "Create", Message::CreateNewPlaylist))
+                    .push(button("Create Smart Playlist").on_press(Message::CreateSmartPlaylist))
+            }
+
+            State::EditPlaylist => {
+                let playlist = self.target_playlist.clone();
+                let is_smart = playlist.as_ref().is_some_and(|playlist| playlist.smart.is_some());
+                let widgets = Column::new()
+                    .spacing(10)
+                    .push(playlist_name_widget(String::from("Enter Playlist Name"), &self.search_bar, "Rename", Message::RenamePlaylist))
+                    .push(Row::new().spacing(20)
+                        .push(button("Back to Playlist")
+                            .on_press_maybe(playlist.map(Message::OpenPlaylist)))
+                        .push(button("Delete Playlist")
+                            .style(|_theme: &Theme, style| button::Style {
+                                background: match style {
+                                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::red()))),
+                                    _ => Some(Background::Color(ResonateColour::red()))
+                                },
+                                border: Border::default().rounded(10),
+                                shadow: Shadow::default(),
+                                text_color: ResonateColour::text_emphasis(),
+                            })
+                            .on_press(Message::DeletePlaylist)))
+                    .push(Row::new().spacing(20).align_y(Vertical::Center)
+                        .push(button("Export...").on_press(Message::ExportPlaylist))
+                        .push(toggler(self.relative_export)
+                            .label("Relative paths")
+                            .on_toggle(Message::SetRelativeExport)));
+                match is_smart {
+                    true => widgets.push(Scrollable::new(smart_playlist_editor(&self.smart_draft, &self.smart_limit))),
+                    false => widgets
+                }
             }
 
             State::Playlist => {
@@ -576,11 +1683,14 @@ impl Application {
                     None => String::from("404 - Braincell not found.")
                 };
 
+                let is_smart = self.target_playlist.as_ref().is_some_and(|playlist| playlist.smart.is_some());
+                let is_liked = self.target_playlist.as_ref().is_some_and(|playlist| playlist.id == LIKED_PLAYLIST_ID);
+
                 let widgets = Column::new()
                     .spacing(10)
                     .width(Length::Fill)
                     .push(text(name).size(50).color(ResonateColour::text_emphasis()))
-                    .push(Row::new().spacing(20).push(
+                    .push(Row::new().spacing(20).push_maybe((!is_smart).then(||
                         button("Add Songs")
                         .style(|_theme: &Theme, style| button::Style {
                             background: match style {
@@ -592,7 +1702,7 @@ impl Application {
                             text_color: ResonateColour::text(),
                         })
                         .on_press(Message::AddSongs)
-                    )
+                    ))
                     .push(
                         button("Home")
                         .style(|_theme: &Theme, style| button::Style {
@@ -616,17 +1726,37 @@ impl Application {
                             shadow: Shadow::default(),
                             text_color: ResonateColour::text_emphasis(),
                         })
-                        .on_press(Message::ShuffleCurrent)));
+                        .on_press(Message::ShuffleCurrent))
+                    .push_maybe((!is_liked).then(||
+                        button("Edit")
+                        .style(|_theme: &Theme, style| button::Style {
+                            background: match style {
+                                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
+                                _ => Some(Background::Color(ResonateColour::blue()))
+                            },
+                            border: Border::default().rounded(10),
+                            shadow: Shadow::default(),
+                            text_color: ResonateColour::text(),
+                        })
+                        .on_press(Message::EditPlaylist)))
+                    .push_maybe((!is_liked).then(|| preset_picker(
+                        &self.audio_player.get_equalizer(),
+                        self.target_playlist.as_ref().and_then(|playlist| playlist.equalizer.clone()),
+                        Message::SetPlaylistPreset
+                    ))));
 
                 let path = {
                     let database = self.database.lock().unwrap();
                     database.get_directory()
                 };
 
-                let songs: Vec<Element<Message>> = self.target_playlist.as_ref().unwrap().songs.as_ref().unwrap()
+                let playlist_songs = self.target_playlist.as_ref().unwrap().songs.as_ref().unwrap();
+                let count = playlist_songs.len();
+                let songs: Vec<Element<Message>> = playlist_songs
                     .iter()
-                    .map(|song| {
-                        display_song_widget(song.clone(), self.audio_player.is_this_playing(&song), self.audio_player.is_paused(), path.join(format!("{}.png", song.id)))
+                    .enumerate()
+                    .map(|(index, song)| {
+                        display_song_widget(song.clone(), index, count, self.audio_player.is_this_playing(song), self.audio_player.is_paused(), is_smart, path.join(format!("{}.png", song.id)))
                     })
                     .collect();
 
@@ -634,9 +1764,33 @@ impl Application {
                 for song in songs { song_columns = song_columns.push(song); }
 
                 let scrollable_song_list: Scrollable<Message> = Scrollable::new(song_columns);
+                let widgets = match self.unmatched_entries.as_ref() {
+                    Some((id, entries)) if *id == self.target_playlist.as_ref().unwrap().id => widgets.push(unmatched_entries_widget(entries)),
+                    _ => widgets
+                };
                 widgets.push(scrollable_song_list)
             }
 
+            State::Library => {
+                let songs = self.library_view.songs(&self.library);
+                let count = songs.len();
+                let list = songs.into_iter().fold(Column::new().spacing(5), |column, entry| {
+                    let is_playing = self.audio_player.is_this_playing(&entry.song);
+                    let status = self.downloads.status_of(&entry.song);
+                    column.push(library_song_widget(entry, is_playing, self.audio_player.is_paused(), status))
+                });
+
+                Column::new()
+                    .spacing(10)
+                    .push(library_controls(&self.library_view, count))
+                    .push(library_browser(&self.library_view, self.library_view.artists(&self.library), self.library_view.albums(&self.library)))
+                    .push(Scrollable::new(list))
+            }
+
+            State::Statistics => statistics_widget(&self.statistics),
+
+            State::Equalizer => equalizer_widget(&self.audio_player.get_equalizer(), &self.preset_name, self.audio_player.get_current()),
+
             State::UploadFile => {
                 let name = match &self.target_playlist {
                     Some(playlist) => playlist.name.clone(),
@@ -673,19 +1827,44 @@ impl Application {
 
         println!("[VIEW] Volume: {}", self.audio_player.get_volume());
 
-        let display_split = Row::new()
-            .align_y(Vertical::Top)
+        let mut side_panel = Column::new()
             .spacing(10)
-            .push(widgets.width(Length::FillPortion(2)))
             .push(
                 queue_widget(self.audio_player.get_current(),
                     self.audio_player.get_queue(),
                     self.audio_player.is_paused(),
-                    self.progress,
+                    self.seek_preview.unwrap_or(self.progress),
                     self.audio_player.get_volume(),
-                    self.audio_player.is_looping()));
+                    self.audio_player.get_repeat(),
+                    self.audio_player.is_shuffling()))
+            .push(playback_widget(
+                self.audio_player.get_crossfade(),
+                self.audio_player.get_normalization(),
+                self.is_scanning_loudness,
+                self.audio_player.get_speed(),
+                self.audio_player.get_pitch()));
+
+        if let Some(progress) = self.import_progress.as_ref() {
+            side_panel = side_panel.push(import_widget(progress.lock().unwrap().clone()));
+        }
+
+        let downloads = self.downloads.entries();
+        if !downloads.is_empty() {
+            side_panel = side_panel.push(downloads_widget(downloads, self.downloads.get_limit()));
+        }
+
+        let display_split = Row::new()
+            .align_y(Vertical::Top)
+            .spacing(10)
+            .push(widgets.width(Length::FillPortion(2)))
+            .push(side_panel.width(Length::FillPortion(1)));
+
+        let mut page = Column::new().spacing(10);
+        for (index, error) in self.errors.iter().enumerate() {
+            page = page.push(error_widget(index, error));
+        }
 
-        Container::new(display_split)
+        Container::new(page.push(display_split))
             .padding(20)
             .style(|_theme| {
                 container::Style::default().background(
@@ -697,18 +1876,37 @@ impl Application {
             .into()
     }
 
+    pub fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch(vec![
+            Self::keyboard_subscription(),
+            window::close_requests().map(Message::CloseRequested),
+            iced::time::every(PROGRESS_UPDATE_INTERVAL).map(|_| Message::ProgressUpdate)
+        ])
+    }
+
     pub fn keyboard_subscription() -> Subscription<Message> {
         keyboard::on_key_press(
-            |key, _mod|
+            |key, modifiers|
             match key {
+                // Rating the current song needs ctrl/cmd so that it never fires while typing
+                keyboard::Key::Character(c) if modifiers.command() => match c.as_str() {
+                    "l" => Some(Message::ToggleFavouriteCurrent),
+                    digit => digit.parse::<u8>().ok().filter(|rating| *rating <= MAX_RATING).map(Message::RateCurrent)
+                },
                 keyboard::Key::Named(k) => {
+                    // Holding shift seeks by a few seconds, holding ctrl/cmd jumps further
+                    let seek_offset = if modifiers.command() { Some(30f32) } else if modifiers.shift() { Some(5f32) } else { None };
                     match k {
+                        keyboard::key::Named::ArrowRight if seek_offset.is_some() => seek_offset.map(Message::SeekRelative),
+                        keyboard::key::Named::ArrowLeft if seek_offset.is_some() => seek_offset.map(|offset| Message::SeekRelative(-offset)),
                         keyboard::key::Named::MediaPause => Some(Message::Pause),
                         keyboard::key::Named::MediaPlay => Some(Message::Resume),
                         keyboard::key::Named::MediaPlayPause => Some(Message::PauseClicked),
                         keyboard::key::Named::MediaTrackNext => Some(Message::Skip),
+                        keyboard::key::Named::MediaTrackPrevious => Some(Message::Previous),
                         keyboard::key::Named::Space => Some(Message::PauseClicked),
                         keyboard::key::Named::ArrowRight => Some(Message::Skip),
+                        keyboard::key::Named::ArrowLeft => Some(Message::Previous),
                         _ => None
                     }
                 }
@@ -1,23 +1,142 @@
+use std::f32::consts::FRAC_PI_2;
 use std::fs::File;
 use std::io::BufReader;
 use std::collections::VecDeque;
-use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::Path;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::thread::sleep;
 use std::thread::spawn;
 
+use rand::rng;
+use rand::seq::SliceRandom;
 use rodio::Sink;
 use rodio::Decoder;
 use rodio::OutputStream;
 use rodio::OutputStreamHandle;
 use rodio::Source;
+use rodio::source::{SeekError, UniformSourceIterator};
 
-use crate::application::Message;
+use crate::error::{ResonateError, ResonateResult};
+use crate::dsp::{DspChain, DspSource, Gain};
+use crate::equalizer::{Equalizer, EqualizerSettings, BANDS};
+use crate::loudness::Normalization;
+use crate::stretch::{TimeStretch, MAX_PITCH, MAX_SPEED, MIN_SPEED};
 use crate::utility::*;
 use crate::music::Song;
 
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    All
+}
+
+impl RepeatMode {
+    pub fn from_index(index: usize) -> Self {
+        match index {
+            1 => Self::One,
+            2 => Self::All,
+            _ => Self::Off
+        }
+    }
+
+    pub fn to_index(self) -> usize {
+        match self {
+            Self::Off => 0,
+            Self::One => 1,
+            Self::All => 2
+        }
+    }
+
+    /// The mode after this one when cycling through them with a single control.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::All,
+            Self::All => Self::One,
+            Self::One => Self::Off
+        }
+    }
+}
+
+/// Everything needed to pick up playback where it was left after a restart.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub current: Option<Song>,
+    pub position: f32,
+    pub queue: Vec<Song>,
+    pub volume: f32,
+    pub speed: f32,
+    // Semitones
+    pub pitch: f32,
+    pub repeat: RepeatMode,
+    pub shuffle: bool
+}
+
+/// A song that was listened to for long enough to count as a play.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Play {
+    pub song: Song,
+    // Unix time in seconds
+    pub started: u64,
+    // Seconds of the song that were actually heard, not counting anything seeked past
+    pub listened: f32,
+    // Whether something else was played before the song finished
+    pub skipped: bool
+}
+
+/// A listen has to reach this many seconds, or half the song if that is shorter, to count as a play.
+const PLAY_THRESHOLD: f32 = 240f32;
+
+/// The song being listened to and how much of it has been heard so far.
+struct Listen {
+    song: Song,
+    started: u64,
+    listened: f32,
+    last_position: f32
+}
+
+/// The format every song is converted to, so that one song can be mixed into the next.
+pub const MIX_CHANNELS: u16 = 2;
+pub const MIX_SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_SECOND: usize = MIX_CHANNELS as usize * MIX_SAMPLE_RATE as usize;
+
+/// The longest crossfade that can be set, in seconds.
+pub const MAX_CROSSFADE: f32 = 12f32;
+
+/// A decoded song in the mix format.
+type SongSource = Box<dyn Source<Item = f32> + Send>;
+
+/// Playback state shared between the `AudioPlayer` and its queueing thread.
+#[derive(Clone)]
+struct Shared {
+    sink: AM<Sink>,
+    queue: AMQ<Song>,
+    current: AMO<Song>,
+    history: AMQ<Song>,
+    // The songs that were lined up when playback was last started, along with every song played
+    // since, replayed by `RepeatMode::All`
+    cycle: AMV<Song>,
+    progress: AM<f32>,
+    repeat: AM<RepeatMode>,
+    shuffle: AM<bool>,
+    errors: AMQ<ResonateError>,
+    listen: AMO<Listen>,
+    plays: AMQ<Play>,
+    // The song after the current one, decoded ahead of time so that playback can go straight into it
+    next: AMO<(Song, SongSource)>,
+    // Songs that playback moved on to by itself, waiting for the queueing thread to catch up
+    transitions: AMQ<Song>,
+    // Seconds, 0 for a plain gapless transition
+    crossfade: AM<f32>,
+    normalization: AM<Normalization>,
+    equalizer: AM<EqualizerSettings>,
+    // Tempo as a multiple of normal, and pitch shift in semitones
+    speed: AM<f32>,
+    pitch: AM<f32>
+}
+
 pub struct AudioPlayer {
     
     // Required to keep audio in scope
@@ -25,221 +144,705 @@ pub struct AudioPlayer {
     _handle: OutputStreamHandle,
     _queue_handle: JoinHandle<()>,
 
-    sink: AM<Sink>,
-    queue: AMQ<Song>,
-    current: AMO<Song>,
+    shared: Shared
+}
+
+/// How many previously played songs are remembered for `previous_song`.
+const HISTORY_LIMIT: usize = 50;
+
+fn push_history(history: &mut VecDeque<Song>, song: Song) {
+    history.push_back(song);
+    if history.len() > HISTORY_LIMIT { history.pop_front(); }
+}
+
+/// Decodes a file into the mix format.
+pub fn decode_file(path: &Path) -> ResonateResult<SongSource> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(Box::new(UniformSourceIterator::<_, f32>::new(Decoder::new(file)?, MIX_CHANNELS, MIX_SAMPLE_RATE)))
+}
+
+/// What a song's `DspChain` is built from: the factor its gain scales it by and the equalizer's
+/// gains, if it is not bypassed.
+type ChainSettings = (f32, Option<[f32; BANDS]>);
+
+fn build_chain((factor, gains): ChainSettings) -> DspChain {
+    let mut chain = DspChain::default();
+    if factor != 1f32 { chain.push(Gain(factor)); }
+    if let Some(gains) = gains { chain.push(Equalizer::new(gains, MIX_CHANNELS, MIX_SAMPLE_RATE)); }
+    chain
+}
+
+/// Decodes a song and runs it through its DSP chain, which is rebuilt whenever the normalization,
+/// the equalizer or the song's preset override changes.
+fn decode_song(shared: &Shared, song: &Song) -> ResonateResult<SongSource> {
+    let path = match song.file.as_ref() {
+        Some(path) => path,
+        None => return Err(ResonateError::Io(format!("{} has not been downloaded.", song.name)))
+    };
+    let source = decode_file(path)?;
+
+    let (normalization, equalizer, current) = (shared.normalization.clone(), shared.equalizer.clone(), shared.current.clone());
+    let (sql_id, gain, mut preset) = (song.sql_id, song.gain, song.equalizer.clone());
+    let settings = move |preset: &Option<String>| -> ChainSettings {
+        (
+            gain.map_or(1f32, |gain| gain.factor(*normalization.lock().unwrap())),
+            equalizer.lock().unwrap().gains_for(preset.as_deref())
+        )
+    };
+
+    let mut built = settings(&preset);
+    Ok(Box::new(DspSource::new(source, build_chain(built), move || {
+        // The override can change while the song plays, which is only seen on the current song.
+        // Songs are started with the current song locked, so the lock is never waited on here.
+        if let Ok(current) = current.try_lock() {
+            if let Some(song) = current.as_ref().filter(|song| song.sql_id == sql_id) {
+                preset.clone_from(&song.equalizer);
+            }
+        }
+        let latest = settings(&preset);
+        (latest != built).then(|| { built = latest; build_chain(latest) })
+    })))
+}
+
+/// How often, in samples, a `Transition` reports its position and picks up crossfade changes.
+const PROGRESS_INTERVAL: u64 = SAMPLES_PER_SECOND as u64 / 20;
+
+/// Plays the current song and then whichever song the queueing thread preloaded, as one source so
+/// that the sink never runs dry in between. With a crossfade the current song is read ahead by the
+/// crossfade's length, so that once it has been read to the end its last few seconds can be faded
+/// out under the start of the next song.
+struct Transition {
+    current: SongSource,
+    // Samples of the current song that have been read but not played yet
+    ahead: VecDeque<f32>,
+    exhausted: bool,
+    tried_next: bool,
+    // The end of the previous song as it fades out, with how many samples the fade started with
+    outgoing: Option<(VecDeque<f32>, usize)>,
+    // Samples of the current song played so far, for reporting its position
+    played: u64,
+    lookahead: usize,
     progress: AM<f32>,
-    looping_song: AM<bool>
+    next: AMO<(Song, SongSource)>,
+    transitions: AMQ<Song>,
+    crossfade: AM<f32>
+}
+
+impl Transition {
+    fn new(source: SongSource, shared: &Shared) -> Self {
+        let mut transition = Self {
+            current: source,
+            ahead: VecDeque::new(),
+            exhausted: false,
+            tried_next: false,
+            outgoing: None,
+            played: 0,
+            lookahead: 0,
+            progress: shared.progress.clone(),
+            next: shared.next.clone(),
+            transitions: shared.transitions.clone(),
+            crossfade: shared.crossfade.clone()
+        };
+        transition.update_lookahead();
+        transition
+    }
+
+    fn update_lookahead(&mut self) {
+        let frames = (*self.crossfade.lock().unwrap() * MIX_SAMPLE_RATE as f32) as usize;
+        self.lookahead = frames * MIX_CHANNELS as usize;
+    }
+
+    fn read_ahead(&mut self) {
+        while !self.exhausted && self.ahead.len() <= self.lookahead {
+            match self.current.next() {
+                Some(sample) => self.ahead.push_back(sample),
+                None => self.exhausted = true
+            }
+        }
+    }
+
+    /// Moves on to the preloaded song, leaving whatever is left of the current one to fade out.
+    fn start_next(&mut self) -> bool {
+        let (song, source) = match self.next.lock().unwrap().take() {
+            Some(next) => next,
+            None => return false
+        };
+        let tail = std::mem::take(&mut self.ahead);
+        let length = tail.len();
+        self.outgoing = (length > 0).then_some((tail, length));
+        self.current = source;
+        self.exhausted = false;
+        self.tried_next = false;
+        self.played = 0;
+        self.transitions.lock().unwrap().push_back(song);
+        true
+    }
+}
+
+impl Iterator for Transition {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.played.is_multiple_of(PROGRESS_INTERVAL) {
+            *self.progress.lock().unwrap() = self.played as f32 / SAMPLES_PER_SECOND as f32;
+            self.update_lookahead();
+        }
+
+        self.read_ahead();
+        // Look for the next song once the current one has been read to the end, and again once it
+        // has finished playing in case it was preloaded late
+        if self.exhausted && (!self.tried_next || self.ahead.is_empty()) {
+            self.tried_next = true;
+            if self.start_next() { self.read_ahead(); }
+        }
+
+        let incoming = self.ahead.pop_front();
+        if incoming.is_some() { self.played += 1; }
+
+        let fading = self.outgoing.as_mut().and_then(|(tail, length)| {
+            tail.pop_front().map(|sample| (sample, 1f32 - tail.len() as f32 / *length as f32))
+        });
+        match fading {
+            // Equal-power curves keep the loudness steady through the fade
+            Some((outgoing, t)) => Some(outgoing * (t * FRAC_PI_2).cos() + incoming.unwrap_or_default() * (t * FRAC_PI_2).sin()),
+            None => { self.outgoing = None; incoming }
+        }
+    }
+}
+
+impl Source for Transition {
+    fn current_frame_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { MIX_CHANNELS }
+    fn sample_rate(&self) -> u32 { MIX_SAMPLE_RATE }
+    fn total_duration(&self) -> Option<Duration> { None }
+
+    /// Seeks within the current song, cutting short any fade that is still going.
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.current.try_seek(position)?;
+        self.ahead.clear();
+        self.exhausted = false;
+        self.tried_next = false;
+        self.outgoing = None;
+        self.played = (position.as_secs_f64() * MIX_SAMPLE_RATE as f64) as u64 * MIX_CHANNELS as u64;
+        Ok(())
+    }
+}
+
+/// Adds whatever has been played since the last call to the current listen. Jumps of more than a
+/// few seconds are seeks and are not counted.
+fn track_listen(shared: &Shared) {
+    let position = *shared.progress.lock().unwrap();
+    if let Some(listen) = shared.listen.lock().unwrap().as_mut() {
+        let played = position - listen.last_position;
+        if played > 0f32 && played < 5f32 { listen.listened += played; }
+        listen.last_position = position;
+    }
+}
+
+/// Ends the current listen, recording it as a play if enough of the song was heard.
+fn finish_listen(shared: &Shared, skipped: bool) {
+    track_listen(shared);
+    let listen = match shared.listen.lock().unwrap().take() {
+        Some(listen) => listen,
+        None => return
+    };
+
+    let threshold = PLAY_THRESHOLD.min(listen.song.duration as f32 / 2f32);
+    if listen.listened >= threshold {
+        println!("[AUDIO] Counted a play of {} ({:.0}s heard)", listen.song.name, listen.listened);
+        shared.plays.lock().unwrap().push_back(Play { song: listen.song, started: listen.started, listened: listen.listened, skipped });
+    }
+}
+
+/// Starts counting a listen of `song` and adds it to the repeat cycle.
+fn begin_listen(shared: &Shared, song: &Song) {
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+    *shared.listen.lock().unwrap() = Some(Listen { song: song.clone(), started, listened: 0f32, last_position: 0f32 });
+    let mut cycle = shared.cycle.lock().unwrap();
+    if !cycle.iter().any(|s| s.sql_id == song.sql_id) { cycle.push(song.clone()); }
+}
+
+/// Starts a new repeat cycle from the current song and whatever is queued after it.
+fn start_cycle(shared: &Shared, current: Option<&Song>, queue: &VecDeque<Song>) {
+    let mut cycle = shared.cycle.lock().unwrap();
+    cycle.clear();
+    for song in current.into_iter().chain(queue.iter()) {
+        if !cycle.iter().any(|s| s.sql_id == song.sql_id) { cycle.push(song.clone()); }
+    }
+}
+
+/// Replaces whatever the sink is playing with `song`. On failure the error is recorded and the
+/// sink is left empty so that the queueing thread moves on to the next song.
+fn load_song(shared: &Shared, sink: &Sink, song: &Song) -> bool {
+    if !sink.empty() { sink.stop(); }
+    // Anything still being listened to was cut short
+    finish_listen(shared, true);
+    *shared.progress.lock().unwrap() = 0f32;
+    // Whatever was lined up to follow the old song may not follow this one
+    *shared.next.lock().unwrap() = None;
+    shared.transitions.lock().unwrap().clear();
+
+    match decode_song(shared, song) {
+        Ok(source) => {
+            begin_listen(shared, song);
+            sink.append(TimeStretch::new(Transition::new(source, shared), shared.speed.clone(), shared.pitch.clone()));
+            true
+        }
+        Err(e) => {
+            println!("[AUDIO] Failed to play {}: {e}", song.name);
+            shared.errors.lock().unwrap().push_back(e);
+            false
+        }
+    }
+}
+
+/// With `RepeatMode::All` an empty queue is refilled with the current cycle, reshuffled if
+/// shuffle is on.
+fn refill_queue(shared: &Shared, queue: &mut VecDeque<Song>) {
+    if queue.is_empty() && *shared.repeat.lock().unwrap() == RepeatMode::All {
+        queue.extend(shared.cycle.lock().unwrap().iter().cloned());
+        if *shared.shuffle.lock().unwrap() {
+            queue.make_contiguous().shuffle(&mut rng());
+        }
+    }
+}
+
+/// Takes the next song from the queue.
+fn next_song(shared: &Shared, queue: &mut VecDeque<Song>) -> Option<Song> {
+    refill_queue(shared, queue);
+    queue.pop_front()
+}
+
+/// Keeps the song that should follow the current one decoded in `Shared::next`, replacing it
+/// whenever the queue changes what comes next.
+fn preload_next(shared: &Shared, repeat: RepeatMode) {
+    let wanted = {
+        let mut queue = shared.queue.lock().unwrap();
+        match repeat {
+            RepeatMode::One => shared.current.lock().unwrap().clone(),
+            _ => { refill_queue(shared, &mut queue); queue.front().cloned() }
+        }
+    };
+    let preloaded = shared.next.lock().unwrap().as_ref().map(|(song, _)| song.sql_id);
+    if wanted.as_ref().map(|song| song.sql_id) == preloaded { return; }
+
+    // Decoded without holding the lock, since playback takes it to move on. Songs that cannot be
+    // decoded are left for the queueing thread, which reports the error when it gets to them.
+    let next = wanted.and_then(|song| decode_song(shared, &song).ok().map(|source| (song, source)));
+    *shared.next.lock().unwrap() = next;
 }
 
-pub fn queueing_thread(sink: AM<Sink>, queue: AMQ<Song>, current: AMO<Song>, progress: AM<f32>, looping_song: AM<bool>) {
-    let sleep_duration = Duration::from_secs(1);
+/// Catches up with playback having moved on to `song` by itself.
+fn advance(shared: &Shared, song: Song) {
+    // The previous song played through to the end
+    finish_listen(shared, false);
+    let mut queue = shared.queue.lock().unwrap();
+    let mut current = shared.current.lock().unwrap();
+    let is_repeat = current.as_ref().is_some_and(|current| current.sql_id == song.sql_id) && *shared.repeat.lock().unwrap() == RepeatMode::One;
+    if !is_repeat {
+        if let Some(finished) = current.take() {
+            push_history(&mut shared.history.lock().unwrap(), finished);
+        }
+        if queue.front().is_some_and(|queued| queued.sql_id == song.sql_id) { queue.pop_front(); }
+        *current = Some(song.clone());
+    }
+    begin_listen(shared, &song);
+}
+
+fn queueing_thread(shared: Shared) {
+    let sleep_duration = Duration::from_millis(250);
     loop {
         sleep(sleep_duration);
 
-        let sink = sink.lock().unwrap();
-        let looping_song = looping_song.lock().unwrap();
+        let sink = shared.sink.lock().unwrap();
+        let repeat = *shared.repeat.lock().unwrap();
+        track_listen(&shared);
 
-        // If we need to queue the next song
-        if sink.empty() {
-            let mut progress = progress.lock().unwrap();
-            *progress = 0f32;
-            let mut queue = queue.lock().unwrap();
-            let mut current = current.lock().unwrap();
-            if !*looping_song { *current = queue.pop_front(); }
+        let transitions: Vec<Song> = shared.transitions.lock().unwrap().drain(..).collect();
+        transitions.into_iter().for_each(|song| advance(&shared, song));
 
-            let song = match current.as_ref() {
-                Some(song_ref) => song_ref.clone(),
-                None => continue
-            };
+        if !sink.empty() {
+            preload_next(&shared, repeat);
+            continue;
+        }
 
-            let file = BufReader::new(File::open(song.file.as_ref().unwrap()).unwrap());
-            let source = Decoder::new(file).unwrap();
-            sink.append(source);
-        } else if !sink.is_paused() {
-            let mut progress = progress.lock().unwrap();
-            *progress += (sleep_duration.as_secs() as f32) * sink.speed();
+        // The song played through to the end with nothing lined up after it, so start the next one
+        finish_listen(&shared, false);
+        let mut queue = shared.queue.lock().unwrap();
+        let mut current = shared.current.lock().unwrap();
+        if repeat != RepeatMode::One || current.is_none() {
+            if let Some(finished) = current.take() {
+                push_history(&mut shared.history.lock().unwrap(), finished);
+            }
+            *current = next_song(&shared, &mut queue);
         }
+
+        let song = match current.as_ref() {
+            Some(song_ref) => song_ref.clone(),
+            None => continue
+        };
+
+        // Drop the broken song so that looping doesn't retry it forever
+        if !load_song(&shared, &sink, &song) { *current = None; }
     }
 }
 
 impl AudioPlayer {
-    pub fn new() -> Result<Self, ()> {
-        let (stream, handle) = match OutputStream::try_default() {
-            Ok(data) => data,
-            Err(_) => return Err(())
-        };
-
-        let sink = match Sink::try_new(&handle) {
-            Ok(sink) => sink,
-            Err(_) => return Err(())
-        };
+    pub fn new() -> ResonateResult<Self> {
+        let (stream, handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&handle)?;
 
         sink.set_volume(0.2);
 
-        let sink = sync(sink);
-        let queue = sync(VecDeque::new());
-        let current = sync(None);
-        let progress = sync(0f32);
-        let looping_song = sync(false);
-
-        let sink_clone = sink.clone();
-        let queue_clone = queue.clone();
-        let current_clone = current.clone();
-        let progress_clone = progress.clone();
-        let looping_song_clone = looping_song.clone();
+        let shared = Shared {
+            sink: sync(sink),
+            queue: sync(VecDeque::new()),
+            current: sync(None),
+            history: sync(VecDeque::new()),
+            cycle: sync(Vec::new()),
+            progress: sync(0f32),
+            repeat: sync(RepeatMode::Off),
+            shuffle: sync(false),
+            errors: sync(VecDeque::new()),
+            listen: sync(None),
+            plays: sync(VecDeque::new()),
+            next: sync(None),
+            transitions: sync(VecDeque::new()),
+            crossfade: sync(0f32),
+            normalization: sync(Normalization::default()),
+            equalizer: sync(EqualizerSettings::default()),
+            speed: sync(1f32),
+            pitch: sync(0f32)
+        };
 
-        let _queue_handle = spawn(move || queueing_thread(sink_clone, queue_clone, current_clone, progress_clone, looping_song_clone));
+        let shared_clone = shared.clone();
+        let _queue_handle = spawn(move || queueing_thread(shared_clone));
 
         Ok(Self {
             _stream: stream,
             _handle: handle,
-            sink,
-            queue,
-            current,
             _queue_handle,
-            progress,
-            looping_song
+            shared
         })
     }
 
+    /// Starts `song` immediately if nothing is loaded, rather than waiting for the queueing thread.
+    fn start_if_idle(&self) {
+        let sink = self.shared.sink.lock().unwrap();
+        let mut queue = self.shared.queue.lock().unwrap();
+        let mut current = self.shared.current.lock().unwrap();
+        if current.is_none() && sink.empty() {
+            *current = queue.pop_front();
+            if let Some(song) = current.clone() {
+                match load_song(&self.shared, &sink, &song) {
+                    true => start_cycle(&self.shared, current.as_ref(), &queue),
+                    false => *current = None
+                }
+            }
+        }
+    }
 
-    pub fn queue_song(&mut self, song: Song) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.push_back(song);
-        let mut current = self.current.lock().unwrap();
-        if current.is_none() {
-            *current = Some(queue[0].clone());
+    /// Makes `next` the current song, moving the old current song into the history.
+    fn replace_current(&self, sink: &Sink, current: &mut Option<Song>, next: Option<Song>) {
+        if let Some(finished) = current.take() {
+            push_history(&mut self.shared.history.lock().unwrap(), finished);
         }
+        match next {
+            Some(song) => if load_song(&self.shared, sink, &song) { *current = Some(song) },
+            None => {
+                sink.stop();
+                finish_listen(&self.shared, true);
+                *self.shared.progress.lock().unwrap() = 0f32;
+            }
+        }
+    }
+
+    /// Adds a song to the end of the queue.
+    pub fn queue_song(&mut self, song: Song) {
+        self.shared.queue.lock().unwrap().push_back(song);
+        self.start_if_idle();
+    }
+
+    /// Adds a song to the front of the queue so that it plays after the current song.
+    pub fn play_next(&mut self, song: Song) {
+        self.shared.queue.lock().unwrap().push_front(song);
+        self.start_if_idle();
     }
 
     pub fn play(&mut self, song: Song) {
         self.resume();
         println!("[AUDIO] Received play command for {}", song.name);
-        self.insert_song(song.clone());
-        println!("[AUDIO] Added song to queue");
-        self.skip_song();
-        println!("[AUDIO] Skipped current song");
-        let mut current = self.current.lock().unwrap();
-        *current = Some(song);
+        let sink = self.shared.sink.lock().unwrap();
+        let queue = self.shared.queue.lock().unwrap();
+        let mut current = self.shared.current.lock().unwrap();
+        self.replace_current(&sink, &mut current, Some(song));
+        // Playing a song directly starts over what `RepeatMode::All` goes back to
+        if current.is_some() { start_cycle(&self.shared, current.as_ref(), &queue); }
         println!("[AUDIO] Updated current");
     }
 
-    pub fn insert_song(&mut self, song: Song) {
-        let mut queue = self.queue.lock().unwrap();
-        queue.push_front(song);
-        let mut current = self.current.lock().unwrap();
-        *current = Some(queue[0].clone());
-    }
-
     pub fn skip_song(&mut self) {
-        let sink = self.sink.lock().unwrap();
+        let sink = self.shared.sink.lock().unwrap();
         println!("[AUDIO] Song skipped");
-        let queue = self.queue.lock().unwrap();
-        if queue.len() > 0 {
-            let mut current = self.current.lock().unwrap();
-            *current = Some(queue[0].clone());
-        } else {
-            let mut current = self.current.lock().unwrap();
-            *current = None;
+        let next = next_song(&self.shared, &mut self.shared.queue.lock().unwrap());
+        let mut current = self.shared.current.lock().unwrap();
+        self.replace_current(&sink, &mut current, next);
+    }
+
+    /// Returns to the previously played song. Early in a song, or with no history, this restarts
+    /// the current song instead.
+    pub fn previous_song(&mut self) -> ResonateResult<()> {
+        let position = *self.shared.progress.lock().unwrap();
+        let previous = if position > 3f32 { None } else { self.shared.history.lock().unwrap().pop_back() };
+
+        let previous = match previous {
+            Some(previous) => previous,
+            None => return self.seek(Duration::ZERO)
+        };
+
+        let sink = self.shared.sink.lock().unwrap();
+        let mut queue = self.shared.queue.lock().unwrap();
+        let mut current = self.shared.current.lock().unwrap();
+        if let Some(song) = current.take() { queue.push_front(song); }
+        if load_song(&self.shared, &sink, &previous) { *current = Some(previous); }
+        Ok(())
+    }
+
+    /// Plays the queued song at `index`, skipping everything queued before it. Only the song that
+    /// was playing goes into the history, since the skipped ones were never heard.
+    pub fn jump_to(&mut self, index: usize) {
+        let sink = self.shared.sink.lock().unwrap();
+        let mut queue = self.shared.queue.lock().unwrap();
+        if index >= queue.len() { return; }
+
+        let mut current = self.shared.current.lock().unwrap();
+        queue.drain(..index);
+        let next = queue.pop_front();
+        drop(queue);
+
+        self.replace_current(&sink, &mut current, next);
+    }
+
+    // Each of these drops the preloaded song while holding the queue, so that a song ending before
+    // the next preload cannot move on to one that was removed or moved.
+
+    pub fn remove_from_queue(&mut self, index: usize) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.remove(index);
+        *self.shared.next.lock().unwrap() = None;
+    }
+
+    /// Moves the queued song at `from` so that it ends up at index `to`.
+    pub fn move_in_queue(&mut self, from: usize, to: usize) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if to >= queue.len() { return; }
+        if let Some(song) = queue.remove(from) {
+            queue.insert(to, song);
         }
-        sink.skip_one();
+        *self.shared.next.lock().unwrap() = None;
+    }
+
+    /// Empties the queue and starts a new repeat cycle from the current song.
+    pub fn clear_queue(&mut self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.clear();
+        *self.shared.next.lock().unwrap() = None;
+        start_cycle(&self.shared, self.get_current().as_ref(), &queue);
     }
 
     pub fn pause(&self) {
-        let sink = self.sink.lock().unwrap();
+        let sink = self.shared.sink.lock().unwrap();
         sink.pause();
     }
 
     pub fn resume(&self) {
-        let sink = self.sink.lock().unwrap();
+        let sink = self.shared.sink.lock().unwrap();
         sink.play();
     }
 
     pub fn is_paused(&self) -> bool {
-        let sink = self.sink.lock().unwrap();
+        let sink = self.shared.sink.lock().unwrap();
         sink.is_paused()
     }
 
     pub fn get_current(&self) -> Option<Song> {
-        let current = self.current.lock().unwrap();
-        match current.as_ref() {
-            Some(song_ref) => Some(song_ref.clone()),
-            None => None
-        }
+        let current = self.shared.current.lock().unwrap();
+        current.as_ref().cloned()
     }
 
     pub fn is_this_playing(&self, song: &Song) -> bool {
-        let current = self.current.lock().unwrap();
+        let current = self.shared.current.lock().unwrap();
         match current.as_ref() {
             Some(song_ref) => song_ref.sql_id == song.sql_id,
             None => false
         }
     }
 
+    /// Applies a change to every copy of a song the player holds, e.g. after it was rated.
+    pub fn update_song(&self, sql_id: usize, update: impl Fn(&mut Song)) {
+        self.shared.current.lock().unwrap().iter_mut().filter(|song| song.sql_id == sql_id).for_each(&update);
+        self.shared.queue.lock().unwrap().iter_mut().filter(|song| song.sql_id == sql_id).for_each(&update);
+        self.shared.history.lock().unwrap().iter_mut().filter(|song| song.sql_id == sql_id).for_each(&update);
+        self.shared.cycle.lock().unwrap().iter_mut().filter(|song| song.sql_id == sql_id).for_each(&update);
+    }
+
     pub fn get_queue(&self) -> Vec<Song> {
-        let queue = self.queue.lock().unwrap();
-        let r: Vec<Song> = queue.clone().into();
-        let remove_first = if let Some(c) = self.get_current() {
-            if r.len() > 0 {
-                if r[0].sql_id == c.sql_id {
-                    true
-                } else { false }
-            } else { false }
-        } else { false };
-        if remove_first { r[1..].to_vec() } else { r }
+        let queue = self.shared.queue.lock().unwrap();
+        queue.iter().cloned().collect()
     }
 
-    pub fn slow(&self) {
-        let sink = self.sink.lock().unwrap();
-        sink.set_speed(0.85);
+    pub fn get_speed(&self) -> f32 {
+        *self.shared.speed.lock().unwrap()
     }
 
-    pub fn fast(&self) {
-        let sink = self.sink.lock().unwrap();
-        sink.set_speed(1.4);
+    /// Changes tempo without changing pitch. Rounded to hundredths, so that a slider landing just
+    /// off 1x still plays untouched.
+    pub fn set_speed(&self, speed: f32) {
+        *self.shared.speed.lock().unwrap() = ((speed * 100f32).round() / 100f32).clamp(MIN_SPEED, MAX_SPEED);
     }
-    
-    pub fn normal(&self) {
-        let sink = self.sink.lock().unwrap();
-        sink.set_speed(1f32)
+
+    pub fn get_pitch(&self) -> f32 {
+        *self.shared.pitch.lock().unwrap()
+    }
+
+    /// Shifts pitch by a number of semitones without changing tempo.
+    pub fn set_pitch(&self, semitones: f32) {
+        *self.shared.pitch.lock().unwrap() = semitones.clamp(-MAX_PITCH, MAX_PITCH);
     }
     
-    pub fn get_progress_source(&self) -> AM<f32> { self.progress.clone() }
+    pub fn get_progress_source(&self) -> AM<f32> { self.shared.progress.clone() }
+
+    /// Jumps to a position within the current song, clamped to the song's length.
+    pub fn seek(&self, position: Duration) -> ResonateResult<()> {
+        let position = match self.get_current() {
+            Some(song) => position.min(Duration::from_secs(song.duration as u64)),
+            None => return Ok(())
+        };
+        let sink = self.shared.sink.lock().unwrap();
+        sink.try_seek(position)?;
+        *self.shared.progress.lock().unwrap() = position.as_secs_f32();
+        Ok(())
+    }
+
+    /// Seeks forwards or backwards by `offset` seconds from the current position.
+    pub fn seek_relative(&self, offset: f32) -> ResonateResult<()> {
+        let target = (*self.shared.progress.lock().unwrap() + offset).max(0f32);
+        self.seek(Duration::from_secs_f32(target))
+    }
 
     pub fn get_volume(&self) -> f32 {
-        let sink = self.sink.lock().unwrap();
+        let sink = self.shared.sink.lock().unwrap();
         sink.volume() * 200f32
     }
 
     pub fn set_volume(&self, volume: f32) {
-        let sink = self.sink.lock().unwrap();
+        let sink = self.shared.sink.lock().unwrap();
         sink.set_volume(volume / 200f32)
     }
 
-    pub fn is_looping(&self) -> bool {
-        let looping = self.looping_song.lock().unwrap();
-        *looping
+    pub fn get_crossfade(&self) -> f32 {
+        *self.shared.crossfade.lock().unwrap()
     }
 
-    pub fn set_looping(&mut self, do_looping: bool) {
-        let mut looping = self.looping_song.lock().unwrap();
-        *looping = do_looping;
+    /// Sets how many seconds one song fades into the next, 0 for a plain gapless transition.
+    pub fn set_crossfade(&self, seconds: f32) {
+        *self.shared.crossfade.lock().unwrap() = seconds.clamp(0f32, MAX_CROSSFADE);
+    }
+
+    pub fn get_normalization(&self) -> Normalization {
+        *self.shared.normalization.lock().unwrap()
+    }
+
+    /// Takes effect straight away, including on the song that is playing.
+    pub fn set_normalization(&self, normalization: Normalization) {
+        *self.shared.normalization.lock().unwrap() = normalization;
+    }
+
+    pub fn get_equalizer(&self) -> EqualizerSettings {
+        self.shared.equalizer.lock().unwrap().clone()
     }
-}
 
-pub async fn get_progress(progress_source: AM<f32>) -> Message {
-    let progress = progress_source.lock().unwrap();
-    sleep(Duration::from_secs(1));
-    Message::ProgressUpdate(*progress)
+    /// Takes effect straight away, including on the song that is playing.
+    pub fn set_equalizer(&self, equalizer: EqualizerSettings) {
+        *self.shared.equalizer.lock().unwrap() = equalizer;
+    }
+
+    pub fn get_repeat(&self) -> RepeatMode {
+        *self.shared.repeat.lock().unwrap()
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        *self.shared.repeat.lock().unwrap() = repeat;
+    }
+
+    pub fn is_shuffling(&self) -> bool {
+        *self.shared.shuffle.lock().unwrap()
+    }
+
+    /// Turning shuffle on also shuffles whatever is already queued.
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        *self.shared.shuffle.lock().unwrap() = shuffle;
+        if shuffle {
+            self.shared.queue.lock().unwrap().make_contiguous().shuffle(&mut rng());
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        Session {
+            current: self.get_current(),
+            position: *self.shared.progress.lock().unwrap(),
+            queue: self.get_queue(),
+            volume: self.get_volume(),
+            speed: self.get_speed(),
+            pitch: self.get_pitch(),
+            repeat: self.get_repeat(),
+            shuffle: self.is_shuffling()
+        }
+    }
+
+    /// Restores a saved session. The saved song is loaded paused at its saved position.
+    pub fn restore(&mut self, session: Session) -> ResonateResult<()> {
+        self.set_volume(session.volume);
+        self.set_speed(session.speed);
+        self.set_pitch(session.pitch);
+        self.set_repeat(session.repeat);
+        *self.shared.shuffle.lock().unwrap() = session.shuffle;
+        *self.shared.queue.lock().unwrap() = session.queue.into();
+        start_cycle(&self.shared, session.current.as_ref(), &self.shared.queue.lock().unwrap());
+
+        let song = match session.current {
+            Some(song) => song,
+            None => return Ok(())
+        };
+
+        {
+            let sink = self.shared.sink.lock().unwrap();
+            let mut current = self.shared.current.lock().unwrap();
+            sink.pause();
+            if !load_song(&self.shared, &sink, &song) { return Ok(()); }
+            *current = Some(song);
+        }
+        self.seek(Duration::from_secs_f32(session.position))
+    }
+
+    /// Drains the plays counted since the last call.
+    pub fn take_plays(&self) -> Vec<Play> {
+        self.shared.plays.lock().unwrap().drain(..).collect()
+    }
+
+    /// Drains any errors raised by the queueing thread since the last call.
+    pub fn take_errors(&self) -> Vec<ResonateError> {
+        let mut errors = self.shared.errors.lock().unwrap();
+        errors.drain(..).collect()
+    }
 }
 
-pub fn query_song_length(path: &PathBuf) -> usize {
-    let file = File::open(path).expect("Failed to open file");
-    println!("Read file, length: {}", file.metadata().unwrap().size());
+pub fn query_song_length(path: &Path) -> ResonateResult<usize> {
+    let file = File::open(path)?;
+    println!("Read file, length: {}", file.metadata()?.len());
     let reader = BufReader::new(file);
-    let decoder = Decoder::new(reader).expect("Failed to decode file");
+    let decoder = Decoder::new(reader)?;
     let sample_rate = decoder.sample_rate() as f64;
     let num_samples = decoder.total_duration().unwrap_or_default().as_secs_f64() * sample_rate;
-    (num_samples / sample_rate).round() as usize
+    Ok((num_samples / sample_rate).round() as usize)
 }
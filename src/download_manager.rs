@@ -0,0 +1,384 @@
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use iced::Task;
+
+use crate::application::Message;
+use crate::downloader::download_song;
+use crate::error::{ResonateError, ResonateResult};
+use crate::filemanager::fetch_thumbnail;
+use crate::tagger::write_tags;
+use crate::music::Song;
+use crate::utility::*;
+
+pub const DEFAULT_DOWNLOAD_LIMIT: usize = 4;
+pub const MAX_DOWNLOAD_LIMIT: usize = 16;
+
+/// How many times yt-dlp is run for a song before the download is marked as failed.
+const MAX_ATTEMPTS: usize = 3;
+/// Wait before the first retry. Doubles after every further failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadProgress {
+    pub percent: f32,
+    pub speed: Option<String>,
+    pub eta: Option<String>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DownloadStatus {
+    Queued,
+    Downloading,
+    // Waiting this many seconds before trying again
+    Retrying(u64),
+    Finished,
+    Failed(ResonateError),
+    Cancelled
+}
+
+impl DownloadStatus {
+    /// Whether the download still holds, or is waiting for, a slot.
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Self::Queued | Self::Downloading | Self::Retrying(_))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DownloadState {
+    pub status: DownloadStatus,
+    pub progress: Option<DownloadProgress>,
+    pub attempt: usize
+}
+
+/// A single song download, shared between the manager and the worker running yt-dlp.
+pub struct DownloadJob {
+    pub song: Song,
+    state: AM<DownloadState>,
+    child: AMO<Child>,
+    cancelled: AtomicBool
+}
+
+impl DownloadJob {
+    fn new(song: Song) -> Self {
+        Self {
+            song,
+            state: sync(DownloadState { status: DownloadStatus::Queued, progress: None, attempt: 0 }),
+            child: sync(None),
+            cancelled: AtomicBool::new(false)
+        }
+    }
+
+    pub fn state(&self) -> DownloadState {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn set_status(&self, status: DownloadStatus) {
+        self.state.lock().unwrap().status = status;
+    }
+
+    pub fn set_progress(&self, progress: DownloadProgress) {
+        self.state.lock().unwrap().progress = Some(progress);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Hands the running yt-dlp process to the job so that it can be killed on cancel.
+    pub fn attach(&self, child: Child) {
+        *self.child.lock().unwrap() = Some(child);
+        // A cancel that arrived while the process was starting would otherwise be missed
+        if self.is_cancelled() { self.kill(); }
+    }
+
+    /// Waits for the attached process to exit and releases it.
+    pub fn wait(&self) -> ResonateResult<ExitStatus> {
+        let mut child = self.child.lock().unwrap().take()
+            .ok_or(ResonateError::ProcessSpawn(String::from("yt-dlp is not running")))?;
+        Ok(child.wait()?)
+    }
+
+    fn kill(&self) {
+        if let Some(child) = self.child.lock().unwrap().as_mut() {
+            let _ = child.kill();
+        }
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.kill();
+    }
+
+    /// Sleeps for the backoff period, returning early with false if the job is cancelled.
+    fn backoff(&self, duration: Duration) -> bool {
+        let mut remaining = duration.as_secs();
+        while remaining > 0 {
+            if self.is_cancelled() { return false; }
+            self.set_status(DownloadStatus::Retrying(remaining));
+            sleep(Duration::from_secs(1));
+            remaining -= 1;
+        }
+        !self.is_cancelled()
+    }
+}
+
+/// Fetches the thumbnail if it is missing and writes the song's tags. Neither is required for
+/// playback, so failures are only logged.
+fn tag_download(directory: &Path, song: &Song) {
+    if !directory.join(format!("{}.png", song.id)).exists() {
+        if let Err(e) = fetch_thumbnail(directory.to_path_buf(), song.id.clone()) {
+            println!("[DOWNLOADER] Failed to fetch thumbnail for {}: {e}", song.name);
+        }
+    }
+
+    if let Err(e) = write_tags(directory, song) {
+        println!("[TAGGER] Failed to tag {}: {e}", song.name);
+    }
+}
+
+/// Runs `attempt` until it succeeds, the job is cancelled or `MAX_ATTEMPTS` is reached, waiting
+/// with `backoff` between attempts. The backoff returns false if the job was cancelled meanwhile.
+fn retry(job: &DownloadJob, mut attempt: impl FnMut() -> ResonateResult<Song>, mut backoff: impl FnMut(Duration) -> bool) -> ResonateResult<Song> {
+    let mut wait = INITIAL_BACKOFF;
+    let mut number = 1;
+    loop {
+        {
+            let mut state = job.state.lock().unwrap();
+            state.status = DownloadStatus::Downloading;
+            state.progress = None;
+            state.attempt = number;
+        }
+
+        match attempt() {
+            Ok(song) => return Ok(song),
+            Err(e) if job.is_cancelled() || number >= MAX_ATTEMPTS => return Err(e),
+            Err(e) => {
+                println!("[DOWNLOADER] Attempt {number} of {} failed, retrying: {e}", job.song.name);
+                if !backoff(wait) { return Err(ResonateError::Cancelled); }
+                wait *= 2;
+                number += 1;
+            }
+        }
+    }
+}
+
+/// Runs a job to completion, retrying failed attempts with exponential backoff.
+fn run_job(directory: PathBuf, job: Arc<DownloadJob>) -> ResonateResult<Song> {
+    let song = retry(&job, || download_song(&directory, &job), |wait| job.backoff(wait))?;
+    tag_download(&directory, &song);
+    Ok(song)
+}
+
+/// Owns every download started this session, limiting how many run at once. Finished, failed and
+/// cancelled downloads stay listed until cleared so the downloads panel can show what happened.
+pub struct DownloadManager {
+    directory: PathBuf,
+    jobs: Vec<Arc<DownloadJob>>,
+    limit: usize
+}
+
+impl DownloadManager {
+    pub fn new(directory: PathBuf, limit: usize) -> Self {
+        Self { directory, jobs: Vec::new(), limit: limit.clamp(1, MAX_DOWNLOAD_LIMIT) }
+    }
+
+    pub fn get_limit(&self) -> usize { self.limit }
+
+    /// Changes the concurrency limit, starting queued downloads if it went up.
+    pub fn set_limit(&mut self, limit: usize) -> Task<Message> {
+        self.limit = limit.clamp(1, MAX_DOWNLOAD_LIMIT);
+        self.start_ready()
+    }
+
+    fn find(&self, song: &Song) -> Option<&Arc<DownloadJob>> {
+        self.jobs.iter().find(|job| job.song.sql_id == song.sql_id)
+    }
+
+    pub fn status_of(&self, song: &Song) -> Option<DownloadStatus> {
+        self.find(song).map(|job| job.state().status)
+    }
+
+    /// Whether the song is queued or being downloaded.
+    pub fn is_pending(&self, song: &Song) -> bool {
+        self.status_of(song).is_some_and(|status| status.is_pending())
+    }
+
+    /// Every download with its current state, in the order they were requested.
+    pub fn entries(&self) -> Vec<(Song, DownloadState)> {
+        self.jobs.iter().map(|job| (job.song.clone(), job.state())).collect()
+    }
+
+    /// Queues a song, replacing any earlier attempt that has ended. Does nothing if the song is
+    /// already pending.
+    pub fn enqueue(&mut self, song: Song) -> Task<Message> {
+        if self.is_pending(&song) { return Task::none(); }
+        self.jobs.retain(|job| job.song.sql_id != song.sql_id);
+        println!("[DOWNLOADER] Queued {}", song.name);
+        self.jobs.push(Arc::new(DownloadJob::new(song)));
+        self.start_ready()
+    }
+
+    /// Starts queued downloads until the concurrency limit is reached.
+    fn start_ready(&mut self) -> Task<Message> {
+        let running = self.jobs.iter()
+            .filter(|job| matches!(job.state().status, DownloadStatus::Downloading | DownloadStatus::Retrying(_)))
+            .count();
+
+        let ready: Vec<Arc<DownloadJob>> = self.jobs.iter()
+            .filter(|job| job.state().status == DownloadStatus::Queued)
+            .take(self.limit.saturating_sub(running))
+            .cloned()
+            .collect();
+
+        Task::batch(ready.into_iter().map(|job| {
+            // Mark the job before its worker starts so the next call does not pick it up again
+            job.set_status(DownloadStatus::Downloading);
+            println!("[DOWNLOADER] Downloading {}", job.song.name);
+            let directory = self.directory.clone();
+            let song = job.song.clone();
+            Task::perform(async move {
+                tokio::task::spawn_blocking(move || run_job(directory, job)).await
+                    .map_err(|e| ResonateError::ProcessSpawn(e.to_string()))?
+            }, move |result| match result {
+                Ok(song) => Message::SuccessfulDownload(song),
+                Err(e) => Message::DownloadFailed(song.clone(), e)
+            })
+        }))
+    }
+
+    /// Records a finished download and starts the next one.
+    pub fn finish(&mut self, song: &Song) -> Task<Message> {
+        if let Some(job) = self.find(song) {
+            let mut state = job.state.lock().unwrap();
+            state.status = DownloadStatus::Finished;
+            state.progress = None;
+        }
+        self.start_ready()
+    }
+
+    /// Records a failed or cancelled download and starts the next one.
+    pub fn fail(&mut self, song: &Song, error: ResonateError) -> Task<Message> {
+        if let Some(job) = self.find(song) {
+            let status = if job.is_cancelled() { DownloadStatus::Cancelled } else { DownloadStatus::Failed(error) };
+            job.set_status(status);
+        }
+        self.start_ready()
+    }
+
+    /// Stops a download. Queued downloads are cancelled immediately; running ones once yt-dlp exits.
+    pub fn cancel(&mut self, song: &Song) {
+        if let Some(job) = self.find(song) {
+            job.cancel();
+            if job.state().status == DownloadStatus::Queued {
+                job.set_status(DownloadStatus::Cancelled);
+            }
+        }
+    }
+
+    /// Clears every download that has ended, leaving pending ones in place.
+    pub fn clear_finished(&mut self) {
+        self.jobs.retain(|job| job.state().status.is_pending());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> DownloadJob {
+        DownloadJob::new(Song::new(1, String::from("Under Pressure"), String::from("Queen"), String::new(), String::from("a01QQZyl-_I"), 249, None))
+    }
+
+    fn failure() -> ResonateError {
+        ResonateError::ProcessSpawn(String::from("yt-dlp exited with 1"))
+    }
+
+    #[test]
+    fn retries_with_doubling_backoff_until_an_attempt_succeeds() {
+        let job = job();
+        let mut attempts = 0;
+        let mut waits = Vec::new();
+        let result = retry(&job, || {
+            attempts += 1;
+            if attempts < MAX_ATTEMPTS { Err(failure()) } else { Ok(job.song.clone()) }
+        }, |wait| { waits.push(wait); true });
+
+        assert_eq!(result, Ok(job.song.clone()));
+        assert_eq!(waits, vec![INITIAL_BACKOFF, INITIAL_BACKOFF * 2]);
+        assert_eq!(job.state().attempt, MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let job = job();
+        let mut attempts = 0;
+        let mut waits = 0;
+        let result = retry(&job, || { attempts += 1; Err(failure()) }, |_| { waits += 1; true });
+
+        assert_eq!(result, Err(failure()));
+        assert_eq!(attempts, MAX_ATTEMPTS);
+        assert_eq!(waits, MAX_ATTEMPTS - 1);
+    }
+
+    #[test]
+    fn cancelling_during_an_attempt_stops_without_retrying() {
+        let job = job();
+        let mut waits = 0;
+        let result = retry(&job, || { job.cancel(); Err(ResonateError::Cancelled) }, |_| { waits += 1; true });
+
+        assert_eq!(result, Err(ResonateError::Cancelled));
+        assert_eq!(waits, 0);
+        assert_eq!(job.state().attempt, 1);
+    }
+
+    #[test]
+    fn cancelling_during_the_backoff_stops_without_retrying() {
+        let job = job();
+        let mut attempts = 0;
+        let result = retry(&job, || { attempts += 1; Err(failure()) }, |wait| { job.cancel(); job.backoff(wait) });
+
+        assert_eq!(result, Err(ResonateError::Cancelled));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn backoff_returns_false_once_cancelled() {
+        let job = job();
+        assert!(job.backoff(Duration::ZERO));
+
+        job.cancel();
+        // Returns straight away rather than sleeping through the backoff
+        assert!(!job.backoff(Duration::from_secs(60)));
+        assert!(!job.backoff(Duration::ZERO));
+        assert_eq!(job.state().status, DownloadStatus::Queued);
+    }
+
+    #[test]
+    fn waiting_without_a_process_fails() {
+        assert!(job().wait().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attaching_to_a_cancelled_job_kills_the_process() {
+        let job = job();
+        job.cancel();
+        job.attach(std::process::Command::new("sleep").arg("30").spawn().unwrap());
+        assert!(!job.wait().unwrap().success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cancelling_kills_the_attached_process() {
+        let job = job();
+        job.attach(std::process::Command::new("sleep").arg("30").spawn().unwrap());
+        job.cancel();
+        assert!(!job.wait().unwrap().success());
+        assert!(job.is_cancelled());
+    }
+}
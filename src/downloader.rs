@@ -1,160 +1,213 @@
-use std::thread::sleep;
 use std::io::{BufReader, BufRead};
 use std::process::Command;
-use thirtyfour_sync::prelude::*;
-use std::time::Duration;
 use std::process::Stdio;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::application::Message;
 use crate::audio::query_song_length;
+use crate::download_manager::{DownloadJob, DownloadProgress};
+use crate::error::{ResonateError, ResonateResult};
+use crate::filemanager::Database;
+use crate::importer::content_id;
+use crate::tagger::write_tags;
 use crate::music::Song;
+use crate::utility::*;
+
+/// Puts a file into the library as an mp3. MP3s are copied as they are, anything else is converted
+/// with ffmpeg at LAME's highest VBR quality, since sources are often lossless. Embedded pictures
+/// are dropped because resonate writes its own cover art.
+pub fn copy_into_library(input: &Path, target: &Path) -> ResonateResult<()> {
+    let is_mp3 = input.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"));
+    if is_mp3 {
+        std::fs::copy(input, target)?;
+        return Ok(());
+    }
 
-pub fn convert_and_save_song(directory: PathBuf, song: &mut Song) {
-    let target_pos = directory.join(PathBuf::from(format!("{}.mp3", song.id)));
-    let output = format!("{}/{}.mp3", directory.to_string_lossy().to_string(), song.id);
-    let input = format!("{}", song.file.as_ref().unwrap().to_string_lossy().to_string());
-
-    song.duration = query_song_length(song.file.as_ref().unwrap());
-
-    let _ = Command::new("ffmpeg")
+    let status = Command::new("ffmpeg")
+        .arg("-y")
         .arg("-i")
         .arg(input)
-        .arg(output)
+        .arg("-vn")
+        .arg("-codec:a")
+        .arg("libmp3lame")
+        .arg("-q:a")
+        .arg("0")
+        .arg(target)
         .stdout(Stdio::null())
-        .spawn().unwrap();
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| ResonateError::ProcessSpawn(format!("ffmpeg: {e}")))?;
+
+    if !status.success() {
+        return Err(ResonateError::ProcessSpawn(format!("ffmpeg exited with {status} while converting {}", input.display())));
+    }
+    Ok(())
+}
+
+pub fn convert_and_save_song(directory: PathBuf, song: &mut Song) -> ResonateResult<()> {
+    let target_pos = directory.join(PathBuf::from(format!("{}.mp3", song.id)));
+    let input = match song.file.as_ref() {
+        Some(file) => file.clone(),
+        None => return Err(ResonateError::Io(String::from("No file selected.")))
+    };
 
+    song.duration = query_song_length(&input)?;
+    copy_into_library(&input, &target_pos)?;
     song.file = Some(target_pos);
+
+    // The song is usable without tags, so a failure here is only logged
+    if let Err(e) = write_tags(&directory, song) {
+        println!("[TAGGER] Failed to tag {}: {e}", song.name);
+    }
+    Ok(())
 }
 
-pub async fn download(directory: PathBuf, mut target: Song) -> Message {
+/// Converts an uploaded file into the library and caches it, returning the cached song. Hashing
+/// and converting can take a while, so this should be run from `spawn_blocking`; the database is
+/// only locked for the lookup and the insert.
+pub fn save_uploaded_song(database: &AM<Database>, mut song: Song) -> ResonateResult<Song> {
+    // Uploads are identified by their contents so that the same file is only stored once
+    if let Some(file) = song.file.as_ref() {
+        song.id = content_id(file)?;
+    }
+
+    let (directory, existing) = {
+        let database = database.lock().unwrap();
+        (database.get_directory(), database.find_song_by_ytid(&song.id)?)
+    };
+    if let Some(existing) = existing.as_ref().filter(|existing| existing.file.as_ref().is_some_and(|file| file.exists())) {
+        return Ok(existing.clone());
+    }
+
+    convert_and_save_song(directory, &mut song)?;
+    if let Some(file) = song.file.as_ref() {
+        println!("Song path: {}", file.display());
+    }
+
+    let database = database.lock().unwrap();
+    match existing {
+        Some(existing) => { song.sql_id = existing.sql_id; database.update(song.clone())?; }
+        None => database.add_song_to_cache(&mut song)?
+    }
+    Ok(song)
+}
+
+// Marks the lines that carry progress so they can be told apart from yt-dlp's other output
+const PROGRESS_PREFIX: &str = "[resonate]";
+
+/// Runs yt-dlp for a single song, reporting progress to the job as it goes. The child process is
+/// shared with the job so that cancelling kills it.
+pub fn download_song(directory: &Path, job: &DownloadJob) -> ResonateResult<Song> {
+    let mut target = job.song.clone();
     let task_path = directory.join(PathBuf::from(format!("{}.mp3", target.id)));
-    println!("[WORKER] Using task_path {}", task_path.to_string_lossy().to_string());
+    println!("[WORKER] Using task_path {}", task_path.to_string_lossy());
     if task_path.exists() {
         println!("[WORKER] {} is already downloaded", target.name);
-        return Message::SuccessfulDownload(target);
+        target.file = Some(task_path);
+        return Ok(target);
     }
 
-    let mut handle = Command::new("yt-dlp")
+    let mut child = Command::new("yt-dlp")
         .arg("-f")
         .arg("bestaudio")
         .arg("--extract-audio")
         .arg("--audio-format")
         .arg("mp3")
+        .arg("--newline")
+        .arg("--no-colors")
+        .arg("--progress-template")
+        .arg(format!("download:{PROGRESS_PREFIX} %(progress._percent_str)s|%(progress._speed_str)s|%(progress._eta_str)s"))
         .arg("-o")
-        .arg(format!("{}/{}.mp3", directory.to_string_lossy().to_string(), target.id))
+        .arg(format!("{}/{}.mp3", directory.to_string_lossy(), target.id))
         .arg(format!("https://music.youtube.com/watch?v={}", target.id))
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .spawn().unwrap();
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ResonateError::ProcessSpawn(format!("yt-dlp: {e}")))?;
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    job.attach(child);
+
+    // Drain stderr on its own thread so a chatty yt-dlp can never fill the pipe and stall
+    let errors = std::thread::spawn(move || {
+        stderr.map(|stderr| BufReader::new(stderr)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| line.starts_with("ERROR"))
+            .last())
+            .unwrap_or_default()
+    });
 
     println!("[WORKER] Waiting for download {}", target.name);
-    let _ = handle.wait();
-    target.file = Some(task_path);
-    println!("[WORKER] SuccessfulDownload({})", target.name);
-    Message::SuccessfulDownload(target)
-}
-
-pub fn search_youtube_music(query: String, directory: PathBuf) -> Result<Vec<Song>, String> {
-    let mut chromedriver = match Command::new("chromedriver").stdout(Stdio::piped()).spawn() {
-        Ok(child) => child,
-        Err(e) => return Err(format!("Failed to spawn chromedriver: {e:?}"))
-    };
-
-    let stdout = match chromedriver.stdout.take() {
-        Some(stdout) => stdout,
-        None => return Err(String::from("Failed to capture STDOUT of chromedriver."))
-    };
-
-    let mut reader = BufReader::new(stdout).lines();
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some(progress) = parse_progress_line(&line) { job.set_progress(progress); }
+        }
+    }
 
-    for _ in 0..3 { let _ = reader.next(); }
+    let status = job.wait()?;
+    let reason = errors.join().unwrap_or_default();
 
-    let ip = match reader.next() {
-        Some(line) => line.unwrap().split(" ").nth(6).unwrap().to_string().strip_suffix('.').unwrap().to_string(),
-        None => return Err(format!("Failed to read STDOUT of chromedriver."))
-    };
+    if job.is_cancelled() {
+        return Err(ResonateError::Cancelled);
+    }
 
-    let mut caps = DesiredCapabilities::chrome();
-    let _ = caps.add_chrome_arg("--headless");
-    let _ = caps.add_chrome_arg("--disable-gpu");
-    let _ = caps.add_chrome_arg("--no-sandbox");
-    let _ = caps.add_chrome_arg("--disable-software-rasterizer");
-    let _ = caps.add_chrome_arg("--remote-debugging-port=9222");
-    let _ = caps.add_chrome_arg("--user-agent=Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36");
-
-    let driver = WebDriver::new(format!("http://localhost:{ip}").as_str(), caps).unwrap();
-    driver.get(format!("https://music.youtube.com/search?q={}", query.replace(" ", "+"))).unwrap();
-
-    let songs_button = driver.find_elements(By::Css("a.yt-simple-endpoint")).unwrap();
-    for element in songs_button {
-        if element.is_clickable().unwrap() && element.text().unwrap() == "Songs" {
-            element.click().unwrap();
-            break;
-        }
+    if !status.success() || !task_path.exists() {
+        return Err(ResonateError::ProcessSpawn(match reason {
+            Some(reason) => format!("yt-dlp failed to download {}: {reason}", target.name),
+            None => format!("yt-dlp exited with {status} while downloading {}", target.name)
+        }));
     }
 
-    sleep(Duration::from_secs(1));
+    target.file = Some(task_path);
+    println!("[WORKER] SuccessfulDownload({})", target.name);
+    Ok(target)
+}
 
-    let video_titles = driver.find_elements(By::ClassName("style-scope ytmusic-shelf-renderer")).unwrap();
-    if video_titles.len() == 0 { return Ok(Vec::new()); }
+/// Parses a line printed through the progress template, e.g. `[resonate]  45.3%|1.20MiB/s|00:12`.
+pub fn parse_progress_line(line: &str) -> Option<DownloadProgress> {
+    let mut fields = line.strip_prefix(PROGRESS_PREFIX)?.split('|').map(str::trim);
+    let percent = fields.next()?.trim_end_matches('%').parse::<f32>().ok()?;
+
+    // yt-dlp prints placeholders such as "Unknown B/s" or "NA" before it has an estimate
+    let known = |field: Option<&str>| field
+        .filter(|field| !field.is_empty() && !field.starts_with("Unknown") && *field != "NA")
+        .map(|field| field.to_string());
+
+    Some(DownloadProgress {
+        percent: percent.clamp(0f32, 100f32),
+        speed: known(fields.next()),
+        eta: known(fields.next())
+    })
+}
 
-    let songs = video_titles[0].clone();
-    let urls = songs.find_elements(By::ClassName("yt-simple-endpoint")).unwrap();
-    let mut url_list: Vec<String> = Vec::<String>::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for url in urls {
-        let addr = url.get_property("href").unwrap().unwrap();
-        
-        if match addr.chars().nth(26) {
-            Some(c) => c == 'w',
-            None => false
-        } {
-            url_list.push(addr.split("watch?v=").nth(1).unwrap().to_string())
-        }
+    fn progress(percent: f32, speed: Option<&str>, eta: Option<&str>) -> Option<DownloadProgress> {
+        Some(DownloadProgress { percent, speed: speed.map(str::to_string), eta: eta.map(str::to_string) })
     }
 
-    let mut lines = songs.text().unwrap().lines().skip(1).map(|x| x.to_string()).collect::<Vec<String>>();
-    let mut options: Vec<Song> = Vec::<Song>::new();
-
-    while lines.len() >= 7 && url_list.len() > 0 {
-        let song = lines.remove(0);
-        let mut artist = lines.remove(0);
-        loop {
-            let item = lines.remove(0);
-            if item != " • " {
-                artist += item.as_str();
-            } else {
-                break;
-            }
-        }
-        let album = lines.remove(0);
-        lines.remove(0);
-        let timestr = lines.remove(0);
-        println!("TIMESTR: {timestr}");
-        let time = match timestr.contains(':') {
-            true => timestr.split(':').map(|x| x.parse::<usize>().unwrap_or(0)).collect::<Vec<usize>>(),
-            false => { vec![0, 0] }
-        };
-        let duration = time[0] * 60 + time[1];
-        if duration != 0 { let _plays = lines.remove(0); }
-
-        let id = url_list.remove(0);
-
-        if duration == 0 {
-            continue;
-        }
-
-        let path = directory.join(PathBuf::from(format!("{}.mp3", id)));
-
-        let downloaded: Option<PathBuf> = match path.exists() {
-            true => Some(path),
-            false => None
-        };
+    #[test]
+    fn parses_a_progress_line() {
+        assert_eq!(parse_progress_line("[resonate]  45.3%|  1.20MiB/s|00:12"), progress(45.3, Some("1.20MiB/s"), Some("00:12")));
+    }
 
-        options.push(Song::new(0, song, artist, album, id, duration, downloaded));
+    #[test]
+    fn unknown_speed_and_eta_are_left_out() {
+        // Printed before yt-dlp has an estimate
+        assert_eq!(parse_progress_line("[resonate]   0.0%|Unknown B/s|Unknown"), progress(0.0, None, None));
+        assert_eq!(parse_progress_line("[resonate]   0.1%|NA|NA"), progress(0.1, None, None));
+        // Printed once the download has finished
+        assert_eq!(parse_progress_line("[resonate] 100.0%|  3.05MiB/s|NA"), progress(100.0, Some("3.05MiB/s"), None));
+        assert_eq!(parse_progress_line("[resonate] 100%||"), progress(100.0, None, None));
     }
 
-    driver.quit().unwrap();
-    Ok(options)
+    #[test]
+    fn ignores_other_output() {
+        assert_eq!(parse_progress_line("[download] Destination: abc.webm"), None);
+        assert_eq!(parse_progress_line("[resonate] N/A%|NA|NA"), None);
+        assert_eq!(parse_progress_line(""), None);
+    }
 }
@@ -0,0 +1,194 @@
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use rodio::Source;
+use rodio::source::SeekError;
+
+/// A second-order IIR filter in direct form I.
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2]
+}
+
+impl Biquad {
+    /// Takes coefficients already divided by a0, so `a[0]` is ignored.
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self { b, a, x: [0f64; 2], y: [0f64; 2] }
+    }
+
+    /// Boosts or cuts by `gain` dB around `frequency`, as in the Audio EQ Cookbook.
+    pub fn peaking(sample_rate: u32, frequency: f32, gain: f32, q: f32) -> Self {
+        let amplitude = 10f64.powf(gain as f64 / 40f64);
+        let w0 = 2f64 * PI * frequency as f64 / sample_rate as f64;
+        let alpha = w0.sin() / (2f64 * q as f64);
+        let cos = w0.cos();
+        let a0 = 1f64 + alpha / amplitude;
+        Self::new(
+            [(1f64 + alpha * amplitude) / a0, -2f64 * cos / a0, (1f64 - alpha * amplitude) / a0],
+            [1f64, -2f64 * cos / a0, (1f64 - alpha / amplitude) / a0]
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1] - self.a[1] * self.y[0] - self.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Processes audio one frame at a time, with a sample for each channel. The last frame of a
+/// source may be cut short.
+pub trait Effect: Send {
+    fn process(&mut self, frame: &mut [f32]);
+}
+
+/// Effects applied one after another, in the order they were pushed.
+#[derive(Default)]
+pub struct DspChain {
+    effects: Vec<Box<dyn Effect>>
+}
+
+impl DspChain {
+    pub fn push(&mut self, effect: impl Effect + 'static) {
+        self.effects.push(Box::new(effect));
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        self.effects.iter_mut().for_each(|effect| effect.process(frame));
+    }
+}
+
+/// Scales every sample by a fixed factor.
+pub struct Gain(pub f32);
+
+impl Effect for Gain {
+    fn process(&mut self, frame: &mut [f32]) {
+        frame.iter_mut().for_each(|sample| *sample *= self.0);
+    }
+}
+
+/// How often, in frames, a `DspSource` asks whether its chain should be rebuilt.
+const REFRESH_INTERVAL: usize = 2048;
+
+/// Runs a source through a `DspChain`. Every so often `refresh` is asked for a new chain, so that
+/// changes to the settings behind the chain are heard while the source plays.
+pub struct DspSource<S: Source<Item = f32>> {
+    source: S,
+    chain: DspChain,
+    refresh: Box<dyn FnMut() -> Option<DspChain> + Send>,
+    frame: Vec<f32>,
+    position: usize,
+    until_refresh: usize
+}
+
+impl<S: Source<Item = f32>> DspSource<S> {
+    pub fn new(source: S, chain: DspChain, refresh: impl FnMut() -> Option<DspChain> + Send + 'static) -> Self {
+        Self { source, chain, refresh: Box::new(refresh), frame: Vec::new(), position: 0, until_refresh: REFRESH_INTERVAL }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for DspSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.frame.len() {
+            self.until_refresh -= 1;
+            if self.until_refresh == 0 {
+                self.until_refresh = REFRESH_INTERVAL;
+                if let Some(chain) = (self.refresh)() { self.chain = chain; }
+            }
+
+            let channels = self.source.channels() as usize;
+            self.frame.clear();
+            self.frame.extend(self.source.by_ref().take(channels));
+            if self.frame.is_empty() { return None }
+            self.chain.process(&mut self.frame);
+            self.position = 0;
+        }
+
+        self.position += 1;
+        Some(self.frame[self.position - 1])
+    }
+}
+
+impl<S: Source<Item = f32>> Source for DspSource<S> {
+    fn current_frame_len(&self) -> Option<usize> { self.source.current_frame_len() }
+    fn channels(&self) -> u16 { self.source.channels() }
+    fn sample_rate(&self) -> u32 { self.source.sample_rate() }
+    fn total_duration(&self) -> Option<Duration> { self.source.total_duration() }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(position)?;
+        // Whatever is left of the frame came from before the seek
+        self.position = self.frame.len();
+        Ok(())
+    }
+}
+
+/// Synthetic signals shared by the tests of the audio pipeline.
+#[cfg(test)]
+pub mod test_signal {
+    pub const SAMPLE_RATE: u32 = 44_100;
+
+    /// A stereo sine at half of full scale, `frames` samples per channel long.
+    pub fn sine(frequency: f32, frames: usize) -> Vec<f32> {
+        (0..frames).flat_map(|n| {
+            let sample = (2f32 * std::f32::consts::PI * frequency * n as f32 / SAMPLE_RATE as f32).sin() * 0.5;
+            [sample, sample]
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::test_signal::{sine, SAMPLE_RATE};
+    use super::*;
+
+    fn run(chain: DspChain, input: Vec<f32>, refresh: impl FnMut() -> Option<DspChain> + Send + 'static) -> Vec<f32> {
+        DspSource::new(SamplesBuffer::new(2, SAMPLE_RATE, input), chain, refresh).collect()
+    }
+
+    #[test]
+    fn gain_scales_the_signal() {
+        let input = sine(440f32, 1000);
+        let mut chain = DspChain::default();
+        chain.push(Gain(0.25));
+        let output = run(chain, input.clone(), || None);
+        assert_eq!(output, input.iter().map(|sample| sample * 0.25).collect::<Vec<f32>>());
+    }
+
+    #[test]
+    fn effects_apply_in_order() {
+        struct Offset(f32);
+        impl Effect for Offset {
+            fn process(&mut self, frame: &mut [f32]) { frame.iter_mut().for_each(|sample| *sample += self.0); }
+        }
+
+        let mut chain = DspChain::default();
+        chain.push(Offset(1f32));
+        chain.push(Gain(2f32));
+        assert_eq!(run(chain, vec![0f32, 0.5], || None), vec![2f32, 3f32]);
+    }
+
+    #[test]
+    fn refresh_replaces_the_chain() {
+        let input = sine(440f32, 3000);
+        let mut refreshed = false;
+        let output = run(DspChain::default(), input.clone(), move || {
+            let mut chain = DspChain::default();
+            chain.push(Gain(0f32));
+            (!std::mem::replace(&mut refreshed, true)).then_some(chain)
+        });
+
+        let switch = (REFRESH_INTERVAL - 1) * 2;
+        assert_eq!(output.len(), input.len());
+        assert_eq!(output[..switch], input[..switch]);
+        assert!(output[switch..].iter().all(|sample| *sample == 0f32));
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+
+use crate::dsp::{Biquad, Effect};
+
+pub const BANDS: usize = 10;
+/// The centre of each band in Hz, an octave apart.
+pub const BAND_FREQUENCIES: [f32; BANDS] = [31.25, 62.5, 125f32, 250f32, 500f32, 1000f32, 2000f32, 4000f32, 8000f32, 16000f32];
+/// The most a band can be boosted or cut by, in dB.
+pub const MAX_BAND_GAIN: f32 = 12f32;
+// About an octave wide, so that neighbouring bands meet without leaving a dip between them
+const BAND_Q: f32 = 1.41;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqPreset {
+    pub name: String,
+    // dB for each band in `BAND_FREQUENCIES`
+    pub gains: [f32; BANDS],
+    // Built-in presets are not stored and cannot be overwritten or deleted
+    pub built_in: bool
+}
+
+impl EqPreset {
+    pub fn new(name: String, gains: [f32; BANDS]) -> Self {
+        Self { name, gains, built_in: false }
+    }
+
+    pub fn built_in() -> Vec<Self> {
+        [
+            ("Flat", [0f32; BANDS]),
+            ("Bass Boost", [6f32, 5f32, 4f32, 2f32, 0f32, 0f32, 0f32, 0f32, 0f32, 0f32]),
+            ("Treble Boost", [0f32, 0f32, 0f32, 0f32, 0f32, 0f32, 2f32, 4f32, 5f32, 6f32]),
+            ("Vocal", [-2f32, -2f32, -1f32, 1f32, 3f32, 4f32, 3f32, 1f32, 0f32, -1f32]),
+            ("Rock", [4f32, 3f32, 2f32, 0f32, -1f32, -1f32, 1f32, 2f32, 3f32, 4f32]),
+            ("Electronic", [5f32, 4f32, 1f32, 0f32, -2f32, 1f32, 0f32, 1f32, 4f32, 5f32]),
+            ("Classical", [3f32, 2f32, 1f32, 0f32, 0f32, 0f32, -1f32, -1f32, 1f32, 2f32])
+        ].into_iter().map(|(name, gains)| Self { name: name.to_string(), gains, built_in: true }).collect()
+    }
+}
+
+/// Gains as stored in the database, e.g. "3,2,0,0,0,0,0,0,-1,-2".
+pub fn format_gains(gains: &[f32; BANDS]) -> String {
+    gains.iter().map(|gain| gain.to_string()).collect::<Vec<String>>().join(",")
+}
+
+pub fn parse_gains(gains: &str) -> Option<[f32; BANDS]> {
+    let gains = gains.split(',').map(|gain| gain.trim().parse::<f32>().ok()).collect::<Option<Vec<f32>>>()?;
+    gains.try_into().ok()
+}
+
+/// The equalizer as set up by the user, shared with the audio player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqualizerSettings {
+    // False to bypass the equalizer altogether, overrides included
+    pub enabled: bool,
+    pub gains: [f32; BANDS],
+    // Built-in presets first, then the user's own
+    pub presets: Vec<EqPreset>
+}
+
+impl Default for EqualizerSettings {
+    fn default() -> Self {
+        Self { enabled: true, gains: [0f32; BANDS], presets: EqPreset::built_in() }
+    }
+}
+
+impl EqualizerSettings {
+    pub fn preset(&self, name: &str) -> Option<&EqPreset> {
+        self.presets.iter().find(|preset| preset.name == name)
+    }
+
+    /// The preset the current gains were set from, if they have not been changed since.
+    pub fn current_preset(&self) -> Option<&EqPreset> {
+        self.presets.iter().find(|preset| preset.gains == self.gains)
+    }
+
+    /// The gains a song plays with, from the preset it or its playlist overrides the equalizer
+    /// with if that preset still exists. None if the equalizer is bypassed.
+    pub fn gains_for(&self, preset: Option<&str>) -> Option<[f32; BANDS]> {
+        if !self.enabled { return None }
+        Some(preset.and_then(|name| self.preset(name)).map(|preset| preset.gains).unwrap_or(self.gains))
+    }
+}
+
+/// A preset to override the equalizer with, as offered in a pick list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PresetChoice {
+    // No override, so the equalizer's own gains are used
+    Default,
+    Preset(String)
+}
+
+impl PresetChoice {
+    pub fn options(settings: &EqualizerSettings) -> Vec<Self> {
+        std::iter::once(Self::Default).chain(settings.presets.iter().map(|preset| Self::Preset(preset.name.clone()))).collect()
+    }
+
+    pub fn from_override(preset: Option<String>) -> Self {
+        preset.map(Self::Preset).unwrap_or(Self::Default)
+    }
+
+    pub fn into_override(self) -> Option<String> {
+        match self {
+            Self::Default => None,
+            Self::Preset(name) => Some(name)
+        }
+    }
+}
+
+impl fmt::Display for PresetChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "Default"),
+            Self::Preset(name) => write!(f, "{name}")
+        }
+    }
+}
+
+/// A graphic equalizer made of one peaking filter per band and channel. Bands left at 0 dB are
+/// skipped, and the signal is turned down by the largest boost first so that boosting never clips.
+pub struct Equalizer {
+    filters: Vec<Vec<Biquad>>,
+    preamp: f64
+}
+
+impl Equalizer {
+    pub fn new(gains: [f32; BANDS], channels: u16, sample_rate: u32) -> Self {
+        let filters: Vec<Biquad> = BAND_FREQUENCIES.iter().zip(gains)
+            .filter(|(_, gain)| *gain != 0f32)
+            .map(|(frequency, gain)| Biquad::peaking(sample_rate, *frequency, gain, BAND_Q))
+            .collect();
+        let boost = gains.into_iter().fold(0f32, f32::max);
+        Self { filters: vec![filters; channels as usize], preamp: 10f64.powf(-boost as f64 / 20f64) }
+    }
+}
+
+impl Effect for Equalizer {
+    fn process(&mut self, frame: &mut [f32]) {
+        for (sample, filters) in frame.iter_mut().zip(self.filters.iter_mut()) {
+            *sample = filters.iter_mut().fold(*sample as f64 * self.preamp, |value, filter| filter.process(value)) as f32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use crate::dsp::test_signal::{sine, SAMPLE_RATE};
+    use crate::dsp::{DspChain, DspSource};
+
+    use super::*;
+
+    fn equalize(gains: [f32; BANDS], input: Vec<f32>) -> Vec<f32> {
+        let mut chain = DspChain::default();
+        chain.push(Equalizer::new(gains, 2, SAMPLE_RATE));
+        DspSource::new(SamplesBuffer::new(2, SAMPLE_RATE, input), chain, || None).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// How much the equalizer changes the level of a sine, in dB, once its filters have settled.
+    fn level_change(gains: [f32; BANDS], frequency: f32) -> f32 {
+        let input = sine(frequency, SAMPLE_RATE as usize);
+        let output = equalize(gains, input.clone());
+        let settled = input.len() / 4;
+        20f32 * (rms(&output[settled..]) / rms(&input[settled..])).log10()
+    }
+
+    #[test]
+    fn boosting_a_band_raises_its_centre_frequency() {
+        let mut gains = [0f32; BANDS];
+        gains[5] = 6f32;
+
+        // The preamp turns everything down by the boost, so a distant frequency should only be
+        // lowered by that and the band's centre should end up the boost above it
+        let distant = level_change(gains, BAND_FREQUENCIES[1]);
+        let centre = level_change(gains, BAND_FREQUENCIES[5]);
+        assert!((distant + 6f32).abs() < 0.2, "distant frequency changed by {distant} dB");
+        assert!((centre - distant - 6f32).abs() < 0.5, "centre frequency raised by {} dB", centre - distant);
+    }
+
+    #[test]
+    fn cutting_a_band_lowers_its_centre_frequency() {
+        let mut gains = [0f32; BANDS];
+        gains[2] = -9f32;
+
+        let distant = level_change(gains, BAND_FREQUENCIES[8]);
+        let centre = level_change(gains, BAND_FREQUENCIES[2]);
+        assert!(distant.abs() < 0.2, "distant frequency changed by {distant} dB");
+        assert!((centre + 9f32).abs() < 0.5, "centre frequency lowered by {centre} dB");
+    }
+
+    #[test]
+    fn flat_preset_passes_audio_through() {
+        let flat = EqPreset::built_in().into_iter().find(|preset| preset.name == "Flat").unwrap();
+        let input = sine(440f32, SAMPLE_RATE as usize);
+        assert_eq!(equalize(flat.gains, input.clone()), input);
+    }
+}
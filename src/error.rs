@@ -0,0 +1,79 @@
+use std::fmt;
+
+use rodio::decoder::DecoderError;
+use rodio::source::SeekError;
+use rodio::{PlayError, StreamError};
+
+/// Crate-wide error type. Variants carry a description rather than the source error so that
+/// they can travel inside `Message`, which needs to be `Clone` and `PartialEq`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ResonateError {
+    Database(String),
+    SchemaTooNew(usize, usize),
+    Decode(String),
+    Audio(String),
+    ProcessSpawn(String),
+    Scrape(String),
+    Io(String),
+    Tag(String),
+    InvalidRule(String),
+    Cancelled
+}
+
+pub type ResonateResult<T> = Result<T, ResonateError>;
+
+impl fmt::Display for ResonateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Database(e) => write!(f, "Database error: {e}"),
+            Self::SchemaTooNew(found, supported) => write!(f,
+                "data.db is at schema version {found} but this build of resonate only understands up to version {supported}. Please update resonate."),
+            Self::Decode(e) => write!(f, "Could not decode file: {e}"),
+            Self::Audio(e) => write!(f, "Audio output error: {e}"),
+            Self::ProcessSpawn(e) => write!(f, "Failed to run external program: {e}"),
+            Self::Scrape(e) => write!(f, "Search failed: {e}"),
+            Self::Io(e) => write!(f, "IO error: {e}"),
+            Self::Tag(e) => write!(f, "Could not write tags: {e}"),
+            Self::InvalidRule(e) => write!(f, "Invalid smart playlist rule: {e}"),
+            Self::Cancelled => write!(f, "Cancelled")
+        }
+    }
+}
+
+impl std::error::Error for ResonateError {}
+
+impl From<rusqlite::Error> for ResonateError {
+    fn from(e: rusqlite::Error) -> Self { Self::Database(e.to_string()) }
+}
+
+impl From<std::io::Error> for ResonateError {
+    fn from(e: std::io::Error) -> Self { Self::Io(e.to_string()) }
+}
+
+impl From<DecoderError> for ResonateError {
+    fn from(e: DecoderError) -> Self { Self::Decode(e.to_string()) }
+}
+
+impl From<image::ImageError> for ResonateError {
+    fn from(e: image::ImageError) -> Self { Self::Decode(e.to_string()) }
+}
+
+impl From<StreamError> for ResonateError {
+    fn from(e: StreamError) -> Self { Self::Audio(e.to_string()) }
+}
+
+impl From<PlayError> for ResonateError {
+    fn from(e: PlayError) -> Self { Self::Audio(e.to_string()) }
+}
+
+impl From<SeekError> for ResonateError {
+    fn from(e: SeekError) -> Self { Self::Audio(e.to_string()) }
+}
+
+impl From<symphonia::core::errors::Error> for ResonateError {
+    fn from(e: symphonia::core::errors::Error) -> Self { Self::Decode(e.to_string()) }
+}
+
+impl From<id3::Error> for ResonateError {
+    fn from(e: id3::Error) -> Self { Self::Tag(e.to_string()) }
+}
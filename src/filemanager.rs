@@ -1,22 +1,32 @@
 use std::{fs::create_dir_all, process::Command};
-use std::collections::HashSet;
 use directories::ProjectDirs;
-use rusqlite::{params, Connection};
-use std::path::PathBuf;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use image::DynamicImage;
+use std::time::Duration;
 
-use crate::{application::Message, music::{Playlist, Song}};
+use crate::audio::{Play, RepeatMode, Session};
+use crate::{application::Message, music::{Playlist, Song, LIKED_PLAYLIST_ID}};
+use crate::error::{ResonateError, ResonateResult};
+use crate::library::{LibraryEntry, PreferenceFilter};
+use crate::loudness::{album_gain, ReplayGain};
+use crate::equalizer::{format_gains, parse_gains, EqPreset};
+use crate::smart_playlist::{Rule, RuleField, RuleOperator, SmartPlaylist, SmartSort};
+use crate::statistics::{RecentPlay, Statistics, LISTENING_DAYS, STATISTICS_LIMIT};
+use crate::tagger::embed_cover;
 
 
 /// Creates and then returns the path to a suitable location for application data to be stored.
 /// If the path already exists, just return the path.
-pub fn get_application_directory() -> Result<PathBuf, ()> {
+pub fn get_application_directory() -> ResonateResult<PathBuf> {
     let project_dir = match ProjectDirs::from("com", "hchap1", "resonate") {
         Some(project_dir) => project_dir,
-        None => return Err(())
+        None => return Err(ResonateError::Io(String::from("Could not determine a home directory.")))
     };
 
     let path = project_dir.data_dir().to_path_buf();
-    let _ = create_dir_all(&path);
+    create_dir_all(&path)?;
     Ok(path)
 }
 
@@ -25,217 +35,1173 @@ pub struct Database {
     directory: PathBuf
 }
 
+/// Ordered list of schema migrations. The database records how many of these have been applied in
+/// `schema_version`, so new steps must only ever be appended to the end of this list.
+const MIGRATIONS: &[&str] = &[
+    // 1: Initial schema
+    "
+    CREATE TABLE IF NOT EXISTS Songs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        ytid TEXT NOT NULL,
+        name TEXT NOT NULL,
+        artist TEXT NOT NULL,
+        album TEXT NOT NULL,
+        duration_s INT NOT NULL,
+        downloaded INT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS Playlists (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS Contents (
+        playlist_id INTEGER,
+        song_id INTEGER,
+        PRIMARY KEY (playlist_id, song_id),
+        FOREIGN KEY (playlist_id) REFERENCES Playlists(id) ON DELETE CASCADE,
+        FOREIGN KEY (song_id) REFERENCES Songs(id) ON DELETE CASCADE
+    );
+    ",
+
+    // 2: Persistent song order within playlists, seeded from insertion order
+    "
+    ALTER TABLE Contents ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+    UPDATE Contents SET position = (
+        SELECT COUNT(*) FROM Contents AS earlier
+        WHERE earlier.playlist_id = Contents.playlist_id AND earlier.rowid < Contents.rowid
+    );
+    ",
+
+    // 3: Playback session restored on startup
+    "
+    CREATE TABLE IF NOT EXISTS Session (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        current_song_id INTEGER,
+        position_s REAL NOT NULL,
+        volume REAL NOT NULL,
+        speed REAL NOT NULL,
+        repeat INT NOT NULL,
+        shuffle INT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS SessionQueue (
+        position INTEGER PRIMARY KEY,
+        song_id INTEGER NOT NULL
+    );
+    ",
+
+    // 4: User preferences as key/value pairs
+    "
+    CREATE TABLE IF NOT EXISTS Settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    ",
+
+    // 5: Where imported songs came from, so watched folders can keep them in sync
+    "
+    ALTER TABLE Songs ADD COLUMN source_path TEXT;
+    ALTER TABLE Songs ADD COLUMN missing INT NOT NULL DEFAULT 0;
+    ",
+
+    // 6: When songs were added and how often they have been played, for the library view
+    "
+    ALTER TABLE Songs ADD COLUMN added_at TEXT;
+    ALTER TABLE Songs ADD COLUMN play_count INT NOT NULL DEFAULT 0;
+    ",
+
+    // 7: Artists and albums as their own rows. Existing artist strings are split on ", " and " & ",
+    // since songs scraped before the InnerTube client stored their artists as e.g. "A, B & C". An
+    // album belongs to the first artist of its songs, and its artwork is the thumbnail of one of
+    // its songs.
+    "
+    CREATE TABLE IF NOT EXISTS Artists (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE
+    );
+
+    CREATE TABLE IF NOT EXISTS Albums (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL COLLATE NOCASE,
+        artist_id INTEGER,
+        year INTEGER,
+        artwork TEXT,
+        UNIQUE (name, artist_id),
+        FOREIGN KEY (artist_id) REFERENCES Artists(id) ON DELETE SET NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS SongArtists (
+        song_id INTEGER,
+        artist_id INTEGER,
+        position INTEGER NOT NULL,
+        PRIMARY KEY (song_id, artist_id),
+        FOREIGN KEY (song_id) REFERENCES Songs(id) ON DELETE CASCADE,
+        FOREIGN KEY (artist_id) REFERENCES Artists(id) ON DELETE CASCADE
+    );
+
+    ALTER TABLE Songs ADD COLUMN album_id INTEGER REFERENCES Albums(id);
+    ALTER TABLE Songs ADD COLUMN track_number INTEGER;
+
+    CREATE TEMP TABLE SplitArtists AS
+    WITH RECURSIVE split(song_id, position, name, rest) AS (
+        SELECT id, -1, '', replace(artist, ' & ', ', ') || ', ' FROM Songs
+        UNION ALL
+        SELECT song_id, position + 1, trim(substr(rest, 1, instr(rest, ', ') - 1)), substr(rest, instr(rest, ', ') + 2)
+        FROM split WHERE rest != ''
+    )
+    SELECT song_id, position, name FROM split WHERE name != '';
+
+    INSERT OR IGNORE INTO Artists (name) SELECT name FROM SplitArtists ORDER BY song_id, position;
+    INSERT OR IGNORE INTO SongArtists (song_id, artist_id, position)
+        SELECT song_id, Artists.id, position FROM SplitArtists JOIN Artists ON Artists.name = SplitArtists.name
+        ORDER BY song_id, position;
+    DROP TABLE SplitArtists;
+
+    INSERT OR IGNORE INTO Albums (name, artist_id, artwork)
+        SELECT trim(album) AS album_name, (SELECT artist_id FROM SongArtists WHERE song_id = Songs.id ORDER BY position LIMIT 1) AS first_artist, MIN(ytid)
+        FROM Songs WHERE trim(album) != '' GROUP BY album_name COLLATE NOCASE, first_artist;
+
+    UPDATE Songs SET album_id = (
+        SELECT Albums.id FROM Albums WHERE Albums.name = trim(Songs.album)
+        AND Albums.artist_id IS (SELECT artist_id FROM SongArtists WHERE song_id = Songs.id ORDER BY position LIMIT 1)
+    ) WHERE trim(album) != '';
+    ",
+
+    // 8: Full text indexes over songs and playlists, kept in sync by triggers. Diacritics are
+    // folded so that "beyonce" finds "Beyoncé".
+    "
+    CREATE VIRTUAL TABLE IF NOT EXISTS SongSearch USING fts5(
+        name, artist, album,
+        content = 'Songs', content_rowid = 'id', tokenize = 'unicode61 remove_diacritics 2'
+    );
+    INSERT INTO SongSearch(SongSearch) VALUES('rebuild');
+
+    CREATE TRIGGER IF NOT EXISTS SongSearchInsert AFTER INSERT ON Songs BEGIN
+        INSERT INTO SongSearch(rowid, name, artist, album) VALUES(new.id, new.name, new.artist, new.album);
+    END;
+    CREATE TRIGGER IF NOT EXISTS SongSearchDelete AFTER DELETE ON Songs BEGIN
+        INSERT INTO SongSearch(SongSearch, rowid, name, artist, album) VALUES('delete', old.id, old.name, old.artist, old.album);
+    END;
+    CREATE TRIGGER IF NOT EXISTS SongSearchUpdate AFTER UPDATE OF name, artist, album ON Songs BEGIN
+        INSERT INTO SongSearch(SongSearch, rowid, name, artist, album) VALUES('delete', old.id, old.name, old.artist, old.album);
+        INSERT INTO SongSearch(rowid, name, artist, album) VALUES(new.id, new.name, new.artist, new.album);
+    END;
+
+    CREATE VIRTUAL TABLE IF NOT EXISTS PlaylistSearch USING fts5(
+        name,
+        content = 'Playlists', content_rowid = 'id', tokenize = 'unicode61 remove_diacritics 2'
+    );
+    INSERT INTO PlaylistSearch(PlaylistSearch) VALUES('rebuild');
+
+    CREATE TRIGGER IF NOT EXISTS PlaylistSearchInsert AFTER INSERT ON Playlists BEGIN
+        INSERT INTO PlaylistSearch(rowid, name) VALUES(new.id, new.name);
+    END;
+    CREATE TRIGGER IF NOT EXISTS PlaylistSearchDelete AFTER DELETE ON Playlists BEGIN
+        INSERT INTO PlaylistSearch(PlaylistSearch, rowid, name) VALUES('delete', old.id, old.name);
+    END;
+    CREATE TRIGGER IF NOT EXISTS PlaylistSearchUpdate AFTER UPDATE OF name ON Playlists BEGIN
+        INSERT INTO PlaylistSearch(PlaylistSearch, rowid, name) VALUES('delete', old.id, old.name);
+        INSERT INTO PlaylistSearch(rowid, name) VALUES(new.id, new.name);
+    END;
+    ",
+
+    // 9: Every counted play. Songs.play_count stays as a running total for the library view.
+    "
+    CREATE TABLE IF NOT EXISTS Plays (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        song_id INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        listened_s REAL NOT NULL,
+        skipped INT NOT NULL,
+        FOREIGN KEY (song_id) REFERENCES Songs(id) ON DELETE CASCADE
+    );
+
+    CREATE INDEX IF NOT EXISTS PlaysByTime ON Plays(started_at);
+    ",
+
+    // 10: Smart playlists, whose songs are chosen by rules rather than listed in Contents
+    "
+    CREATE TABLE IF NOT EXISTS SmartPlaylists (
+        playlist_id INTEGER PRIMARY KEY,
+        match_all INT NOT NULL,
+        sort TEXT NOT NULL,
+        descending INT NOT NULL,
+        song_limit INTEGER,
+        FOREIGN KEY (playlist_id) REFERENCES Playlists(id) ON DELETE CASCADE
+    );
+
+    CREATE TABLE IF NOT EXISTS SmartRules (
+        playlist_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        field TEXT NOT NULL,
+        operator TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (playlist_id, position),
+        FOREIGN KEY (playlist_id) REFERENCES Playlists(id) ON DELETE CASCADE
+    );
+    ",
+
+    // 11: Star ratings and favourites
+    "
+    ALTER TABLE Songs ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE Songs ADD COLUMN favourite INT NOT NULL DEFAULT 0;
+    ",
+
+    // 12: ReplayGain, NULL until a song has been analysed
+    "
+    ALTER TABLE Songs ADD COLUMN track_gain REAL;
+    ALTER TABLE Songs ADD COLUMN track_peak REAL;
+    ALTER TABLE Songs ADD COLUMN album_gain REAL;
+    ALTER TABLE Songs ADD COLUMN album_peak REAL;
+    ",
+
+    // 13: Equalizer presets, and the presets songs and playlists override the equalizer with
+    "
+    CREATE TABLE IF NOT EXISTS EqPresets (
+        name TEXT PRIMARY KEY,
+        gains TEXT NOT NULL
+    );
+    ALTER TABLE Songs ADD COLUMN eq_preset TEXT;
+    ALTER TABLE Playlists ADD COLUMN eq_preset TEXT;
+    ",
+
+    // 14: Pitch shift in semitones, saved with the rest of the session
+    "
+    ALTER TABLE Session ADD COLUMN pitch REAL NOT NULL DEFAULT 0;
+    ",
+
+    // 15: Songs whose loudness could not be analysed, so that they are not decoded on every scan
+    "
+    ALTER TABLE Songs ADD COLUMN gain_failed INT NOT NULL DEFAULT 0;
+    "
+];
+
+/// Fields that can be searched on their own with `field:value`, and the column each one maps to.
+const SONG_SEARCH_FIELDS: &[(&str, &str)] = &[("title", "name"), ("name", "name"), ("artist", "artist"), ("album", "album")];
+
+/// Turns what the user typed into an FTS5 query. Every word has to match the start of a word in
+/// any order, `field:value` and `field:"several words"` only match within that field, and
+/// everything is quoted so that FTS5 syntax in the input is never interpreted. Returns None if
+/// there is nothing to search for.
+fn match_expression(input: &str, fields: &[(&str, &str)]) -> Option<String> {
+    let quote = |term: &str| format!("\"{}\"*", term.replace('"', "\"\""));
+    let mut terms: Vec<String> = Vec::new();
+    let mut rest = input.trim();
+
+    while !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..end];
+
+        let field = word.split_once(':').and_then(|(field, _)| fields.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field))
+            .map(|(name, column)| (name.len() + 1, *column)));
+
+        match field {
+            Some((prefix, column)) => {
+                let value = &rest[prefix..];
+                // Quoted values may contain spaces
+                let (value, remaining) = match value.strip_prefix('"') {
+                    Some(quoted) => match quoted.find('"') {
+                        Some(close) => (&quoted[..close], &quoted[close + 1..]),
+                        None => (quoted, "")
+                    },
+                    None => {
+                        let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                        (&value[..end], &value[end..])
+                    }
+                };
+                if !value.trim().is_empty() { terms.push(format!("{column} : {}", quote(value.trim()))); }
+                rest = remaining.trim_start();
+            }
+            None => {
+                let term = word.trim_matches('"');
+                if !term.is_empty() { terms.push(quote(term)); }
+                rest = rest[end..].trim_start();
+            }
+        }
+    }
+
+    if terms.is_empty() { None } else { Some(terms.join(" AND ")) }
+}
+
+/// The artists in a song's artist string, in order. Songs are stored with their artists joined by
+/// ", ", but ones scraped before the InnerTube client may also use " & " before the last artist.
+pub fn split_artists(artist: &str) -> impl Iterator<Item = &str> {
+    artist.split(", ").flat_map(|part| part.split(" & ")).map(str::trim).filter(|name| !name.is_empty())
+}
+
 impl Database {
-    pub fn new(directory: PathBuf) -> Self {
-        let connection: Connection = match Connection::open(directory.join("data.db")) {
-            Ok(connection) => connection,
-            Err(_) => panic!("Could not create or access database file!")
+    pub fn new(directory: PathBuf) -> ResonateResult<Self> {
+        let mut connection: Connection = Connection::open(directory.join("data.db"))?;
+
+        // Wait for other resonate instances to release the database rather than failing immediately
+        connection.busy_timeout(Duration::from_secs(5))?;
+        Self::migrate(&mut connection)?;
+        Ok(Self { connection, directory })
+    }
+
+    /// A fully migrated database that only lives in memory.
+    #[cfg(test)]
+    pub fn in_memory() -> ResonateResult<Self> {
+        let mut connection = Connection::open_in_memory()?;
+        Self::migrate(&mut connection)?;
+        Ok(Self { connection, directory: std::env::temp_dir() })
+    }
+
+    /// Brings the schema up to date by applying every migration newer than the stored version.
+    /// Each step runs in its own transaction alongside the version bump, so a failed step leaves
+    /// the database at the last good version.
+    fn migrate(connection: &mut Connection) -> ResonateResult<()> {
+        connection.execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);", [])?;
+
+        let current: usize = connection.query_row("SELECT IFNULL(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?;
+
+        if current > MIGRATIONS.len() {
+            return Err(ResonateError::SchemaTooNew(current, MIGRATIONS.len()));
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let version = index + 1;
+            println!("[DATABASE] Migrating schema to version {version}");
+
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.execute_batch(&format!("DELETE FROM schema_version; INSERT INTO schema_version VALUES({version});"))?;
+            transaction.commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds a song from a full `Songs` row.
+    fn song_from_row(&self, row: &Row) -> rusqlite::Result<Song> {
+        let id: String = row.get(1)?;
+        let file = match row.get::<_, usize>(6)? {
+            0 => None,
+            _ => Some(self.directory.join(PathBuf::from(format!("{id}.mp3"))))
+        };
+        let mut song = Song::new(
+            row.get::<_, usize>(0)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            id,
+            row.get::<_, usize>(5)?,
+            file
+        );
+        song.rating = row.get("rating")?;
+        song.favourite = row.get("favourite")?;
+        song.gain = match row.get::<_, Option<f32>>("track_gain")? {
+            Some(track_gain) => Some(ReplayGain {
+                track_gain,
+                track_peak: row.get("track_peak")?,
+                album_gain: row.get("album_gain")?,
+                album_peak: row.get("album_peak")?
+            }),
+            None => None
         };
+        song.equalizer = row.get("eq_preset")?;
+        Ok(song)
+    }
 
-        // Ensure tables exist
-        let _ = connection.execute("
-            CREATE TABLE IF NOT EXISTS Songs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                ytid TEXT NOT NULL,
-                name TEXT NOT NULL,
-                artist TEXT NOT NULL,
-                album TEXT NOT NULL,
-                duration_s INT NOT NULL,
-                downloaded INT NOT NULL
-            );
-        ",[]);
-
-        let _ = connection.execute("
-            CREATE TABLE IF NOT EXISTS Playlists (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT NOT NULL
-            );
-        ",[]);
-
-        let _ = connection.execute("
-            CREATE TABLE Contents (
-                playlist_id INTEGER,
-                song_id INTEGER,
-                PRIMARY KEY (playlist_id, song_id),
-                FOREIGN KEY (playlist_id) REFERENCES Playlists(id) ON DELETE CASCADE,
-                FOREIGN KEY (song_id) REFERENCES Songs(id) ON DELETE CASCADE
-            );
-        ",[]);
-
-        Self { connection, directory }
-    }
-
-    pub fn add_songs_to_cache(&self, songs: &mut Vec<Song>) {
-        songs.iter_mut().for_each(|mut song| self.add_song_to_cache(&mut song));
-    }
-
-    pub fn retrieve_all_songs(&self) -> Vec<Song> {
-        // ID, name, artist, album, duration, exists
-        let mut pattern = self.connection.prepare("SELECT * FROM Songs").unwrap();
-        pattern.query_map([], |row| {
-            let id = row.get(1).unwrap();
-            let file = match row.get(6).unwrap() {
-                0 => None,
-                _ => Some(self.directory.join(PathBuf::from(format!("{id}.mp3"))))
-            };
-            Ok(Song::new(
-                row.get::<_, usize>(0).unwrap(),
-                row.get(2).unwrap(),
-                row.get(3).unwrap(),
-                row.get(4).unwrap(),
-                id,
-                row.get::<_, usize>(5).unwrap(),
-                file
-            ))
-        }).unwrap().map(|x| x.unwrap()).collect()
-    }
-
-    pub fn hash_all_songs(&self) -> HashSet<String> {
-        let mut hash: HashSet<String> = HashSet::<String>::new();
-
-        // ID, name, artist, album, duration, exists
-        let mut pattern = self.connection.prepare("SELECT ytid FROM Songs").unwrap();
-        pattern.query_map([], |row| {
-            let id = row.get::<_, String>(0).unwrap();
-            Ok(id)
-        }).unwrap().for_each(|x| { hash.insert(x.unwrap()); });
-        hash
-    }
-
-    pub fn search_cached_song(&self, query: String) -> Vec<Song> {
-        let like_query = format!("%{query}%");
-        let mut pattern = self.connection.prepare("SELECT * FROM Songs WHERE name LIKE ? OR artist LIKE ? OR album LIKE ?").unwrap();
-        pattern.query_map(params![like_query, like_query, like_query], |row| {
-            Ok({
-                let sql_id = row.get::<_, usize>(0).unwrap();
-                let id = row.get::<_, String>(1).unwrap();
-                let name = row.get::<_, String>(2).unwrap();
-                let artist = row.get::<_, String>(3).unwrap();
-                let album = row.get::<_, String>(4).unwrap();
-                let duration_s = row.get::<_, usize>(5).unwrap();
-                let downloaded = match row.get::<_, usize>(6) {
-                    Ok(d) => if d == 0 { None } else { Some(self.directory.join(PathBuf::from(format!("{}.mp3", id)))) },
-                    Err(_) => None
-                };
-                Song::new(sql_id, name, artist, album, id, duration_s, downloaded)
+    pub fn retrieve_all_songs(&self) -> ResonateResult<Vec<Song>> {
+        let mut pattern = self.connection.prepare("SELECT * FROM Songs")?;
+        let songs = pattern.query_map([], |row| self.song_from_row(row))?.collect::<rusqlite::Result<Vec<Song>>>()?;
+        Ok(songs)
+    }
+
+    /// Every song along with its artists, album, when it was added and how often it has been played.
+    pub fn retrieve_library(&self) -> ResonateResult<Vec<LibraryEntry>> {
+        let mut artists: HashMap<usize, Vec<String>> = HashMap::new();
+        let mut pattern = self.connection.prepare("
+            SELECT song_id, Artists.name FROM SongArtists JOIN Artists ON Artists.id = SongArtists.artist_id
+            ORDER BY song_id, position
+        ")?;
+        for link in pattern.query_map([], |row| Ok((row.get::<_, usize>(0)?, row.get::<_, String>(1)?)))? {
+            let (song_id, name) = link?;
+            artists.entry(song_id).or_default().push(name);
+        }
+
+        let mut pattern = self.connection.prepare("SELECT Songs.*, Albums.year AS album_year FROM Songs LEFT JOIN Albums ON Albums.id = Songs.album_id")?;
+        let entries = pattern.query_map([], |row| {
+            let song = self.song_from_row(row)?;
+            Ok(LibraryEntry {
+                artists: artists.remove(&song.sql_id).unwrap_or_default(),
+                album_id: row.get("album_id")?,
+                year: row.get("album_year")?,
+                track: row.get("track_number")?,
+                added: row.get("added_at")?,
+                plays: row.get("play_count")?,
+                song
             })
-        }).unwrap().map(|x| x.unwrap()).collect::<Vec<Song>>()
+        })?.collect::<rusqlite::Result<Vec<LibraryEntry>>>()?;
+        Ok(entries)
+    }
+
+    pub fn record_play(&self, play: &Play) -> ResonateResult<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute("
+            INSERT INTO Plays (song_id, started_at, listened_s, skipped) VALUES(?1, ?2, ?3, ?4)
+        ", params![play.song.sql_id, play.started, play.listened, play.skipped])?;
+        transaction.execute("UPDATE Songs SET play_count = play_count + 1 WHERE id = ?", params![play.song.sql_id])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Rankings, recent plays and daily listening time from the play history.
+    pub fn retrieve_statistics(&self) -> ResonateResult<Statistics> {
+        let (total_plays, total_listened) = self.connection.query_row(
+            "SELECT COUNT(*), IFNULL(SUM(listened_s), 0) FROM Plays", [], |row| Ok((row.get(0)?, row.get(1)?))
+        )?;
+
+        let mut pattern = self.connection.prepare("
+            SELECT Songs.*, COUNT(*) AS plays FROM Plays JOIN Songs ON Songs.id = Plays.song_id
+            GROUP BY Songs.id ORDER BY plays DESC, MAX(Plays.started_at) DESC LIMIT ?
+        ")?;
+        let top_songs = pattern.query_map(params![STATISTICS_LIMIT], |row| Ok((self.song_from_row(row)?, row.get("plays")?)))?
+            .collect::<rusqlite::Result<Vec<(Song, usize)>>>()?;
+
+        // Songs with several artists count towards each of them
+        let mut pattern = self.connection.prepare("
+            SELECT Artists.name, COUNT(*) AS plays FROM Plays
+            JOIN SongArtists ON SongArtists.song_id = Plays.song_id
+            JOIN Artists ON Artists.id = SongArtists.artist_id
+            GROUP BY Artists.id ORDER BY plays DESC, Artists.name LIMIT ?
+        ")?;
+        let top_artists = pattern.query_map(params![STATISTICS_LIMIT], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, usize)>>>()?;
+
+        let mut pattern = self.connection.prepare("
+            SELECT Songs.*, datetime(Plays.started_at, 'unixepoch', 'localtime') AS played_at, Plays.skipped
+            FROM Plays JOIN Songs ON Songs.id = Plays.song_id ORDER BY Plays.started_at DESC LIMIT ?
+        ")?;
+        let recent = pattern.query_map(params![STATISTICS_LIMIT * 2], |row| Ok(RecentPlay {
+            song: self.song_from_row(row)?,
+            played_at: row.get("played_at")?,
+            skipped: row.get("skipped")?
+        }))?.collect::<rusqlite::Result<Vec<RecentPlay>>>()?;
+
+        let mut pattern = self.connection.prepare("
+            WITH RECURSIVE days(day, remaining) AS (
+                SELECT date('now', 'localtime'), ?1 - 1
+                UNION ALL
+                SELECT date(day, '-1 day'), remaining - 1 FROM days WHERE remaining > 0
+            )
+            SELECT days.day, IFNULL(SUM(Plays.listened_s), 0) FROM days
+            LEFT JOIN Plays ON date(Plays.started_at, 'unixepoch', 'localtime') = days.day
+            GROUP BY days.day ORDER BY days.day
+        ")?;
+        let daily = pattern.query_map(params![LISTENING_DAYS], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, f32)>>>()?;
+
+        Ok(Statistics { total_plays, total_listened, top_songs, top_artists, recent, daily })
+    }
+
+    /// Searches cached songs, best matches first. Matches in the title count for more than matches
+    /// in the artist, which count for more than matches in the album. `rating:N` and `is:liked`
+    /// narrow the results down, and on their own list the matching songs best rated first.
+    pub fn search_cached_song(&self, query: String) -> ResonateResult<Vec<Song>> {
+        let (preference, query) = PreferenceFilter::parse(&query);
+        let songs = match match_expression(&query, SONG_SEARCH_FIELDS) {
+            Some(expression) => {
+                let mut pattern = self.connection.prepare("
+                    SELECT Songs.* FROM SongSearch JOIN Songs ON Songs.id = SongSearch.rowid
+                    WHERE SongSearch MATCH ?1 AND Songs.rating >= ?2 AND Songs.favourite >= ?3
+                    ORDER BY bm25(SongSearch, 10.0, 5.0, 2.0)
+                ")?;
+                let songs = pattern.query_map(params![expression, preference.min_rating, preference.liked], |row| self.song_from_row(row))?
+                    .collect::<rusqlite::Result<Vec<Song>>>()?;
+                songs
+            }
+            None if !preference.is_empty() => {
+                let mut pattern = self.connection.prepare("
+                    SELECT * FROM Songs WHERE rating >= ?1 AND favourite >= ?2 ORDER BY rating DESC, name COLLATE NOCASE
+                ")?;
+                let songs = pattern.query_map(params![preference.min_rating, preference.liked], |row| self.song_from_row(row))?
+                    .collect::<rusqlite::Result<Vec<Song>>>()?;
+                songs
+            }
+            None => Vec::new()
+        };
+        Ok(songs)
     }
 
     pub fn get_directory(&self) -> PathBuf {
         self.directory.clone()
     }
 
-    pub fn update(&self, song: Song) {
-        let sql = "UPDATE Songs SET downloaded = ? WHERE id = ?";
-        let _ = self.connection.execute(sql, params![match song.file { Some(_) => 1, None => 0 }, song.sql_id]);
-    }
-
-    pub fn load_song_by_id(&self, id: usize) -> Song {
-        let mut pattern = self.connection.prepare("SELECT * FROM Songs WHERE id = ?").unwrap();
-        pattern.query_map(params![id], |row| {
-            let sql_id = row.get::<_, usize>(0).unwrap();
-            let id = row.get::<_, String>(1).unwrap();
-            let name = row.get::<_, String>(2).unwrap();
-            let artist = row.get::<_, String>(3).unwrap();
-            let album = row.get::<_, String>(4).unwrap();
-            let duration_s = row.get::<_, usize>(5).unwrap();
-            let downloaded = match row.get::<_, usize>(6) {
-                Ok(d) => if d == 0 { None } else { Some(self.directory.join(PathBuf::from(format!("{id}.mp3")))) },
-                Err(_) => None
-            };
-            Ok(Song::new(sql_id, name, artist, album, id, duration_s, downloaded))
-        }).unwrap().map(|x| x.unwrap()).collect::<Vec<Song>>().remove(0)
-    }
-
-    pub fn load_playlist(&self, playlist: &mut Playlist) {
-        let mut pattern = self.connection.prepare("SELECT song_id FROM Contents WHERE playlist_id = ?").unwrap();
-        playlist.songs = Some(
-            pattern.query_map(params![playlist.id], |row| {
-                Ok(self.load_song_by_id(row.get::<_, usize>(0).unwrap()))
-            }).unwrap().map(|x| x.unwrap()).collect::<Vec<Song>>()
-        );
+    /// Stores a song's rating and whether it is a favourite.
+    pub fn update_preference(&self, song: &Song) -> ResonateResult<()> {
+        self.connection.execute(
+            "UPDATE Songs SET rating = ?1, favourite = ?2 WHERE id = ?3",
+            params![song.rating, song.favourite, song.sql_id]
+        )?;
+        Ok(())
+    }
+
+    /// Downloaded songs whose loudness has not been analysed yet, leaving out ones that failed.
+    pub fn retrieve_songs_without_gain(&self) -> ResonateResult<Vec<Song>> {
+        let mut pattern = self.connection.prepare("SELECT * FROM Songs WHERE downloaded = 1 AND track_gain IS NULL AND gain_failed = 0")?;
+        let songs = pattern.query_map([], |row| self.song_from_row(row))?.collect::<rusqlite::Result<Vec<Song>>>()?;
+        Ok(songs)
+    }
+
+    pub fn set_replay_gain(&self, sql_id: usize, gain: &ReplayGain) -> ResonateResult<()> {
+        self.connection.execute(
+            "UPDATE Songs SET track_gain = ?1, track_peak = ?2, album_gain = ?3, album_peak = ?4 WHERE id = ?5",
+            params![gain.track_gain, gain.track_peak, gain.album_gain, gain.album_peak, sql_id]
+        )?;
+        Ok(())
+    }
+
+    /// Stops a song that could not be analysed from being analysed again until its file changes.
+    pub fn set_gain_failed(&self, sql_id: usize) -> ResonateResult<()> {
+        self.connection.execute("UPDATE Songs SET gain_failed = 1 WHERE id = ?", params![sql_id])?;
+        Ok(())
+    }
+
+    /// Works out the gain of every album with a downloaded song that is missing its album gain,
+    /// once all of the album's downloaded songs have been analysed. Songs that failed analysis are
+    /// left out of the album gain. Returns the songs updated.
+    pub fn update_album_gains(&self) -> ResonateResult<Vec<usize>> {
+        let mut albums: HashMap<usize, Vec<(usize, f32, f32, usize)>> = HashMap::new();
+        let mut pattern = self.connection.prepare("
+            SELECT album_id, id, track_gain, track_peak, duration_s FROM Songs
+            WHERE downloaded = 1 AND track_gain IS NOT NULL AND album_id IN (
+                SELECT album_id FROM Songs WHERE downloaded = 1 AND album_id IS NOT NULL
+                GROUP BY album_id HAVING COUNT(*) = COUNT(track_gain) + SUM(gain_failed) AND COUNT(track_gain) > COUNT(album_gain)
+            )
+        ")?;
+        for song in pattern.query_map([], |row| Ok((row.get::<_, usize>(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))))? {
+            let (album_id, song) = song?;
+            albums.entry(album_id).or_default().push(song);
+        }
+
+        let mut updated = Vec::new();
+        for (album_id, songs) in albums {
+            let (gain, peak) = album_gain(&songs.iter().map(|(_, gain, peak, duration)| (*gain, *peak, *duration)).collect::<Vec<_>>());
+            self.connection.execute(
+                "UPDATE Songs SET album_gain = ?1, album_peak = ?2 WHERE downloaded = 1 AND track_gain IS NOT NULL AND album_id = ?3",
+                params![gain, peak, album_id]
+            )?;
+            updated.extend(songs.into_iter().map(|(id, ..)| id));
+        }
+        Ok(updated)
+    }
+
+    /// The user's own equalizer presets, by name.
+    pub fn retrieve_eq_presets(&self) -> ResonateResult<Vec<EqPreset>> {
+        let mut pattern = self.connection.prepare("SELECT name, gains FROM EqPresets ORDER BY name COLLATE NOCASE")?;
+        let presets = pattern.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        // Presets that cannot be read are skipped rather than stopping the rest from loading
+        Ok(presets.into_iter().filter_map(|(name, gains)| parse_gains(&gains).map(|gains| EqPreset::new(name, gains))).collect())
+    }
+
+    /// Saves a preset, replacing any with the same name.
+    pub fn save_eq_preset(&self, preset: &EqPreset) -> ResonateResult<()> {
+        self.connection.execute("INSERT OR REPLACE INTO EqPresets VALUES(?1, ?2)", params![preset.name, format_gains(&preset.gains)])?;
+        Ok(())
+    }
+
+    /// Deletes a preset, along with every override that uses it.
+    pub fn delete_eq_preset(&self, name: &str) -> ResonateResult<()> {
+        self.connection.execute("DELETE FROM EqPresets WHERE name = ?", params![name])?;
+        self.connection.execute("UPDATE Songs SET eq_preset = NULL WHERE eq_preset = ?", params![name])?;
+        self.connection.execute("UPDATE Playlists SET eq_preset = NULL WHERE eq_preset = ?", params![name])?;
+        Ok(())
+    }
+
+    pub fn set_song_eq_preset(&self, sql_id: usize, preset: Option<&str>) -> ResonateResult<()> {
+        self.connection.execute("UPDATE Songs SET eq_preset = ?1 WHERE id = ?2", params![preset, sql_id])?;
+        Ok(())
     }
 
-    pub fn search_playlist_by_name(&self, query: String) -> Vec<Playlist> {
-        let like_query = format!("%{query}");
-        let mut pattern = self.connection.prepare("SELECT * FROM Playlists WHERE name LIKE ?").unwrap();
-        pattern.query_map(params![like_query], |row| {
+    pub fn set_playlist_eq_preset(&self, playlist_id: usize, preset: Option<&str>) -> ResonateResult<()> {
+        self.connection.execute("UPDATE Playlists SET eq_preset = ?1 WHERE id = ?2", params![preset, playlist_id])?;
+        Ok(())
+    }
+
+    pub fn update(&self, song: Song) -> ResonateResult<()> {
+        // A new file gets another chance at being analysed
+        let sql = "UPDATE Songs SET downloaded = ?, gain_failed = 0 WHERE id = ?";
+        self.connection.execute(sql, params![match song.file { Some(_) => 1, None => 0 }, song.sql_id])?;
+        Ok(())
+    }
+
+    /// Replaces a song's file and metadata, e.g. after its source file was edited.
+    pub fn replace_song(&self, song: &Song) -> ResonateResult<()> {
+        // The new file has to be analysed again, and so does the album it was on
+        self.connection.execute("
+            UPDATE Songs SET album_gain = NULL, album_peak = NULL WHERE album_id = (SELECT album_id FROM Songs WHERE id = ?)
+        ", params![song.sql_id])?;
+        self.connection.execute("
+            UPDATE Songs SET ytid = ?1, name = ?2, artist = ?3, album = ?4, duration_s = ?5, downloaded = ?6, missing = 0,
+                track_gain = NULL, track_peak = NULL, gain_failed = 0
+            WHERE id = ?7
+        ", params![song.id, song.name, song.artist, song.album, song.duration, song.file.is_some(), song.sql_id])?;
+        self.link_song(song)
+    }
+
+    /// Files a song under its artists and album, creating either if they do not exist yet.
+    fn link_song(&self, song: &Song) -> ResonateResult<()> {
+        self.connection.execute("DELETE FROM SongArtists WHERE song_id = ?", params![song.sql_id])?;
+        let mut artist_ids: Vec<usize> = Vec::new();
+        for (position, name) in split_artists(&song.artist).enumerate() {
+            self.connection.execute("INSERT OR IGNORE INTO Artists (name) VALUES(?)", params![name])?;
+            let artist_id: usize = self.connection.query_row("SELECT id FROM Artists WHERE name = ?", params![name], |row| row.get(0))?;
+            self.connection.execute("INSERT OR IGNORE INTO SongArtists VALUES(?1, ?2, ?3)", params![song.sql_id, artist_id, position])?;
+            artist_ids.push(artist_id);
+        }
+
+        let album = song.album.trim();
+        let album_id: Option<usize> = if album.is_empty() { None } else {
+            let artist_id = artist_ids.first().copied();
+            // UNIQUE does not stop duplicate albums without an artist, since NULLs are distinct
+            self.connection.execute("
+                INSERT INTO Albums (name, artist_id, artwork) SELECT ?1, ?2, ?3
+                WHERE NOT EXISTS (SELECT 1 FROM Albums WHERE name = ?1 AND artist_id IS ?2)
+            ", params![album, artist_id, song.id])?;
+            Some(self.connection.query_row("SELECT id FROM Albums WHERE name = ?1 AND artist_id IS ?2", params![album, artist_id], |row| row.get(0))?)
+        };
+        self.connection.execute("UPDATE Songs SET album_id = ?1 WHERE id = ?2", params![album_id, song.sql_id])?;
+        Ok(())
+    }
+
+    /// Records a song's position on its album and the album's release year, keeping whatever was
+    /// known before if either is missing.
+    pub fn set_track_info(&self, sql_id: usize, year: Option<u32>, track: Option<u32>) -> ResonateResult<()> {
+        self.connection.execute("UPDATE Songs SET track_number = IFNULL(?1, track_number) WHERE id = ?2", params![track, sql_id])?;
+        self.connection.execute("
+            UPDATE Albums SET year = IFNULL(?1, year) WHERE id = (SELECT album_id FROM Songs WHERE id = ?2)
+        ", params![year, sql_id])?;
+        Ok(())
+    }
+
+    /// Records the file a song was imported from, clearing any earlier missing mark.
+    pub fn set_song_source(&self, sql_id: usize, source: &Path) -> ResonateResult<()> {
+        self.connection.execute("UPDATE Songs SET source_path = ?1, missing = 0 WHERE id = ?2", params![source.to_string_lossy(), sql_id])?;
+        Ok(())
+    }
+
+    pub fn find_song_by_artist_title(&self, artist: &str, title: &str) -> ResonateResult<Option<Song>> {
+        let song = self.connection.query_row("
+            SELECT * FROM Songs WHERE name = ?1 COLLATE NOCASE AND artist = ?2 COLLATE NOCASE
+            ORDER BY downloaded DESC LIMIT 1
+        ", params![title.trim(), artist.trim()], |row| self.song_from_row(row)).optional()?;
+        Ok(song)
+    }
+
+    pub fn find_song_by_source(&self, source: &Path) -> ResonateResult<Option<Song>> {
+        let song = self.connection.query_row("SELECT * FROM Songs WHERE source_path = ?", params![source.to_string_lossy()], |row| self.song_from_row(row)).optional()?;
+        Ok(song)
+    }
+
+    /// Source files of every song imported from somewhere inside a folder.
+    pub fn sources_under(&self, folder: &Path) -> ResonateResult<Vec<PathBuf>> {
+        let prefix = format!("{}{}", folder.to_string_lossy(), std::path::MAIN_SEPARATOR);
+        let mut pattern = self.connection.prepare("SELECT source_path FROM Songs WHERE substr(source_path, 1, length(?1)) = ?1")?;
+        let sources = pattern.query_map(params![prefix], |row| row.get::<_, String>(0))?
+            .map(|source| source.map(PathBuf::from))
+            .collect::<rusqlite::Result<Vec<PathBuf>>>()?;
+        Ok(sources)
+    }
+
+    /// Marks songs whose source is the given path, or lies inside it, as missing. Returns how many
+    /// songs were newly marked.
+    pub fn mark_source_missing(&self, path: &Path) -> ResonateResult<usize> {
+        let path = path.to_string_lossy();
+        let prefix = format!("{path}{}", std::path::MAIN_SEPARATOR);
+        let changed = self.connection.execute("
+            UPDATE Songs SET missing = 1
+            WHERE missing = 0 AND (source_path = ?1 OR substr(source_path, 1, length(?2)) = ?2)
+        ", params![path, prefix])?;
+        Ok(changed)
+    }
+
+    pub fn count_missing_songs(&self) -> ResonateResult<usize> {
+        let count = self.connection.query_row("SELECT COUNT(*) FROM Songs WHERE missing = 1", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    pub fn load_song_by_id(&self, id: usize) -> ResonateResult<Song> {
+        let song = self.connection.query_row("SELECT * FROM Songs WHERE id = ?", params![id], |row| self.song_from_row(row))?;
+        Ok(song)
+    }
+
+    pub fn try_load_song_by_id(&self, id: usize) -> ResonateResult<Option<Song>> {
+        let song = self.connection.query_row("SELECT * FROM Songs WHERE id = ?", params![id], |row| self.song_from_row(row)).optional()?;
+        Ok(song)
+    }
+
+    pub fn find_song_by_ytid(&self, ytid: &str) -> ResonateResult<Option<Song>> {
+        let song = self.connection.query_row("SELECT * FROM Songs WHERE ytid = ?", params![ytid], |row| self.song_from_row(row)).optional()?;
+        Ok(song)
+    }
+
+    /// Loads a playlist's songs. Smart playlists are evaluated against the library as it is now.
+    pub fn load_playlist(&self, playlist: &mut Playlist) -> ResonateResult<()> {
+        playlist.smart = match playlist.id {
+            LIKED_PLAYLIST_ID => Some(SmartPlaylist::liked()),
+            id => self.load_smart_playlist(id)?
+        };
+        let ids = match playlist.smart.as_ref() {
+            Some(smart) => {
+                let (sql, values) = smart.to_sql()?;
+                let mut pattern = self.connection.prepare(&sql)?;
+                let ids = pattern.query_map(params_from_iter(values), |row| row.get::<_, usize>(0))?.collect::<rusqlite::Result<Vec<usize>>>()?;
+                ids
+            }
+            None => {
+                let mut pattern = self.connection.prepare("SELECT song_id FROM Contents WHERE playlist_id = ? ORDER BY position")?;
+                let ids = pattern.query_map(params![playlist.id], |row| row.get::<_, usize>(0))?.collect::<rusqlite::Result<Vec<usize>>>()?;
+                ids
+            }
+        };
+        playlist.equalizer = self.connection.query_row("SELECT eq_preset FROM Playlists WHERE id = ?", params![playlist.id], |row| row.get(0)).optional()?.flatten();
+        let mut songs = ids.into_iter().map(|id| self.load_song_by_id(id)).collect::<ResonateResult<Vec<Song>>>()?;
+        // Songs with an override of their own keep it
+        songs.iter_mut().filter(|song| song.equalizer.is_none()).for_each(|song| song.equalizer.clone_from(&playlist.equalizer));
+        playlist.songs = Some(songs);
+        Ok(())
+    }
+
+    fn load_smart_playlist(&self, playlist_id: usize) -> ResonateResult<Option<SmartPlaylist>> {
+        let smart = self.connection.query_row(
+            "SELECT match_all, sort, descending, song_limit FROM SmartPlaylists WHERE playlist_id = ?",
+            params![playlist_id],
+            |row| Ok(SmartPlaylist {
+                match_all: row.get(0)?,
+                rules: Vec::new(),
+                sort: SmartSort::from_key(&row.get::<_, String>(1)?).unwrap_or(SmartSort::Artist),
+                descending: row.get(2)?,
+                limit: row.get(3)?
+            })
+        ).optional()?;
+
+        let mut smart = match smart {
+            Some(smart) => smart,
+            None => return Ok(None)
+        };
+
+        let mut pattern = self.connection.prepare("SELECT field, operator, value FROM SmartRules WHERE playlist_id = ? ORDER BY position")?;
+        let rules = pattern.query_map(params![playlist_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String, String)>>>()?;
+        for (field, operator, value) in rules {
+            // Rules written by a newer version of resonate are left out rather than failing the playlist
+            match (RuleField::from_key(&field), RuleOperator::from_key(&operator)) {
+                (Some(field), Some(operator)) => smart.rules.push(Rule { field, operator, value }),
+                _ => println!("[DATABASE] Skipping unknown smart playlist rule {field} {operator}")
+            }
+        }
+        Ok(Some(smart))
+    }
+
+    /// Stores a playlist's rules, turning it into a smart playlist if it was not one already.
+    pub fn save_smart_playlist(&self, playlist: &mut Playlist, smart: SmartPlaylist) -> ResonateResult<()> {
+        smart.validate()?;
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute("
+            INSERT OR REPLACE INTO SmartPlaylists (playlist_id, match_all, sort, descending, song_limit) VALUES(?1, ?2, ?3, ?4, ?5)
+        ", params![playlist.id, smart.match_all, smart.sort.key(), smart.descending, smart.limit])?;
+        transaction.execute("DELETE FROM SmartRules WHERE playlist_id = ?", params![playlist.id])?;
+        for (position, rule) in smart.rules.iter().enumerate() {
+            let (field, operator) = rule.keys();
+            transaction.execute("
+                INSERT INTO SmartRules (playlist_id, position, field, operator, value) VALUES(?1, ?2, ?3, ?4, ?5)
+            ", params![playlist.id, position, field, operator, rule.value.trim()])?;
+        }
+        transaction.commit()?;
+        self.load_playlist(playlist)
+    }
+
+    pub fn search_playlist_by_name(&self, query: String) -> ResonateResult<Vec<Playlist>> {
+        let expression = match match_expression(&query, &[]) {
+            Some(expression) => expression,
+            None => return self.dump_all_playlists()
+        };
+        let mut pattern = self.connection.prepare("
+            SELECT Playlists.* FROM PlaylistSearch JOIN Playlists ON Playlists.id = PlaylistSearch.rowid
+            WHERE PlaylistSearch MATCH ? ORDER BY bm25(PlaylistSearch)
+        ")?;
+        let playlists = pattern.query_map(params![expression], |row| {
             Ok(Playlist {
-                id: row.get::<_, usize>(0).unwrap(),
-                name: row.get::<_, String>(1).unwrap(),
-                songs: None
+                id: row.get::<_, usize>(0)?,
+                name: row.get::<_, String>(1)?,
+                songs: None,
+                smart: None,
+                equalizer: None
             })
-        }).unwrap().map(|playlist| playlist.unwrap()).collect()
+        })?.collect::<rusqlite::Result<Vec<Playlist>>>()?;
+        Ok(playlists)
     }
 
-    pub fn dump_all_playlists(&self) -> Vec<Playlist> {
-        let mut pattern = self.connection.prepare("SELECT * FROM Playlists").unwrap();
+    pub fn dump_all_playlists(&self) -> ResonateResult<Vec<Playlist>> {
+        let mut pattern = self.connection.prepare("SELECT * FROM Playlists")?;
         let mut playlists = pattern.query_map([], |row| {
             Ok(Playlist {
-                id: row.get::<_, usize>(0).unwrap(),
-                name: row.get::<_, String>(1).unwrap(),
-                songs: None
+                id: row.get::<_, usize>(0)?,
+                name: row.get::<_, String>(1)?,
+                songs: None,
+                smart: None,
+                equalizer: None
             })
-        }).unwrap().map(|x| x.unwrap()).collect::<Vec<Playlist>>();
+        })?.collect::<rusqlite::Result<Vec<Playlist>>>()?;
 
-        playlists.iter_mut().for_each(|playlist| self.load_playlist(playlist));
-        playlists
+        playlists.iter_mut().try_for_each(|playlist| self.load_playlist(playlist))?;
+        Ok(playlists)
     }
 
-    pub fn add_song_to_cache(&self, song: &mut Song) {
-        let _ = self.connection.execute("
-            INSERT INTO Songs
-            VALUES(null, ?1, ?2, ?3, ?4, ?5, ?6);
+    pub fn add_song_to_cache(&self, song: &mut Song) -> ResonateResult<()> {
+        self.connection.execute("
+            INSERT INTO Songs (ytid, name, artist, album, duration_s, downloaded, added_at)
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6, datetime('now'));
         ",
-        params![song.id, song.name, song.artist, song.album, song.duration, if song.file == None { 0 } else { 1 }]);
+        params![song.id, song.name, song.artist, song.album, song.duration, if song.file.is_none() { 0 } else { 1 }])?;
         song.sql_id = self.connection.last_insert_rowid() as usize;
+        self.link_song(song)
     }
 
-    pub fn create_playlist(&self, name: String) -> Playlist {
-        let _ = self.connection.execute("
-            INSERT INTO Playlists
-            VALUES(null, ?1);
-        ",params![name]);
+    pub fn create_playlist(&self, name: String) -> ResonateResult<Playlist> {
+        self.connection.execute("
+            INSERT INTO Playlists (name)
+            VALUES(?1);
+        ",params![name])?;
         println!("Created playlist. {} at ID {}", name, self.connection.last_insert_rowid());
-        Playlist {
+        Ok(Playlist {
             id: self.connection.last_insert_rowid() as usize,
             name,
-            songs: Some(vec![])
-        }
+            songs: Some(vec![]),
+            smart: None,
+            equalizer: None
+        })
     }
 
-    pub fn add_song_to_playlist(&self, song: &Song, playlist: &mut Playlist) {
-        let _ = self.connection.execute("
-            INSERT INTO Contents
-            VALUES(?1, ?2)
-        ", params![playlist.id, song.sql_id]);
+    pub fn add_song_to_playlist(&self, song: &Song, playlist: &mut Playlist) -> ResonateResult<()> {
+        let inserted = self.connection.execute("
+            INSERT OR IGNORE INTO Contents (playlist_id, song_id, position)
+            VALUES(?1, ?2, (SELECT IFNULL(MAX(position) + 1, 0) FROM Contents WHERE playlist_id = ?1))
+        ", params![playlist.id, song.sql_id])?;
+
+        // A song can only appear in a playlist once
+        if inserted == 0 { return Ok(()); }
+
         match &mut playlist.songs {
             Some(songs) => songs.push(song.clone()),
             None => playlist.songs = Some(vec![song.clone()])
         }
+        Ok(())
+    }
+
+    pub fn get_setting(&self, key: &str) -> ResonateResult<Option<String>> {
+        let value = self.connection.query_row("SELECT value FROM Settings WHERE key = ?", params![key], |row| row.get(0)).optional()?;
+        Ok(value)
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> ResonateResult<()> {
+        self.connection.execute("INSERT OR REPLACE INTO Settings VALUES(?1, ?2)", params![key, value])?;
+        Ok(())
+    }
+
+    pub fn save_session(&self, session: &Session) -> ResonateResult<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute("
+            INSERT OR REPLACE INTO Session (id, current_song_id, position_s, volume, speed, repeat, shuffle, pitch)
+            VALUES(0, ?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ", params![
+            session.current.as_ref().map(|song| song.sql_id),
+            session.position,
+            session.volume,
+            session.speed,
+            session.repeat.to_index(),
+            session.shuffle,
+            session.pitch
+        ])?;
+
+        transaction.execute("DELETE FROM SessionQueue", [])?;
+        for (position, song) in session.queue.iter().enumerate() {
+            transaction.execute("INSERT INTO SessionQueue VALUES(?1, ?2)", params![position, song.sql_id])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Loads the last saved session. Songs that have since been deleted are left out.
+    pub fn load_session(&self) -> ResonateResult<Option<Session>> {
+        let saved = self.connection.query_row("SELECT * FROM Session WHERE id = 0", [], |row| {
+            Ok((
+                row.get::<_, Option<usize>>(1)?,
+                row.get::<_, f32>(2)?,
+                row.get::<_, f32>(3)?,
+                row.get::<_, f32>(4)?,
+                row.get::<_, usize>(5)?,
+                row.get::<_, bool>(6)?,
+                row.get::<_, f32>("pitch")?
+            ))
+        }).optional()?;
+
+        let (current_id, position, volume, speed, repeat, shuffle, pitch) = match saved {
+            Some(saved) => saved,
+            None => return Ok(None)
+        };
+
+        let mut pattern = self.connection.prepare("SELECT song_id FROM SessionQueue ORDER BY position")?;
+        let ids = pattern.query_map([], |row| row.get::<_, usize>(0))?.collect::<rusqlite::Result<Vec<usize>>>()?;
+
+        let current = match current_id {
+            Some(id) => self.try_load_song_by_id(id)?,
+            None => None
+        };
+        let queue = ids.into_iter()
+            .map(|id| self.try_load_song_by_id(id))
+            .collect::<ResonateResult<Vec<Option<Song>>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(Some(Session { current, position, queue, volume, speed, pitch, repeat: RepeatMode::from_index(repeat), shuffle }))
+    }
+
+    pub fn remove_song_from_playlist(&self, song: &Song, playlist: &mut Playlist) -> ResonateResult<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let position: Option<usize> = transaction.query_row(
+            "SELECT position FROM Contents WHERE playlist_id = ?1 AND song_id = ?2",
+            params![playlist.id, song.sql_id],
+            |row| row.get(0)
+        ).optional()?;
+
+        if let Some(position) = position {
+            transaction.execute("DELETE FROM Contents WHERE playlist_id = ?1 AND song_id = ?2", params![playlist.id, song.sql_id])?;
+            transaction.execute("UPDATE Contents SET position = position - 1 WHERE playlist_id = ?1 AND position > ?2", params![playlist.id, position])?;
+        }
+        transaction.commit()?;
+
+        if let Some(songs) = playlist.songs.as_mut() {
+            songs.retain(|s| s.sql_id != song.sql_id);
+        }
+        Ok(())
+    }
+
+    /// Moves the song at index `from` so that it ends up at index `to`, shifting the songs in between.
+    pub fn move_song_in_playlist(&self, playlist: &mut Playlist, from: usize, to: usize) -> ResonateResult<()> {
+        if playlist.songs.is_none() { self.load_playlist(playlist)?; }
+        let songs = match playlist.songs.as_mut() {
+            Some(songs) if from < songs.len() && to < songs.len() => songs,
+            _ => return Ok(())
+        };
+
+        let song = songs.remove(from);
+        songs.insert(to, song);
+
+        let transaction = self.connection.unchecked_transaction()?;
+        for (position, song) in songs.iter().enumerate() {
+            transaction.execute(
+                "UPDATE Contents SET position = ?1 WHERE playlist_id = ?2 AND song_id = ?3",
+                params![position, playlist.id, song.sql_id]
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn rename_playlist(&self, playlist: &mut Playlist, name: String) -> ResonateResult<()> {
+        self.connection.execute("UPDATE Playlists SET name = ?1 WHERE id = ?2", params![name, playlist.id])?;
+        playlist.name = name;
+        Ok(())
+    }
+
+    pub fn delete_playlist(&self, playlist: &Playlist) -> ResonateResult<()> {
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute("DELETE FROM Contents WHERE playlist_id = ?", params![playlist.id])?;
+        transaction.execute("DELETE FROM SmartRules WHERE playlist_id = ?", params![playlist.id])?;
+        transaction.execute("DELETE FROM SmartPlaylists WHERE playlist_id = ?", params![playlist.id])?;
+        transaction.execute("DELETE FROM Playlists WHERE id = ?", params![playlist.id])?;
+        transaction.commit()?;
+        Ok(())
     }
 }
 
 pub async fn download_thumbnail(directory: PathBuf, id: String) -> Message {
+    match tokio::task::spawn_blocking(move || fetch_thumbnail(directory, id)).await {
+        Ok(Ok(())) => Message::ThumbnailDownloaded,
+        Ok(Err(e)) => Message::Error(e),
+        Err(e) => Message::Error(ResonateError::ProcessSpawn(e.to_string()))
+    }
+}
+
+/// Crops an image to a centred square and saves it as the song's thumbnail.
+pub fn save_thumbnail(raw: DynamicImage, directory: &Path, id: &str) -> ResonateResult<()> {
+    let side = raw.width().min(raw.height());
+    let cropped = raw.crop_imm((raw.width() - side) / 2, (raw.height() - side) / 2, side, side);
+    cropped.save(directory.join(format!("{id}.png")))?;
+    Ok(())
+}
+
+/// Downloads and crops a song's thumbnail, then embeds it into the song's file if it has one.
+pub fn fetch_thumbnail(directory: PathBuf, id: String) -> ResonateResult<()> {
     let path = directory.join(&id).to_string_lossy().to_string();
 
-    let mut handle = Command::new("yt-dlp")
+    let status = Command::new("yt-dlp")
         .arg("--write-thumbnail")
         .arg("--skip-download")
         .arg(format!("https://music.youtube.com/watch?v={}", id))
         .arg("-o")
-        .arg(path.clone())
-        .spawn().unwrap();
+        .arg(path)
+        .status()
+        .map_err(|e| ResonateError::ProcessSpawn(format!("yt-dlp: {e}")))?;
+
+    if !status.success() {
+        return Err(ResonateError::ProcessSpawn(format!("yt-dlp exited with {status} while fetching thumbnail for {id}")));
+    }
+
+    let webp = directory.join(format!("{id}.webp"));
+    save_thumbnail(image::open(&webp)?, &directory, &id)?;
+    let _ = std::fs::remove_file(webp);
+
+    embed_cover(&directory, &id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let _ = handle.wait();
+    // The tables as they were created before there were migrations
+    const BASELINE_SCHEMA: &str = "
+        CREATE TABLE Songs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            ytid TEXT NOT NULL,
+            name TEXT NOT NULL,
+            artist TEXT NOT NULL,
+            album TEXT NOT NULL,
+            duration_s INT NOT NULL,
+            downloaded INT NOT NULL
+        );
+        CREATE TABLE Playlists (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL
+        );
+        CREATE TABLE Contents (
+            playlist_id INTEGER,
+            song_id INTEGER,
+            PRIMARY KEY (playlist_id, song_id),
+            FOREIGN KEY (playlist_id) REFERENCES Playlists(id) ON DELETE CASCADE,
+            FOREIGN KEY (song_id) REFERENCES Songs(id) ON DELETE CASCADE
+        );
+    ";
+
+    fn schema_version(connection: &Connection) -> usize {
+        connection.query_row("SELECT version FROM schema_version", [], |row| row.get(0)).unwrap()
+    }
 
-    let raw = image::open(directory.join(&id).join(".webp")).unwrap();
-    let height = raw.height();
-    let padding = (raw.width() - height)/2;
-    let cropped = raw.crop_imm(padding, 0, height, height);
-    let _ = cropped.save(directory.join(&id).join(".png"));
-    let _ = std::fs::remove_file(PathBuf::from(path).join(".webp"));
+    #[test]
+    fn migrates_a_baseline_database_to_the_latest_version() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(BASELINE_SCHEMA).unwrap();
+        connection.execute_batch("
+            INSERT INTO Songs (ytid, name, artist, album, duration_s, downloaded) VALUES
+                ('a', 'First', 'Alpha, Beta & Gamma', ' Record ', 200, 1),
+                ('b', 'Second', 'Alpha', 'Record', 180, 0);
+            INSERT INTO Playlists (name) VALUES ('Mix');
+            INSERT INTO Contents (playlist_id, song_id) VALUES (1, 2), (1, 1);
+        ").unwrap();
 
-    Message::ThumbnailDownloaded
+        Database::migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection), MIGRATIONS.len());
+
+        let database = Database { connection, directory: std::env::temp_dir() };
+        let playlist = database.dump_all_playlists().unwrap().into_iter().find(|playlist| playlist.name == "Mix").unwrap();
+        let names = playlist.songs.unwrap().into_iter().map(|song| song.name).collect::<Vec<String>>();
+        assert_eq!(names, vec!["Second", "First"]);
+
+        let mut library = database.retrieve_library().unwrap();
+        library.sort_by_key(|entry| entry.song.sql_id);
+        let artists = library.into_iter().map(|entry| entry.artists).collect::<Vec<Vec<String>>>();
+        assert_eq!(artists, vec![vec!["Alpha", "Beta", "Gamma"], vec!["Alpha"]]);
+        let albums: usize = database.connection.query_row("SELECT COUNT(*) FROM Albums", [], |row| row.get(0)).unwrap();
+        assert_eq!(albums, 1);
+    }
+
+    #[test]
+    fn migrates_step_by_step_from_an_intermediate_version() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute("CREATE TABLE schema_version (version INTEGER NOT NULL);", []).unwrap();
+        for migration in &MIGRATIONS[..3] {
+            connection.execute_batch(migration).unwrap();
+        }
+        connection.execute("INSERT INTO schema_version VALUES(3)", []).unwrap();
+
+        Database::migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection), MIGRATIONS.len());
+
+        // Running again on an up to date database changes nothing
+        Database::migrate(&mut connection).unwrap();
+        assert_eq!(schema_version(&connection), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn refuses_a_schema_newer_than_the_binary() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let newer = MIGRATIONS.len() + 1;
+        connection.execute_batch(&format!("
+            CREATE TABLE schema_version (version INTEGER NOT NULL);
+            INSERT INTO schema_version VALUES({newer});
+        ")).unwrap();
+
+        assert_eq!(Database::migrate(&mut connection), Err(ResonateError::SchemaTooNew(newer, MIGRATIONS.len())));
+        assert_eq!(schema_version(&connection), newer);
+    }
+
+    fn song_expression(input: &str) -> Option<String> {
+        match_expression(input, SONG_SEARCH_FIELDS)
+    }
+
+    #[test]
+    fn words_match_as_prefixes() {
+        assert_eq!(song_expression("under press").as_deref(), Some("\"under\"* AND \"press\"*"));
+    }
+
+    #[test]
+    fn field_filters_match_within_their_column() {
+        assert_eq!(song_expression("artist:queen Album:\"hot space\" pressure").as_deref(),
+            Some("artist : \"queen\"* AND album : \"hot space\"* AND \"pressure\"*"));
+        assert_eq!(song_expression("title:under").as_deref(), Some("name : \"under\"*"));
+    }
+
+    #[test]
+    fn unknown_fields_are_searched_as_text() {
+        assert_eq!(song_expression("year:1982").as_deref(), Some("\"year:1982\"*"));
+        // Playlists have no fields at all
+        assert_eq!(match_expression("artist:queen", &[]).as_deref(), Some("\"artist:queen\"*"));
+    }
+
+    #[test]
+    fn fts5_syntax_in_the_input_is_quoted() {
+        assert_eq!(song_expression("\"queen\"").as_deref(), Some("\"queen\"*"));
+        assert_eq!(song_expression("a\"b").as_deref(), Some("\"a\"\"b\"*"));
+        assert_eq!(song_expression("queen* OR NOT").as_deref(), Some("\"queen*\"* AND \"OR\"* AND \"NOT\"*"));
+        assert_eq!(song_expression("artist:\"unclosed").as_deref(), Some("artist : \"unclosed\"*"));
+    }
+
+    #[test]
+    fn empty_input_searches_nothing() {
+        assert_eq!(song_expression(""), None);
+        assert_eq!(song_expression("   "), None);
+        assert_eq!(song_expression("\"\""), None);
+        assert_eq!(song_expression("artist:"), None);
+    }
+
+    #[test]
+    fn expressions_are_valid_fts5_queries() {
+        let database = Database::in_memory().unwrap();
+        let mut song = Song::new(0, String::from("Under Pressure"), String::from("Queen, David Bowie"), String::from("Hot Space"), String::from("a"), 249, None);
+        database.add_song_to_cache(&mut song).unwrap();
+
+        for input in ["under", "artist:bowie", "album:\"hot space\"", "\"queen", "a\"b", "*", "queen*", "year:1982", "(", "NEAR(under pressure)", "-", "artist:\"unclosed"] {
+            let result = database.search_cached_song(input.to_string());
+            assert!(result.is_ok(), "{input} gave {result:?}");
+        }
+        assert_eq!(database.search_cached_song(String::from("artist:bowie pres")).unwrap(), vec![song]);
+    }
 }
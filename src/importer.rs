@@ -0,0 +1,376 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::Hint;
+
+use crate::audio::query_song_length;
+use crate::downloader::copy_into_library;
+use crate::error::{ResonateError, ResonateResult};
+use crate::filemanager::{save_thumbnail, Database};
+use crate::loudness::{ReplayGain, REFERENCE_LOUDNESS};
+use crate::music::Song;
+use crate::search::is_audio_file;
+use crate::tagger::write_tags;
+use crate::utility::*;
+
+/// Songs that came from local files have ids starting with this, rather than a YouTube id.
+pub const LOCAL_ID_PREFIX: &str = "local-";
+
+pub fn is_local_id(id: &str) -> bool {
+    id.starts_with(LOCAL_ID_PREFIX)
+}
+
+/// A stable id derived from the file's contents, so the same recording is recognised however it
+/// is named or wherever it is moved.
+pub fn content_id(path: &Path) -> ResonateResult<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 { break; }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{LOCAL_ID_PREFIX}{}", &hasher.finalize().to_hex()[..32]))
+}
+
+/// Metadata read from a file's embedded tags.
+#[derive(Default)]
+pub struct FileTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub duration: Option<usize>,
+    pub artwork: Option<Vec<u8>>,
+    // ReplayGain, in dB and linear amplitude
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>
+}
+
+/// Reads gains written as e.g. "-6.54 dB".
+fn parse_gain(value: &str) -> Option<f32> {
+    value.trim_end_matches(|c: char| c.is_alphabetic() || c.is_whitespace()).parse().ok()
+}
+
+/// Opus files store R128 gains as Q7.8 fixed point relative to -23 LUFS rather than ReplayGain.
+fn parse_r128_gain(value: &str) -> Option<f32> {
+    value.parse::<i16>().ok().map(|gain| gain as f32 / 256f32 + REFERENCE_LOUDNESS + 23f32)
+}
+
+impl FileTags {
+    /// The gains stored in the file, if it has at least a track gain. Files without a peak are
+    /// assumed to reach full scale, so that their gain never makes them clip.
+    pub fn replay_gain(&self) -> Option<ReplayGain> {
+        let track_gain = self.track_gain?;
+        Some(ReplayGain {
+            track_gain,
+            track_peak: self.track_peak.unwrap_or(1f32),
+            album_gain: self.album_gain,
+            album_peak: self.album_gain.map(|_| self.album_peak.or(self.track_peak).unwrap_or(1f32))
+        })
+    }
+
+    fn apply(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let value = tag.value.to_string().trim().to_string();
+            if value.is_empty() { continue; }
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = Some(value),
+                Some(StandardTagKey::Artist) => self.artist = Some(value),
+                // Only used when there is no track artist
+                Some(StandardTagKey::AlbumArtist) => { self.artist.get_or_insert(value); }
+                Some(StandardTagKey::Album) => self.album = Some(value),
+                // Dates may be a bare year or a full date
+                Some(StandardTagKey::Date) | Some(StandardTagKey::ReleaseDate) | Some(StandardTagKey::OriginalDate) => {
+                    if let Some(year) = value.get(..4).and_then(|year| year.parse().ok()) { self.year.get_or_insert(year); }
+                }
+                // Often written as "3/12"
+                Some(StandardTagKey::TrackNumber) => self.track = value.split('/').next().and_then(|track| track.trim().parse().ok()),
+                Some(StandardTagKey::ReplayGainTrackGain) => self.track_gain = parse_gain(&value),
+                Some(StandardTagKey::ReplayGainTrackPeak) => self.track_peak = value.parse().ok(),
+                Some(StandardTagKey::ReplayGainAlbumGain) => self.album_gain = parse_gain(&value),
+                Some(StandardTagKey::ReplayGainAlbumPeak) => self.album_peak = value.parse().ok(),
+                // ReplayGain tags win over R128 ones when a file has both
+                None if tag.key.eq_ignore_ascii_case("R128_TRACK_GAIN") => { self.track_gain = self.track_gain.or(parse_r128_gain(&value)); }
+                None if tag.key.eq_ignore_ascii_case("R128_ALBUM_GAIN") => { self.album_gain = self.album_gain.or(parse_r128_gain(&value)); }
+                _ => {}
+            }
+        }
+
+        // Prefer the front cover, but any embedded picture beats none
+        let cover = revision.visuals().iter()
+            .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+            .or(revision.visuals().first());
+        if let Some(cover) = cover {
+            self.artwork = Some(cover.data.to_vec());
+        }
+    }
+}
+
+/// Reads tags, duration and artwork. Tags outside the container (e.g. ID3 on a FLAC file) are read
+/// first so that the container's own tags take precedence.
+pub fn read_file_tags(path: &Path) -> ResonateResult<FileTags> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?;
+
+    let mut tags = FileTags::default();
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|metadata| metadata.current()) {
+        tags.apply(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.apply(revision);
+    }
+
+    tags.duration = probed.format.default_track().and_then(|track| {
+        let frames = track.codec_params.n_frames?;
+        let time = track.codec_params.time_base?.calc_time(frames);
+        Some(time.seconds as usize + time.frac.round() as usize)
+    });
+
+    Ok(tags)
+}
+
+pub enum ImportOutcome {
+    Imported(Song),
+    // The file changed since it was imported, so the song was replaced in place
+    Updated(Song),
+    Skipped(Song)
+}
+
+impl ImportOutcome {
+    pub fn into_song(self) -> Song {
+        match self {
+            Self::Imported(song) | Self::Updated(song) | Self::Skipped(song) => song
+        }
+    }
+}
+
+/// The title, artist and album a file is imported as, falling back to its file and folder names
+/// for whatever its tags leave out.
+pub fn file_details(path: &Path, tags: &mut FileTags) -> (String, String, String) {
+    let title = tags.title.take().unwrap_or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default());
+    let artist = tags.artist.take().unwrap_or_else(|| String::from("Unknown Artist"));
+    let album = tags.album.take().unwrap_or_else(|| path.parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default());
+    (title, artist, album)
+}
+
+/// Reads a file's tags and artwork and stores a tagged copy in the library. The song is not cached.
+fn copy_with_tags(directory: &Path, path: &Path, id: String) -> ResonateResult<(Song, FileTags)> {
+    let mut tags = read_file_tags(path)?;
+    let (title, artist, album) = file_details(path, &mut tags);
+    let duration = match tags.duration {
+        Some(duration) => duration,
+        None => query_song_length(path)?
+    };
+
+    if let Some(artwork) = tags.artwork.take() {
+        // Missing artwork only costs a thumbnail
+        if let Err(e) = image::load_from_memory(&artwork).map_err(ResonateError::from).and_then(|image| save_thumbnail(image, directory, &id)) {
+            println!("[IMPORT] Failed to read artwork from {}: {e}", path.display());
+        }
+    }
+
+    let target = directory.join(format!("{id}.mp3"));
+    copy_into_library(path, &target)?;
+    let song = Song::new(0, title, artist, album, id, duration, Some(target));
+
+    if let Err(e) = write_tags(directory, &song) {
+        println!("[TAGGER] Failed to tag {}: {e}", song.name);
+    }
+    Ok((song, tags))
+}
+
+/// Copies a single file into the library and caches it, using its embedded tags. Files that were
+/// imported before are skipped, and files that changed since they were imported replace the
+/// song they were imported as.
+pub fn import_file(database: &AM<Database>, path: &Path) -> ResonateResult<ImportOutcome> {
+    let id = content_id(path)?;
+    let (directory, existing, previous) = {
+        let database = database.lock().unwrap();
+        let existing = database.find_song_by_ytid(&id)?;
+        if let Some(song) = existing.as_ref() {
+            if song.file.as_ref().is_some_and(|file| file.exists()) {
+                // The same recording may have been moved or renamed
+                database.set_song_source(song.sql_id, path)?;
+                return Ok(ImportOutcome::Skipped(song.clone()));
+            }
+        }
+        (database.get_directory(), existing, database.find_song_by_source(path)?)
+    };
+
+    let (mut song, tags) = copy_with_tags(&directory, path, id)?;
+
+    let database = database.lock().unwrap();
+    let outcome = match (existing, previous) {
+        // Cached before, but the library copy had gone missing
+        (Some(cached), _) => {
+            song.sql_id = cached.sql_id;
+            database.replace_song(&song)?;
+            ImportOutcome::Imported(song.clone())
+        }
+        (None, Some(previous)) => {
+            song.sql_id = previous.sql_id;
+            database.replace_song(&song)?;
+            // The old copy is no longer referenced by anything
+            if let Some(old) = previous.file.as_ref() { let _ = std::fs::remove_file(old); }
+            let _ = std::fs::remove_file(directory.join(format!("{}.png", previous.id)));
+            ImportOutcome::Updated(song.clone())
+        }
+        (None, None) => {
+            database.add_song_to_cache(&mut song)?;
+            ImportOutcome::Imported(song.clone())
+        }
+    };
+    database.set_song_source(song.sql_id, path)?;
+    database.set_track_info(song.sql_id, tags.year, tags.track)?;
+    Ok(outcome)
+}
+
+/// Every audio file below a folder.
+pub fn collect_audio_files(directory: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(directory) {
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.is_dir() { files.extend(collect_audio_files(&path)); }
+            else if is_audio_file(&path) { files.push(path); }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Progress of a folder import, shared with the UI while the import runs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportProgress {
+    pub total: usize,
+    pub processed: usize,
+    pub imported: usize,
+    pub skipped: usize,
+    pub failed: Vec<(PathBuf, ResonateError)>,
+    pub current: Option<PathBuf>,
+    pub finished: bool
+}
+
+/// Imports every audio file below a folder, updating the progress as it goes. Failed files are
+/// recorded and the import carries on.
+pub fn import_folder(database: AM<Database>, root: PathBuf, progress: AM<ImportProgress>) -> ImportProgress {
+    let files = collect_audio_files(&root);
+    println!("[IMPORT] Found {} files in {}", files.len(), root.display());
+    progress.lock().unwrap().total = files.len();
+
+    for file in files {
+        progress.lock().unwrap().current = Some(file.clone());
+        let outcome = import_file(&database, &file);
+
+        let mut progress = progress.lock().unwrap();
+        progress.processed += 1;
+        match outcome {
+            Ok(ImportOutcome::Imported(_)) | Ok(ImportOutcome::Updated(_)) => progress.imported += 1,
+            Ok(ImportOutcome::Skipped(_)) => progress.skipped += 1,
+            Err(e) => {
+                println!("[IMPORT] Failed to import {}: {e}", file.display());
+                progress.failed.push((file, e));
+            }
+        }
+    }
+
+    let mut progress = progress.lock().unwrap();
+    progress.current = None;
+    progress.finished = true;
+    progress.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use symphonia::core::meta::{MetadataBuilder, Tag, Value};
+
+    use super::*;
+
+    fn apply(tags: &[(Option<StandardTagKey>, &str, &str)]) -> FileTags {
+        let mut builder = MetadataBuilder::new();
+        for (std_key, key, value) in tags {
+            builder.add_tag(Tag::new(*std_key, key, Value::from(*value)));
+        }
+        let mut file_tags = FileTags::default();
+        file_tags.apply(&builder.metadata());
+        file_tags
+    }
+
+    #[test]
+    fn reads_track_numbers_with_a_total() {
+        assert_eq!(apply(&[(Some(StandardTagKey::TrackNumber), "TRCK", "3/12")]).track, Some(3));
+        assert_eq!(apply(&[(Some(StandardTagKey::TrackNumber), "TRACKNUMBER", "7")]).track, Some(7));
+        assert_eq!(apply(&[(Some(StandardTagKey::TrackNumber), "TRCK", "/12")]).track, None);
+    }
+
+    #[test]
+    fn reads_years_from_dates() {
+        assert_eq!(apply(&[(Some(StandardTagKey::Date), "DATE", "1982-05-21")]).year, Some(1982));
+        // The first date found wins
+        assert_eq!(apply(&[(Some(StandardTagKey::Date), "DATE", "1982"), (Some(StandardTagKey::OriginalDate), "ORIGINALDATE", "1975")]).year, Some(1982));
+    }
+
+    #[test]
+    fn prefers_the_track_artist() {
+        let tags = apply(&[
+            (Some(StandardTagKey::AlbumArtist), "ALBUMARTIST", "Various Artists"),
+            (Some(StandardTagKey::Artist), "ARTIST", "Queen"),
+            (Some(StandardTagKey::TrackTitle), "TITLE", "  "),
+        ]);
+        assert_eq!(tags.artist.as_deref(), Some("Queen"));
+        assert_eq!(tags.title, None);
+        assert_eq!(apply(&[(Some(StandardTagKey::AlbumArtist), "ALBUMARTIST", "Queen")]).artist.as_deref(), Some("Queen"));
+    }
+
+    #[test]
+    fn parses_replay_gain_values() {
+        assert_eq!(parse_gain("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_gain("+2.10 dB"), Some(2.1));
+        assert_eq!(parse_gain("1.5"), Some(1.5));
+        assert_eq!(parse_gain("loud"), None);
+
+        let tags = apply(&[
+            (Some(StandardTagKey::ReplayGainTrackGain), "REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+            (Some(StandardTagKey::ReplayGainTrackPeak), "REPLAYGAIN_TRACK_PEAK", "0.988"),
+        ]);
+        assert_eq!(tags.replay_gain(), Some(ReplayGain { track_gain: -6.54, track_peak: 0.988, album_gain: None, album_peak: None }));
+    }
+
+    #[test]
+    fn parses_r128_gains() {
+        // Q7.8 gains relative to -23 LUFS, where ReplayGain is relative to -18 LUFS
+        assert_eq!(parse_r128_gain("0"), Some(5f32));
+        assert_eq!(parse_r128_gain("-2560"), Some(-5f32));
+        assert_eq!(parse_r128_gain("384"), Some(6.5));
+        assert_eq!(parse_r128_gain("-6.5"), None);
+
+        let tags = apply(&[(None, "R128_TRACK_GAIN", "-2560"), (None, "R128_ALBUM_GAIN", "-1280")]);
+        assert_eq!(tags.replay_gain(), Some(ReplayGain { track_gain: -5f32, track_peak: 1f32, album_gain: Some(0f32), album_peak: Some(1f32) }));
+
+        // ReplayGain tags win over R128 ones
+        let tags = apply(&[(None, "R128_TRACK_GAIN", "0"), (Some(StandardTagKey::ReplayGainTrackGain), "REPLAYGAIN_TRACK_GAIN", "-1 dB")]);
+        assert_eq!(tags.track_gain, Some(-1f32));
+    }
+
+    #[test]
+    fn files_without_a_track_gain_have_no_replay_gain() {
+        assert_eq!(apply(&[(Some(StandardTagKey::ReplayGainAlbumGain), "REPLAYGAIN_ALBUM_GAIN", "-3 dB")]).replay_gain(), None);
+    }
+}
@@ -0,0 +1,214 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::music::{Song, MAX_RATING};
+
+/// A song along with the bookkeeping shown in the library view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryEntry {
+    pub song: Song,
+    // Each of the song's artists, in credited order
+    pub artists: Vec<String>,
+    pub album_id: Option<usize>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    // "YYYY-MM-DD HH:MM:SS" in UTC, or None for songs cached before this was recorded
+    pub added: Option<String>,
+    pub plays: usize
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortColumn {
+    Title,
+    Artist,
+    Album,
+    Duration,
+    Added,
+    Plays,
+    Rating
+}
+
+impl SortColumn {
+    pub const ALL: [SortColumn; 7] = [Self::Title, Self::Artist, Self::Album, Self::Duration, Self::Added, Self::Plays, Self::Rating];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Title => "Title",
+            Self::Artist => "Artist",
+            Self::Album => "Album",
+            Self::Duration => "Duration",
+            Self::Added => "Added",
+            Self::Plays => "Plays",
+            Self::Rating => "Rating"
+        }
+    }
+
+    fn compare(&self, a: &LibraryEntry, b: &LibraryEntry) -> Ordering {
+        let text = |a: &str, b: &str| a.to_lowercase().cmp(&b.to_lowercase());
+        match self {
+            Self::Title => text(&a.song.name, &b.song.name),
+            Self::Artist => text(&a.song.artist, &b.song.artist).then_with(|| Self::Album.compare(a, b)),
+            // Tracks without a number go after the numbered ones
+            Self::Album => text(&a.song.album, &b.song.album).then_with(|| a.track.unwrap_or(u32::MAX).cmp(&b.track.unwrap_or(u32::MAX))),
+            Self::Duration => a.song.duration.cmp(&b.song.duration),
+            // Songs without a date were cached first, and ids follow insertion order
+            Self::Added => a.added.cmp(&b.added).then_with(|| a.song.sql_id.cmp(&b.song.sql_id)),
+            Self::Plays => a.plays.cmp(&b.plays),
+            // Favourites go above other songs with the same rating
+            Self::Rating => a.song.rating.cmp(&b.song.rating).then_with(|| a.song.favourite.cmp(&b.song.favourite))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DownloadFilter {
+    #[default]
+    All,
+    Downloaded,
+    NotDownloaded
+}
+
+impl DownloadFilter {
+    pub const ALL: [DownloadFilter; 3] = [Self::All, Self::Downloaded, Self::NotDownloaded];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::All => "All",
+            Self::Downloaded => "Downloaded",
+            Self::NotDownloaded => "Not Downloaded"
+        }
+    }
+
+    fn matches(&self, song: &Song) -> bool {
+        match self {
+            Self::All => true,
+            Self::Downloaded => song.file.is_some(),
+            Self::NotDownloaded => song.file.is_none()
+        }
+    }
+}
+
+/// How the library view is sorted, filtered and browsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryView {
+    pub sort: SortColumn,
+    pub descending: bool,
+    pub filter: String,
+    pub downloaded: DownloadFilter,
+    pub artist: Option<String>,
+    pub album: Option<usize>
+}
+
+impl Default for LibraryView {
+    fn default() -> Self {
+        Self { sort: SortColumn::Artist, descending: false, filter: String::new(), downloaded: DownloadFilter::All, artist: None, album: None }
+    }
+}
+
+impl LibraryView {
+    /// Sorts by a column, flipping the direction if it is already sorted by it.
+    pub fn sort_by(&mut self, column: SortColumn) {
+        if self.sort == column {
+            self.descending = !self.descending;
+        } else {
+            self.sort = column;
+            // Numbers are most useful largest first
+            self.descending = matches!(column, SortColumn::Added | SortColumn::Plays | SortColumn::Rating);
+        }
+    }
+
+    pub fn browse_artist(&mut self, artist: Option<String>) {
+        self.artist = artist;
+        self.album = None;
+    }
+
+    /// Songs that pass the text, rating and download filters, before browsing narrows them down.
+    fn filtered<'a>(&'a self, entries: &'a [LibraryEntry]) -> impl Iterator<Item = &'a LibraryEntry> + 'a {
+        let (preference, filter) = PreferenceFilter::parse(&self.filter);
+        let filter = filter.to_lowercase();
+        entries.iter().filter(move |entry| {
+            let song = &entry.song;
+            self.downloaded.matches(song)
+                && preference.matches(song)
+                && (filter.is_empty() || [&song.name, &song.artist, &song.album].iter().any(|field| field.to_lowercase().contains(&filter)))
+        })
+    }
+
+    fn by_artist(&self, entry: &LibraryEntry) -> bool {
+        self.artist.as_ref().is_none_or(|artist| entry.artists.contains(artist))
+    }
+
+    /// Every artist with the number of matching songs, alphabetically. Songs with several artists
+    /// count towards each of them.
+    pub fn artists(&self, entries: &[LibraryEntry]) -> Vec<(String, String, usize)> {
+        count_by(self.filtered(entries).flat_map(|entry| entry.artists.iter().map(|artist| (artist.clone(), artist.clone()))))
+    }
+
+    /// Every album by the selected artist, or by anyone if no artist is selected.
+    pub fn albums(&self, entries: &[LibraryEntry]) -> Vec<(usize, String, usize)> {
+        count_by(self.filtered(entries)
+            .filter(|entry| self.by_artist(entry))
+            .filter_map(|entry| entry.album_id.map(|id| (id, match entry.year {
+                Some(year) => format!("{} ({year})", entry.song.album),
+                None => entry.song.album.clone()
+            }))))
+    }
+
+    /// The songs to list, sorted.
+    pub fn songs(&self, entries: &[LibraryEntry]) -> Vec<LibraryEntry> {
+        let mut songs: Vec<LibraryEntry> = self.filtered(entries)
+            .filter(|entry| self.by_artist(entry))
+            .filter(|entry| self.album.is_none_or(|album| entry.album_id == Some(album)))
+            .cloned()
+            .collect();
+
+        songs.sort_by(|a, b| {
+            let ordering = self.sort.compare(a, b);
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            ordering.then_with(|| SortColumn::Title.compare(a, b))
+        });
+        songs
+    }
+}
+
+/// The `rating:N` and `is:liked` terms of a search, which filter on how songs are rated rather
+/// than on their text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PreferenceFilter {
+    pub min_rating: u8,
+    pub liked: bool
+}
+
+impl PreferenceFilter {
+    /// Takes the preference terms out of a search, returning them along with the rest of it.
+    pub fn parse(input: &str) -> (Self, String) {
+        let mut preference = Self::default();
+        let rest = input.split_whitespace().filter(|word| match word.split_once(':') {
+            Some((field, value)) if field.eq_ignore_ascii_case("rating") => match value.trim_end_matches('+').parse::<u8>() {
+                Ok(rating) => { preference.min_rating = rating.min(MAX_RATING); false }
+                Err(_) => true
+            },
+            Some((field, value)) if field.eq_ignore_ascii_case("is") && value.eq_ignore_ascii_case("liked") => { preference.liked = true; false }
+            _ => true
+        }).collect::<Vec<&str>>().join(" ");
+        (preference, rest)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_rating == 0 && !self.liked
+    }
+
+    pub fn matches(&self, song: &Song) -> bool {
+        song.rating >= self.min_rating && (song.favourite || !self.liked)
+    }
+}
+
+/// Counts how many times each key appears, returning the keys with their labels sorted by label.
+fn count_by<K: Eq + Hash>(values: impl Iterator<Item = (K, String)>) -> Vec<(K, String, usize)> {
+    let mut counts: HashMap<K, (String, usize)> = HashMap::new();
+    values.for_each(|(key, label)| counts.entry(key).or_insert((label, 0)).1 += 1);
+    let mut counts: Vec<(K, String, usize)> = counts.into_iter().map(|(key, (label, count))| (key, label, count)).collect();
+    counts.sort_by_key(|(_, label, _)| label.to_lowercase());
+    counts
+}
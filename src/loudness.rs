@@ -0,0 +1,325 @@
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use crate::audio::{decode_file, MIX_CHANNELS, MIX_SAMPLE_RATE};
+use crate::dsp::Biquad;
+use crate::error::ResonateResult;
+use crate::filemanager::Database;
+use crate::importer::read_file_tags;
+use crate::utility::*;
+
+/// The loudness ReplayGain 2.0 normalises to, in LUFS.
+pub const REFERENCE_LOUDNESS: f32 = -18f32;
+
+/// Blocks quieter than this are silence and never count towards loudness.
+const ABSOLUTE_GATE: f64 = -70f64;
+/// Blocks this far below the ungated loudness are ignored, so quiet passages do not drag it down.
+const RELATIVE_GATE: f64 = -10f64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    Off,
+    #[default]
+    Track,
+    Album
+}
+
+impl Normalization {
+    pub const ALL: [Normalization; 3] = [Self::Off, Self::Track, Self::Album];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Track => "track",
+            Self::Album => "album"
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|normalization| normalization.key() == key)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Track => "Track",
+            Self::Album => "Album"
+        }
+    }
+}
+
+/// A song's gains in dB relative to `REFERENCE_LOUDNESS`, with peaks as linear sample amplitude.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: f32,
+    pub track_peak: f32,
+    // Missing until every song on the album has been measured
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>
+}
+
+// Gains are parsed or measured, so they are never NaN
+impl Eq for ReplayGain {}
+
+impl Hash for ReplayGain {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.track_gain.to_bits().hash(state);
+        self.track_peak.to_bits().hash(state);
+        self.album_gain.map(f32::to_bits).hash(state);
+        self.album_peak.map(f32::to_bits).hash(state);
+    }
+}
+
+impl ReplayGain {
+    /// What to scale samples by, lowered if needed so that the loudest sample does not clip.
+    /// Songs without an album gain fall back to their track gain.
+    pub fn factor(&self, normalization: Normalization) -> f32 {
+        let (gain, peak) = match normalization {
+            Normalization::Off => return 1f32,
+            Normalization::Track => (self.track_gain, self.track_peak),
+            Normalization::Album => (self.album_gain.unwrap_or(self.track_gain), self.album_peak.unwrap_or(self.track_peak))
+        };
+        let factor = 10f32.powf(gain / 20f32);
+        if peak > 0f32 { factor.min(1f32 / peak) } else { factor }
+    }
+}
+
+/// The gain and peak of an album, from the gains, peaks and durations of its songs. Each song's
+/// loudness counts in proportion to its length, as if the album were measured in one go.
+pub fn album_gain(songs: &[(f32, f32, usize)]) -> (f32, f32) {
+    let total: f64 = songs.iter().map(|(_, _, duration)| (*duration).max(1) as f64).sum();
+    let energy: f64 = songs.iter()
+        .map(|(gain, _, duration)| (*duration).max(1) as f64 * 10f64.powf((REFERENCE_LOUDNESS - gain) as f64 / 10f64))
+        .sum::<f64>() / total;
+    let peak = songs.iter().map(|(_, peak, _)| *peak).fold(0f32, f32::max);
+    (REFERENCE_LOUDNESS - (10f64 * energy.log10()) as f32, peak)
+}
+
+/// The two stages of the BS.1770 K-weighting filter, a high shelf modelling the head followed by a
+/// high-pass, worked out for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20f64);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1f64 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2f64 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [1f64, 2f64 * (k * k - 1f64) / a0, (1f64 - k / q + k * k) / a0]
+    );
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1f64 + k / q + k * k;
+    let high_pass = Biquad::new([1f64, -2f64, 1f64], [1f64, 2f64 * (k * k - 1f64) / a0, (1f64 - k / q + k * k) / a0]);
+
+    [shelf, high_pass]
+}
+
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10f64 * power.log10()
+}
+
+/// Measures integrated loudness as EBU R128 defines it, along with the sample peak. Power is
+/// collected in 100ms steps, and 400ms blocks are built from four of them so that the blocks
+/// overlap by 75%.
+struct LoudnessMeter {
+    filters: Vec<[Biquad; 2]>,
+    channel: usize,
+    step_frames: usize,
+    frames: usize,
+    power: f64,
+    steps: Vec<f64>,
+    peak: f32
+}
+
+impl LoudnessMeter {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            filters: vec![k_weighting(sample_rate); channels as usize],
+            channel: 0,
+            step_frames: sample_rate as usize / 10,
+            frames: 0,
+            power: 0f64,
+            steps: Vec::new(),
+            peak: 0f32
+        }
+    }
+
+    /// Takes interleaved samples one at a time.
+    fn push(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        let [shelf, high_pass] = &mut self.filters[self.channel];
+        let weighted = high_pass.process(shelf.process(sample as f64));
+        // Front channels all have a weighting of 1
+        self.power += weighted * weighted;
+
+        self.channel += 1;
+        if self.channel == self.filters.len() {
+            self.channel = 0;
+            self.frames += 1;
+            if self.frames == self.step_frames {
+                self.steps.push(self.power / self.step_frames as f64);
+                self.frames = 0;
+                self.power = 0f64;
+            }
+        }
+    }
+
+    /// The integrated loudness in LUFS, or None if there was nothing but silence.
+    fn finish(&self) -> Option<f64> {
+        let blocks: Vec<f64> = self.steps.windows(4)
+            .map(|steps| steps.iter().sum::<f64>() / 4f64)
+            .filter(|power| block_loudness(*power) > ABSOLUTE_GATE)
+            .collect();
+        if blocks.is_empty() { return None }
+
+        let threshold = block_loudness(blocks.iter().sum::<f64>() / blocks.len() as f64) + RELATIVE_GATE;
+        let gated: Vec<f64> = blocks.into_iter().filter(|power| block_loudness(*power) > threshold).collect();
+        Some(block_loudness(gated.iter().sum::<f64>() / gated.len() as f64))
+    }
+
+    /// The track gain and peak of everything pushed so far.
+    fn gain(&self) -> ReplayGain {
+        // Silence is left alone rather than amplified without limit
+        let loudness = self.finish().map(|loudness| loudness as f32).unwrap_or(REFERENCE_LOUDNESS);
+        ReplayGain { track_gain: REFERENCE_LOUDNESS - loudness, track_peak: self.peak, album_gain: None, album_peak: None }
+    }
+}
+
+/// A file's gains, from its ReplayGain tags if it has them and measured otherwise.
+pub fn analyse_file(path: &Path) -> ResonateResult<ReplayGain> {
+    if let Some(gain) = read_file_tags(path)?.replay_gain() {
+        return Ok(gain)
+    }
+
+    let mut meter = LoudnessMeter::new(MIX_CHANNELS, MIX_SAMPLE_RATE);
+    decode_file(path)?.for_each(|sample| meter.push(sample));
+    Ok(meter.gain())
+}
+
+/// Measures every downloaded song without a gain, then works out the gains of albums whose songs
+/// have all been measured. Returns the new gains of every song that changed.
+pub fn scan_loudness(database: AM<Database>) -> ResonateResult<Vec<(usize, ReplayGain)>> {
+    let songs = database.lock().unwrap().retrieve_songs_without_gain()?;
+    println!("[LOUDNESS] Analysing {} songs", songs.len());
+
+    let mut changed = Vec::new();
+    for song in songs {
+        let path = match song.file.as_ref() {
+            Some(path) => path,
+            None => continue
+        };
+        // The database is only locked to store each result, since analysing a song takes a while
+        match analyse_file(path) {
+            Ok(gain) => {
+                database.lock().unwrap().set_replay_gain(song.sql_id, &gain)?;
+                changed.push(song.sql_id);
+            }
+            // Marked so that a broken file is not decoded again on every scan
+            Err(e) => {
+                println!("[LOUDNESS] Could not analyse {}: {e}", song.name);
+                database.lock().unwrap().set_gain_failed(song.sql_id)?;
+            }
+        }
+    }
+
+    let database = database.lock().unwrap();
+    changed.extend(database.update_album_gains()?);
+    changed.sort_unstable();
+    changed.dedup();
+    Ok(changed.into_iter()
+        .filter_map(|id| database.load_song_by_id(id).ok())
+        .filter_map(|song| song.gain.map(|gain| (song.sql_id, gain)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsp::test_signal::{sine, SAMPLE_RATE};
+    use crate::music::Song;
+
+    use super::*;
+
+    fn measure(samples: &[f32]) -> LoudnessMeter {
+        let mut meter = LoudnessMeter::new(2, SAMPLE_RATE);
+        samples.iter().for_each(|sample| meter.push(*sample));
+        meter
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() < tolerance, "expected about {expected}, got {actual}");
+    }
+
+    #[test]
+    fn measures_a_sine_of_known_level() {
+        // A 997Hz sine at full scale in one channel reads -3.01 LUFS, so one at half scale in both
+        // channels reads 6.02dB lower and 3.01dB higher
+        let meter = measure(&sine(997f32, 5 * SAMPLE_RATE as usize));
+        assert_close(meter.finish().unwrap() as f32, -6.02, 0.05);
+
+        let gain = meter.gain();
+        assert_close(gain.track_gain, REFERENCE_LOUDNESS + 6.02, 0.05);
+        assert_close(gain.track_peak, 0.5, 0.001);
+        assert_eq!(gain.album_gain, None);
+    }
+
+    #[test]
+    fn quiet_passages_are_gated() {
+        // A stretch 60dB down, as long as the sine, barely changes the loudness. Only the blocks
+        // that straddle the change get past the gate.
+        let mut samples = sine(997f32, 5 * SAMPLE_RATE as usize);
+        samples.extend(sine(997f32, 5 * SAMPLE_RATE as usize).into_iter().map(|sample| sample * 0.001));
+        assert_close(measure(&samples).finish().unwrap() as f32, -6.02, 0.2);
+    }
+
+    #[test]
+    fn silence_is_left_alone() {
+        let meter = measure(&vec![0f32; 2 * SAMPLE_RATE as usize]);
+        assert_eq!(meter.finish(), None);
+        assert_eq!(meter.gain().track_gain, 0f32);
+    }
+
+    #[test]
+    fn factors_never_clip() {
+        let gain = ReplayGain { track_gain: 12f32, track_peak: 0.5, album_gain: Some(-6f32), album_peak: Some(0.9) };
+        assert_eq!(gain.factor(Normalization::Off), 1f32);
+        assert_eq!(gain.factor(Normalization::Track), 2f32);
+        assert_close(gain.factor(Normalization::Album), 10f32.powf(-6f32 / 20f32), 0.0001);
+    }
+
+    #[test]
+    fn album_gain_weights_songs_by_length() {
+        assert_eq!(album_gain(&[(-6f32, 0.5, 200), (-6f32, 0.8, 100)]), (-6f32, 0.8));
+        // A long song at the reference outweighs a short one that is 10dB louder
+        let (gain, _) = album_gain(&[(0f32, 1f32, 300), (-10f32, 1f32, 10)]);
+        assert_close(gain, -1.1, 0.05);
+    }
+
+    #[test]
+    fn failed_songs_are_skipped_and_do_not_hold_up_their_album() {
+        let database = Database::in_memory().unwrap();
+        let mut songs = ["One", "Two"].map(|name| Song::new(0, name.to_string(), String::from("Artist"), String::from("Album"),
+            name.to_string(), 100, Some(std::env::temp_dir().join(format!("{name}.mp3")))));
+        songs.iter_mut().for_each(|song| database.add_song_to_cache(song).unwrap());
+
+        let gain = ReplayGain { track_gain: -4f32, track_peak: 0.7, album_gain: None, album_peak: None };
+        database.set_replay_gain(songs[0].sql_id, &gain).unwrap();
+        database.set_gain_failed(songs[1].sql_id).unwrap();
+        assert!(database.retrieve_songs_without_gain().unwrap().is_empty());
+
+        assert_eq!(database.update_album_gains().unwrap(), vec![songs[0].sql_id]);
+        let album = database.load_song_by_id(songs[0].sql_id).unwrap().gain.unwrap();
+        assert_eq!(album.album_gain, Some(-4f32));
+        assert_eq!(database.load_song_by_id(songs[1].sql_id).unwrap().gain, None);
+        // Nothing is left to work out on the next scan
+        assert!(database.update_album_gains().unwrap().is_empty());
+
+        // A new file is analysed again
+        database.update(songs[1].clone()).unwrap();
+        assert_eq!(database.retrieve_songs_without_gain().unwrap(), vec![songs[1].clone()]);
+    }
+}
@@ -1,18 +1,37 @@
 mod application;
 mod filemanager;
 mod downloader;
+mod download_manager;
 mod utility;
 mod widgets;
 mod music;
+mod search;
+mod tagger;
+mod importer;
+mod watcher;
+mod playlist_file;
+mod library;
+mod statistics;
+mod smart_playlist;
+mod loudness;
+mod dsp;
+mod equalizer;
+mod stretch;
 mod audio;
+mod error;
 
 use application::Message;
 use iced::Task;
 
-use crate::application::Application;
+use crate::application::Resonate;
 
 fn main() -> iced::Result {
-    iced::application("Resonate", Application::update, Application::view)
-        .subscription(|_| Application::keyboard_subscription())
-        .run_with(|| (Application::default(), Task::done(Message::CheckAllIcons)))
+    iced::application("Resonate", Resonate::update, Resonate::view)
+        .subscription(Resonate::subscription)
+        .exit_on_close_request(false)
+        .run_with(|| (Resonate::start(), Task::batch(vec![
+            Task::done(Message::RestoreSession),
+            Task::done(Message::CheckAllIcons),
+            Task::done(Message::ScanLoudness)
+        ])))
 }
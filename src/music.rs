@@ -1,8 +1,12 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::application::Message;
 use crate::filemanager::Database;
-use crate::downloader::search_youtube_music;
+use crate::loudness::ReplayGain;
+use crate::smart_playlist::SmartPlaylist;
+use crate::search::{fetch_remote_playlist, SearchHit, SearchProvider};
+use crate::error::{ResonateError, ResonateResult};
 use crate::utility::*;
 
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
@@ -13,48 +17,105 @@ pub struct Song {
     pub album: String,
     pub id: String,
     pub duration: usize,
-    pub file: Option<PathBuf>
+    pub file: Option<PathBuf>,
+    // Stars out of `MAX_RATING`, 0 if unrated
+    pub rating: u8,
+    pub favourite: bool,
+    // None until the song has been analysed
+    pub gain: Option<ReplayGain>,
+    // The preset the equalizer is overridden with, the playlist's if the song has none of its own
+    pub equalizer: Option<String>
 }
 
+/// The highest star rating a song can be given.
+pub const MAX_RATING: u8 = 5;
+
 impl Song {
     pub fn new(sql_id: usize, name: String, artist: String, album: String, id: String, duration: usize, file: Option<PathBuf>) -> Self {
-        Self { sql_id, name, artist, album, id, duration, file }
+        Self { sql_id, name, artist, album, id, duration, file, rating: 0, favourite: false, gain: None, equalizer: None }
     }
 }
 
-pub async fn local_search(query: String, database: AM<Database>) -> Message {
-    println!("[LOCAL] Thread started.");
-    let database = database.lock().unwrap();
-    Message::SearchResults(database.search_cached_song(query))
+/// Runs a single search provider, reporting its results tagged with the provider's source.
+pub async fn provider_search(provider: Arc<dyn SearchProvider>, query: String, database: AM<Database>) -> Message {
+    let source = provider.source();
+    println!("[SEARCH] {} thread started.", source.label());
+    match search_and_cache(provider, query, database).await {
+        Ok(songs) => Message::SearchResults(songs.into_iter().map(|song| SearchHit { song, sources: vec![source] }).collect()),
+        Err(e) => Message::SearchFailed(e)
+    }
 }
 
-pub async fn cloud_search(query: String, database: AM<Database>) -> Message {
-    println!("[CLOUD] Thread started.");
+async fn search_and_cache(provider: Arc<dyn SearchProvider>, query: String, database: AM<Database>) -> ResonateResult<Vec<Song>> {
+    let directory = database.lock().unwrap().get_directory();
+    let source = provider.source();
 
-    // Find directory and get a HashSet of all cached songs then implicitly drop mutex
-    let (directory, db_hash) = {
-        let database = database.lock().unwrap();
-        (database.get_directory(), database.hash_all_songs())
-    };
-
-    // Async blocking call to search youtube music, this should take about 5-10 seconds
+    // Online searches take a few seconds through the API and folder searches walk the disk, so
+    // every provider runs off the UI thread
     let mut results: Vec<Song> = tokio::task::spawn_blocking(move || {
-        search_youtube_music(query, directory)
-            .unwrap()
+        provider.search(&query).map(|results| results
             .into_iter()
-            .filter(|song| !db_hash.contains(&song.id))
-            .collect()
-    }).await.unwrap();
+            .map(|result| result.into_song(&directory))
+            .collect::<Vec<Song>>())
+    }).await.map_err(|e| ResonateError::Scrape(e.to_string()))??;
+
+    if !source.is_online() { return Ok(results); }
+
+    cache_songs(&database.lock().unwrap(), &mut results)?;
+    Ok(results)
+}
+
+/// Swaps in songs that are already cached and caches the new ones.
+fn cache_songs(database: &Database, songs: &mut [Song]) -> ResonateResult<()> {
+    for song in songs.iter_mut() {
+        match database.find_song_by_ytid(&song.id)? {
+            Some(cached) => *song = cached,
+            None => database.add_song_to_cache(song)?
+        }
+    }
+    Ok(())
+}
+
+/// Creates a playlist from a YouTube Music playlist or album with every track in its original
+/// order. Tracks are cached but not downloaded.
+pub async fn import_remote_playlist(url: String, database: AM<Database>) -> ResonateResult<Playlist> {
+    let directory = database.lock().unwrap().get_directory();
+    let (name, mut songs, is_album) = tokio::task::spawn_blocking(move || {
+        fetch_remote_playlist(&url).map(|(name, results, is_album)| (name, results
+            .into_iter()
+            .map(|result| result.into_song(&directory))
+            .collect::<Vec<Song>>(), is_album))
+    }).await.map_err(|e| ResonateError::Scrape(e.to_string()))??;
 
-    // Relock the mutex in order to cache the new songs
     let database = database.lock().unwrap();
-    database.add_songs_to_cache(&mut results);
-    Message::SearchResults(results)
+    cache_songs(&database, &mut songs)?;
+    if is_album {
+        // Albums are listed in track order
+        songs.iter().enumerate().try_for_each(|(index, song)| database.set_track_info(song.sql_id, None, Some(index as u32 + 1)))?;
+    }
+    let mut playlist = database.create_playlist(name)?;
+    songs.iter().try_for_each(|song| database.add_song_to_playlist(song, &mut playlist))?;
+    println!("[IMPORT] Created {} with {} songs", playlist.name, songs.len());
+    Ok(playlist)
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Playlist {
     pub id: usize,
     pub name: String,
-    pub songs: Option<Vec<Song>>
+    pub songs: Option<Vec<Song>>,
+    // The rules of a smart playlist, filled in when the playlist is loaded
+    pub smart: Option<SmartPlaylist>,
+    // The preset the equalizer is overridden with for songs played from this playlist
+    pub equalizer: Option<String>
+}
+
+/// The id of the Liked Songs playlist, which has no row of its own since SQLite ids start at 1.
+pub const LIKED_PLAYLIST_ID: usize = 0;
+
+impl Playlist {
+    /// Every favourite song, kept up to date by evaluating it like a smart playlist.
+    pub fn liked() -> Self {
+        Self { id: LIKED_PLAYLIST_ID, name: String::from("Liked Songs"), songs: None, smart: None, equalizer: None }
+    }
 }
@@ -0,0 +1,526 @@
+use std::path::{Component, Path, PathBuf};
+
+use url::Url;
+
+use crate::error::{ResonateError, ResonateResult};
+use crate::filemanager::Database;
+use crate::importer::import_file;
+use crate::music::{Playlist, Song};
+use crate::utility::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            _ => None
+        }
+    }
+}
+
+/// A single entry read from a playlist file. Every field is optional because the formats allow
+/// anything from a bare path to full metadata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlaylistEntry {
+    // A file path, file:// URL or web URL exactly as written in the file
+    pub location: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<usize>
+}
+
+impl PlaylistEntry {
+    /// Fills in the artist from an "Artist - Title" title, which M3U and PLS use in place of
+    /// separate fields.
+    fn split_title(mut self) -> Self {
+        if self.artist.is_none() {
+            if let Some((artist, title)) = self.title.as_ref().and_then(|title| title.split_once(" - ")) {
+                let (artist, title) = (artist.trim().to_string(), title.trim().to_string());
+                self.artist = Some(artist);
+                self.title = Some(title);
+            }
+        }
+        self
+    }
+
+    /// Text to search for when the entry could not be matched to a song.
+    pub fn query(&self) -> String {
+        match (self.artist.as_ref(), self.title.as_ref()) {
+            (Some(artist), Some(title)) => format!("{artist} {title}"),
+            (None, Some(title)) => title.clone(),
+            _ => self.location.as_ref()
+                .and_then(|location| Path::new(location).file_stem().map(|stem| stem.to_string_lossy().to_string()))
+                .unwrap_or_default()
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match (self.artist.as_ref(), self.title.as_ref()) {
+            (Some(artist), Some(title)) => format!("{artist} - {title}"),
+            _ => self.query()
+        }
+    }
+}
+
+fn display_title(song: &Song) -> String {
+    if song.artist.is_empty() { song.name.clone() } else { format!("{} - {}", song.artist, song.name) }
+}
+
+/// Path from a directory to a file, falling back to the absolute path when they share no root.
+fn relative_path(base: &Path, target: &Path) -> PathBuf {
+    let base: Vec<Component> = base.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = base.iter().zip(target.iter()).take_while(|(a, b)| a == b).count();
+    if common == 0 { return target.iter().collect(); }
+
+    let mut path = PathBuf::new();
+    (common..base.len()).for_each(|_| path.push(".."));
+    target[common..].iter().for_each(|component| path.push(component));
+    path
+}
+
+/// Where an exported entry points. Songs that are not downloaded point at YouTube Music so that
+/// other players can still stream them and resonate can match them on the way back in.
+fn song_location(song: &Song, base: &Path, relative: bool) -> String {
+    match song.file.as_ref() {
+        Some(file) if relative => relative_path(base, file).to_string_lossy().to_string(),
+        Some(file) => file.to_string_lossy().to_string(),
+        None => format!("https://music.youtube.com/watch?v={}", song.id)
+    }
+}
+
+fn xspf_location(song: &Song, base: &Path, relative: bool) -> String {
+    let file = match song.file.as_ref() {
+        Some(file) => file,
+        None => return song_location(song, base, relative)
+    };
+    let absolute = Url::from_file_path(file).ok();
+    let relative_url = if relative {
+        Url::from_directory_path(base).ok().zip(absolute.as_ref()).and_then(|(base, absolute)| base.make_relative(absolute))
+    } else { None };
+    relative_url
+        .or(absolute.map(|url| url.to_string()))
+        .unwrap_or_else(|| file.to_string_lossy().to_string())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+/// Writes a playlist in the format given by the file's extension. Relative paths are relative to
+/// the folder the playlist is written to.
+pub fn export_playlist(playlist: &Playlist, path: &Path, relative: bool) -> ResonateResult<()> {
+    let format = PlaylistFormat::from_path(path)
+        .ok_or(ResonateError::Io(format!("{} is not an .m3u8, .pls or .xspf file", path.display())))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    let songs = playlist.songs.clone().unwrap_or_default();
+
+    let contents = match format {
+        PlaylistFormat::M3u => {
+            let mut lines = vec![String::from("#EXTM3U"), format!("#PLAYLIST:{}", playlist.name)];
+            for song in songs.iter() {
+                lines.push(format!("#EXTINF:{},{}", song.duration, display_title(song)));
+                lines.push(song_location(song, base, relative));
+            }
+            lines.join("\n")
+        }
+
+        PlaylistFormat::Pls => {
+            let mut lines = vec![String::from("[playlist]")];
+            for (index, song) in songs.iter().enumerate() {
+                let number = index + 1;
+                lines.push(format!("File{number}={}", song_location(song, base, relative)));
+                lines.push(format!("Title{number}={}", display_title(song)));
+                lines.push(format!("Length{number}={}", song.duration));
+            }
+            lines.push(format!("NumberOfEntries={}", songs.len()));
+            lines.push(String::from("Version=2"));
+            lines.join("\n")
+        }
+
+        PlaylistFormat::Xspf => {
+            let mut lines = vec![
+                String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#),
+                String::from(r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#),
+                format!("  <title>{}</title>", escape_xml(&playlist.name)),
+                String::from("  <trackList>")
+            ];
+            for song in songs.iter() {
+                lines.push(String::from("    <track>"));
+                lines.push(format!("      <location>{}</location>", escape_xml(&xspf_location(song, base, relative))));
+                lines.push(format!("      <title>{}</title>", escape_xml(&song.name)));
+                lines.push(format!("      <creator>{}</creator>", escape_xml(&song.artist)));
+                lines.push(format!("      <album>{}</album>", escape_xml(&song.album)));
+                // XSPF durations are in milliseconds
+                lines.push(format!("      <duration>{}</duration>", song.duration * 1000));
+                lines.push(String::from("    </track>"));
+            }
+            lines.push(String::from("  </trackList>"));
+            lines.push(String::from("</playlist>"));
+            lines.join("\n")
+        }
+    };
+
+    std::fs::write(path, contents + "\n")?;
+    Ok(())
+}
+
+fn parse_m3u(contents: &str) -> (Option<String>, Vec<PlaylistEntry>) {
+    let mut name = None;
+    let mut entries = Vec::new();
+    let mut pending = PlaylistEntry::default();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (duration, title) = info.split_once(',').unwrap_or((info, ""));
+            // Negative durations mean unknown
+            pending.duration = duration.trim().parse::<i64>().ok().filter(|duration| *duration >= 0).map(|duration| duration as usize);
+            pending.title = Some(title.trim().to_string()).filter(|title| !title.is_empty());
+        } else if let Some(playlist) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(playlist.trim().to_string());
+        } else if !line.starts_with('#') {
+            pending.location = Some(line.to_string());
+            entries.push(std::mem::take(&mut pending).split_title());
+        }
+    }
+    (name, entries)
+}
+
+fn parse_pls(contents: &str) -> Vec<PlaylistEntry> {
+    let mut entries: Vec<PlaylistEntry> = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_string()),
+            None => continue
+        };
+
+        let (field, number) = match ["file", "title", "length"].iter().find(|field| key.starts_with(**field)) {
+            Some(field) => (*field, key[field.len()..].parse::<usize>().ok()),
+            None => continue
+        };
+        let index = match number {
+            Some(number) if number > 0 => number - 1,
+            _ => continue
+        };
+
+        if entries.len() <= index { entries.resize(index + 1, PlaylistEntry::default()); }
+        let entry = &mut entries[index];
+        match field {
+            "file" => entry.location = Some(value),
+            "title" => entry.title = Some(value).filter(|title| !title.is_empty()),
+            _ => entry.duration = value.parse::<i64>().ok().filter(|duration| *duration >= 0).map(|duration| duration as usize)
+        }
+    }
+    entries.into_iter().filter(|entry| entry.location.is_some()).map(PlaylistEntry::split_title).collect()
+}
+
+fn parse_xspf(contents: &str) -> ResonateResult<(Option<String>, Vec<PlaylistEntry>)> {
+    let document = roxmltree::Document::parse(contents).map_err(|e| ResonateError::Decode(e.to_string()))?;
+    let child_text = |node: roxmltree::Node, name: &str| node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+
+    let root = document.root_element();
+    let entries = root.descendants()
+        .filter(|node| node.has_tag_name("track"))
+        .map(|track| PlaylistEntry {
+            location: child_text(track, "location"),
+            title: child_text(track, "title"),
+            artist: child_text(track, "creator"),
+            duration: child_text(track, "duration").and_then(|duration| duration.parse::<usize>().ok()).map(|duration| duration / 1000)
+        })
+        .collect();
+    Ok((child_text(root, "title"), entries))
+}
+
+/// Reads a playlist file, returning its name and entries. The name falls back to the file name.
+pub fn read_playlist_file(path: &Path) -> ResonateResult<(String, Vec<PlaylistEntry>)> {
+    let format = PlaylistFormat::from_path(path)
+        .ok_or(ResonateError::Io(format!("{} is not an .m3u, .m3u8, .pls or .xspf file", path.display())))?;
+    let contents = String::from_utf8_lossy(&std::fs::read(path)?).trim_start_matches('\u{feff}').to_string();
+
+    let (name, entries) = match format {
+        PlaylistFormat::M3u => parse_m3u(&contents),
+        PlaylistFormat::Pls => (None, parse_pls(&contents)),
+        PlaylistFormat::Xspf => parse_xspf(&contents)?
+    };
+
+    let name = name.unwrap_or_else(|| path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default());
+    Ok((name, entries))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EntryLocation {
+    File(PathBuf),
+    YouTube(String),
+    Other
+}
+
+/// Interprets an entry's location relative to the folder the playlist was read from.
+pub fn resolve_location(location: &str, base: &Path) -> EntryLocation {
+    if let Ok(url) = Url::parse(location) {
+        return match url.scheme() {
+            "file" => url.to_file_path().map(EntryLocation::File).unwrap_or(EntryLocation::Other),
+            "http" | "https" => url.query_pairs()
+                .find(|(key, _)| key == "v")
+                .map(|(_, id)| EntryLocation::YouTube(id.to_string()))
+                .unwrap_or(EntryLocation::Other),
+            // Windows drive letters parse as a one letter scheme
+            scheme if scheme.len() == 1 => EntryLocation::File(PathBuf::from(location)),
+            _ => EntryLocation::Other
+        };
+    }
+
+    // XSPF locations are URIs, so relative ones may be percent encoded
+    let decoded = Url::from_directory_path(base).ok()
+        .and_then(|base_url| base_url.join(location).ok())
+        .and_then(|url| url.to_file_path().ok());
+    let path = base.join(location);
+    match decoded {
+        Some(decoded) if !path.exists() => EntryLocation::File(decoded),
+        _ => EntryLocation::File(path)
+    }
+}
+
+pub enum EntryMatch {
+    Song(Song),
+    // A file on disk that is not in the library yet
+    File(PathBuf),
+    Unmatched
+}
+
+/// Finds the song an entry refers to, first by its location and then by artist and title.
+pub fn match_entry(database: &Database, entry: &PlaylistEntry, base: &Path) -> ResonateResult<EntryMatch> {
+    let location = entry.location.as_ref().map(|location| resolve_location(location, base));
+
+    match location.as_ref() {
+        Some(EntryLocation::File(path)) => {
+            // Library files are named after the song's id
+            let in_library = path.parent() == Some(database.get_directory().as_path());
+            let by_id = match path.file_stem() {
+                Some(stem) if in_library => database.find_song_by_ytid(&stem.to_string_lossy())?,
+                _ => None
+            };
+            if let Some(song) = by_id.or(database.find_song_by_source(path)?) { return Ok(EntryMatch::Song(song)); }
+        }
+        Some(EntryLocation::YouTube(id)) => {
+            if let Some(song) = database.find_song_by_ytid(id)? { return Ok(EntryMatch::Song(song)); }
+        }
+        _ => {}
+    }
+
+    if let (Some(artist), Some(title)) = (entry.artist.as_ref(), entry.title.as_ref()) {
+        if let Some(song) = database.find_song_by_artist_title(artist, title)? { return Ok(EntryMatch::Song(song)); }
+    }
+
+    Ok(match location {
+        Some(EntryLocation::File(path)) if path.is_file() => EntryMatch::File(path),
+        _ => EntryMatch::Unmatched
+    })
+}
+
+/// Creates a playlist from a playlist file. Entries that point at files outside the library are
+/// imported; entries that match nothing are returned so the user can search for them.
+pub fn import_playlist(database: &AM<Database>, path: &Path) -> ResonateResult<(Playlist, Vec<PlaylistEntry>)> {
+    let (name, entries) = read_playlist_file(path)?;
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let matches = {
+        let database = database.lock().unwrap();
+        entries.into_iter()
+            .map(|entry| match_entry(&database, &entry, base).map(|found| (entry, found)))
+            .collect::<ResonateResult<Vec<(PlaylistEntry, EntryMatch)>>>()?
+    };
+
+    let mut songs = Vec::new();
+    let mut unmatched = Vec::new();
+    for (entry, found) in matches {
+        match found {
+            EntryMatch::Song(song) => songs.push(song),
+            EntryMatch::File(file) => match import_file(database, &file) {
+                Ok(outcome) => songs.push(outcome.into_song()),
+                Err(e) => {
+                    println!("[PLAYLIST] Failed to import {}: {e}", file.display());
+                    unmatched.push(entry);
+                }
+            },
+            EntryMatch::Unmatched => unmatched.push(entry)
+        }
+    }
+
+    let database = database.lock().unwrap();
+    let mut playlist = database.create_playlist(name)?;
+    songs.iter().try_for_each(|song| database.add_song_to_playlist(song, &mut playlist))?;
+    println!("[PLAYLIST] Imported {} with {} songs, {} unmatched", playlist.name, songs.len(), unmatched.len());
+    Ok((playlist, unmatched))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(location: &str, title: Option<&str>, artist: Option<&str>, duration: Option<usize>) -> PlaylistEntry {
+        PlaylistEntry {
+            location: Some(location.to_string()),
+            title: title.map(str::to_string),
+            artist: artist.map(str::to_string),
+            duration
+        }
+    }
+
+    /// A folder of its own for each test, emptied before use.
+    fn scratch(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("resonate-playlist-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn playlist(directory: &Path) -> Playlist {
+        Playlist {
+            id: 1,
+            name: String::from("Road & Trip"),
+            songs: Some(vec![
+                Song::new(1, String::from("Under Pressure"), String::from("Queen, David Bowie"), String::from("Hot Space"),
+                    String::from("a01QQZyl-_I"), 249, Some(directory.join("library").join("under pressure.mp3"))),
+                Song::new(2, String::from("Midnight Drive"), String::from("Lena Hart"), String::new(),
+                    String::from("Kd9JDZt7o4o"), 207, None)
+            ]),
+            smart: None,
+            equalizer: None
+        }
+    }
+
+    /// Exports and reads back a playlist, checking that every entry leads back to its song.
+    fn round_trip(extension: &str, relative: bool) {
+        let directory = scratch(&format!("{extension}-{relative}"));
+        let playlist = playlist(&directory);
+        let path = directory.join(format!("road trip.{extension}"));
+        export_playlist(&playlist, &path, relative).unwrap();
+
+        let (name, entries) = read_playlist_file(&path).unwrap();
+        // PLS files have no name of their own
+        assert_eq!(name, if extension == "pls" { "road trip" } else { "Road & Trip" });
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].title.as_deref(), Some("Under Pressure"));
+        assert_eq!(entries[0].artist.as_deref(), Some("Queen, David Bowie"));
+        assert_eq!(entries[0].duration, Some(249));
+        assert_eq!(resolve_location(entries[0].location.as_ref().unwrap(), &directory),
+            EntryLocation::File(directory.join("library").join("under pressure.mp3")));
+
+        assert_eq!(entries[1].title.as_deref(), Some("Midnight Drive"));
+        assert_eq!(entries[1].artist.as_deref(), Some("Lena Hart"));
+        assert_eq!(resolve_location(entries[1].location.as_ref().unwrap(), &directory), EntryLocation::YouTube(String::from("Kd9JDZt7o4o")));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn m3u_round_trips() {
+        round_trip("m3u8", true);
+        round_trip("m3u8", false);
+    }
+
+    #[test]
+    fn pls_round_trips() {
+        round_trip("pls", true);
+        round_trip("pls", false);
+    }
+
+    #[test]
+    fn xspf_round_trips() {
+        round_trip("xspf", true);
+        round_trip("xspf", false);
+    }
+
+    #[test]
+    fn parses_m3u() {
+        let (name, entries) = parse_m3u("
+            #EXTM3U
+            #PLAYLIST:Favourites
+            #EXTINF:207,Lena Hart - Midnight Drive
+            music/midnight.mp3
+            #EXTINF:-1,Live Stream
+            http://example.com/stream
+            # A comment
+            bare.flac
+        ");
+        assert_eq!(name.as_deref(), Some("Favourites"));
+        assert_eq!(entries, vec![
+            entry("music/midnight.mp3", Some("Midnight Drive"), Some("Lena Hart"), Some(207)),
+            entry("http://example.com/stream", Some("Live Stream"), None, None),
+            entry("bare.flac", None, None, None)
+        ]);
+    }
+
+    #[test]
+    fn parses_pls_with_sparse_numbering() {
+        let entries = parse_pls("
+            [playlist]
+            File1=one.mp3
+            Title1=First
+            Length1=-1
+            Title2=Has no file
+            FILE4=four.mp3
+            Length4=120
+            File0=ignored.mp3
+            NumberOfEntries=4
+            Version=2
+        ");
+        assert_eq!(entries, vec![
+            entry("one.mp3", Some("First"), None, None),
+            entry("four.mp3", None, None, Some(120))
+        ]);
+    }
+
+    #[test]
+    fn parses_xspf() {
+        let (name, entries) = parse_xspf(r#"<?xml version="1.0" encoding="UTF-8"?>
+            <playlist version="1" xmlns="http://xspf.org/ns/0/">
+              <title>Mix &amp; Match</title>
+              <trackList>
+                <track>
+                  <location>music/my%20song.mp3</location>
+                  <title>My Song</title>
+                  <creator>Someone</creator>
+                  <duration>207500</duration>
+                </track>
+                <track><title>No location</title></track>
+              </trackList>
+            </playlist>"#).unwrap();
+        assert_eq!(name.as_deref(), Some("Mix & Match"));
+        assert_eq!(entries[0], entry("music/my%20song.mp3", Some("My Song"), Some("Someone"), Some(207)));
+        assert_eq!(entries[1].location, None);
+        assert!(parse_xspf("<playlist>").is_err());
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path(Path::new("/home/user/playlists"), Path::new("/home/user/music/a.mp3")), PathBuf::from("../music/a.mp3"));
+        assert_eq!(relative_path(Path::new("/home/user"), Path::new("/home/user/music/a.mp3")), PathBuf::from("music/a.mp3"));
+        // Nothing in common, so the path is kept as it was
+        assert_eq!(relative_path(Path::new("playlists"), Path::new("/music/a.mp3")), PathBuf::from("/music/a.mp3"));
+    }
+
+    #[test]
+    fn resolves_locations() {
+        let base = Path::new("/nonexistent/playlists");
+        assert_eq!(resolve_location("music/my%20song.mp3", base), EntryLocation::File(base.join("music/my song.mp3")));
+        assert_eq!(resolve_location("music/a.mp3", base), EntryLocation::File(base.join("music/a.mp3")));
+        assert_eq!(resolve_location("file:///music/my%20song.mp3", base), EntryLocation::File(PathBuf::from("/music/my song.mp3")));
+        assert_eq!(resolve_location(r"C:\Music\a.mp3", base), EntryLocation::File(PathBuf::from(r"C:\Music\a.mp3")));
+        assert_eq!(resolve_location("https://music.youtube.com/watch?v=a01QQZyl-_I&list=x", base), EntryLocation::YouTube(String::from("a01QQZyl-_I")));
+        assert_eq!(resolve_location("https://example.com/stream", base), EntryLocation::Other);
+        assert_eq!(resolve_location("spotify:track:abc", base), EntryLocation::Other);
+    }
+}
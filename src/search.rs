@@ -0,0 +1,645 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use directories::UserDirs;
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+use crate::error::{ResonateError, ResonateResult};
+use crate::filemanager::Database;
+use crate::importer::{file_details, read_file_tags};
+use crate::music::Song;
+use crate::utility::*;
+
+const INNERTUBE_ENDPOINT: &str = "https://music.youtube.com/youtubei/v1/search?prettyPrint=false";
+const INNERTUBE_CLIENT_NAME: &str = "WEB_REMIX";
+const INNERTUBE_CLIENT_VERSION: &str = "1.20250101.01.00";
+
+// Restricts results to the "Songs" shelf
+const SONGS_FILTER: &str = "EgWKAQIIAWoMEA4QChADEAQQCRAF";
+
+/// Where a search result came from. Doubles as the badge shown next to results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SearchSource {
+    Library,
+    Folder,
+    YouTubeMusic,
+    YtDlp
+}
+
+impl SearchSource {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Library => "Library",
+            Self::Folder => "Folder",
+            Self::YouTubeMusic => "YouTube Music",
+            Self::YtDlp => "yt-dlp"
+        }
+    }
+
+    /// Online results are cached in the database as soon as they are found, like the original
+    /// cloud search. Folder results are only added once they are imported.
+    pub fn is_online(self) -> bool {
+        matches!(self, Self::YouTubeMusic | Self::YtDlp)
+    }
+}
+
+/// A single song returned by a search provider, before it is cached as a `Song`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    // Zero unless the song is already in the database
+    pub sql_id: usize,
+    pub id: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: String,
+    pub duration: usize,
+    pub thumbnail: Option<String>,
+    pub explicit: bool,
+    // A playable file that already exists, either in the library or in a music folder
+    pub file: Option<PathBuf>
+}
+
+impl SearchResult {
+    /// Converts the result into a `Song`, picking up the file if it is already downloaded.
+    pub fn into_song(self, directory: &Path) -> Song {
+        let path = directory.join(format!("{}.mp3", self.id));
+        let file = self.file.or(if path.exists() { Some(path) } else { None });
+        Song::new(self.sql_id, self.title, self.artists.join(", "), self.album, self.id, self.duration, file)
+    }
+
+    fn from_song(song: Song) -> Self {
+        Self {
+            sql_id: song.sql_id,
+            id: song.id,
+            title: song.name,
+            artists: vec![song.artist],
+            album: song.album,
+            duration: song.duration,
+            thumbnail: None,
+            explicit: false,
+            file: song.file
+        }
+    }
+}
+
+/// A song in the search view along with every source that returned it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub song: Song,
+    pub sources: Vec<SearchSource>
+}
+
+impl SearchHit {
+    /// Whether two hits describe the same song. Online providers share YouTube ids, otherwise
+    /// fall back to comparing the title and artist.
+    pub fn is_same_song(&self, song: &Song) -> bool {
+        if self.song.sql_id != 0 && self.song.sql_id == song.sql_id { return true; }
+        if self.song.id == song.id { return true; }
+        self.song.name.trim().eq_ignore_ascii_case(song.name.trim()) && self.song.artist.trim().eq_ignore_ascii_case(song.artist.trim())
+    }
+}
+
+/// Adds hits to a result list, merging the sources of songs that are already present.
+pub fn merge_hits(existing: &mut Vec<SearchHit>, hits: Vec<SearchHit>) {
+    for hit in hits {
+        match existing.iter_mut().find(|other| other.is_same_song(&hit.song)) {
+            Some(other) => {
+                hit.sources.into_iter().for_each(|source| if !other.sources.contains(&source) { other.sources.push(source) });
+                // Prefer the copy that is already in the library
+                if other.song.sql_id == 0 && hit.song.sql_id != 0 { other.song = hit.song; }
+            }
+            None => existing.push(hit)
+        }
+    }
+}
+
+/// A source of songs that can be searched by free text. Searches are blocking and are expected to
+/// be run from `spawn_blocking`.
+pub trait SearchProvider: Send + Sync {
+    fn source(&self) -> SearchSource;
+    fn search(&self, query: &str) -> ResonateResult<Vec<SearchResult>>;
+}
+
+/// Searches YouTube Music by calling the InnerTube JSON API that the web client itself uses.
+pub struct InnerTubeProvider {
+    client: Client,
+    endpoint: String
+}
+
+impl InnerTubeProvider {
+    pub fn new() -> ResonateResult<Self> {
+        Self::with_endpoint(INNERTUBE_ENDPOINT.to_string())
+    }
+
+    /// Points the provider at a different server, such as a local stub serving recorded responses.
+    pub fn with_endpoint(endpoint: String) -> ResonateResult<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(15))
+            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36")
+            .build()
+            .map_err(|e| ResonateError::Scrape(e.to_string()))?;
+        Ok(Self { client, endpoint })
+    }
+}
+
+impl SearchProvider for InnerTubeProvider {
+    fn source(&self) -> SearchSource { SearchSource::YouTubeMusic }
+
+    fn search(&self, query: &str) -> ResonateResult<Vec<SearchResult>> {
+        let body = json!({
+            "context": {
+                "client": {
+                    "clientName": INNERTUBE_CLIENT_NAME,
+                    "clientVersion": INNERTUBE_CLIENT_VERSION,
+                    "hl": "en",
+                    "gl": "US"
+                }
+            },
+            "query": query,
+            "params": SONGS_FILTER
+        });
+
+        let response = self.client.post(&self.endpoint)
+            .header("Origin", "https://music.youtube.com")
+            .json(&body)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ResonateError::Scrape(e.to_string()))?;
+
+        let value: Value = response.json().map_err(|e| ResonateError::Scrape(e.to_string()))?;
+        Ok(parse_search_response(&value))
+    }
+}
+
+/// Searches songs that are already cached in the database.
+pub struct LibraryProvider {
+    database: AM<Database>
+}
+
+impl LibraryProvider {
+    pub fn new(database: AM<Database>) -> Self {
+        Self { database }
+    }
+}
+
+impl SearchProvider for LibraryProvider {
+    fn source(&self) -> SearchSource { SearchSource::Library }
+
+    fn search(&self, query: &str) -> ResonateResult<Vec<SearchResult>> {
+        let database = self.database.lock().unwrap();
+        Ok(database.search_cached_song(query.to_string())?
+            .into_iter()
+            .map(SearchResult::from_song)
+            .collect())
+    }
+}
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "opus", "wav", "m4a", "aac"];
+
+/// Matches file names in a music folder on disk. Every word of the query has to appear in the
+/// file's path relative to the folder, so "artist album" style queries work with nested folders.
+pub struct FolderProvider {
+    root: PathBuf,
+    limit: usize
+}
+
+impl FolderProvider {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root, limit: 25 }
+    }
+
+    /// The user's music directory, if the platform has one.
+    pub fn default_root() -> Option<PathBuf> {
+        UserDirs::new().and_then(|dirs| dirs.audio_dir().map(|dir| dir.to_path_buf()))
+    }
+
+    fn walk(&self, directory: &Path, words: &[String], results: &mut Vec<PathBuf>) -> ResonateResult<()> {
+        for entry in std::fs::read_dir(directory)? {
+            if results.len() >= self.limit { break; }
+            let path = entry?.path();
+            if path.is_dir() {
+                // Unreadable subfolders are skipped rather than failing the search
+                let _ = self.walk(&path, words, results);
+            } else if is_audio_file(&path) {
+                let relative = path.strip_prefix(&self.root).unwrap_or(&path).to_string_lossy().to_lowercase();
+                if words.iter().all(|word| relative.contains(word.as_str())) {
+                    results.push(path);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// A stable id for a file outside the library, used until it is imported.
+pub fn local_file_id(path: &Path) -> String {
+    let hash = blake3::hash(path.as_os_str().as_encoded_bytes());
+    format!("local-{}", &hash.to_hex()[..16])
+}
+
+impl SearchProvider for FolderProvider {
+    fn source(&self) -> SearchSource { SearchSource::Folder }
+
+    fn search(&self, query: &str) -> ResonateResult<Vec<SearchResult>> {
+        if !self.root.is_dir() { return Ok(Vec::new()); }
+
+        let words = query.to_lowercase().split_whitespace().map(|word| word.to_string()).collect::<Vec<String>>();
+        let mut paths = Vec::new();
+        self.walk(&self.root, &words, &mut paths)?;
+
+        // Tags give the same title and artist the file is imported with, so a file that is already
+        // in the library merges with its library hit. Durations come from the container rather
+        // than decoding the file.
+        Ok(paths.into_iter().map(|path| {
+            let mut tags = read_file_tags(&path).unwrap_or_default();
+            let (title, artist, album) = file_details(&path, &mut tags);
+            SearchResult {
+                sql_id: 0,
+                id: local_file_id(&path),
+                title,
+                artists: vec![artist],
+                album,
+                duration: tags.duration.unwrap_or(0),
+                thumbnail: None,
+                explicit: false,
+                file: Some(path)
+            }
+        }).collect())
+    }
+}
+
+/// Searches YouTube through yt-dlp's `ytsearch:` prefix, which needs nothing beyond yt-dlp itself.
+pub struct YtDlpProvider {
+    limit: usize
+}
+
+impl YtDlpProvider {
+    pub fn new() -> Self {
+        Self { limit: 10 }
+    }
+}
+
+impl SearchProvider for YtDlpProvider {
+    fn source(&self) -> SearchSource { SearchSource::YtDlp }
+
+    fn search(&self, query: &str) -> ResonateResult<Vec<SearchResult>> {
+        let output = Command::new("yt-dlp")
+            .arg("--flat-playlist")
+            .arg("-J")
+            .arg(format!("ytsearch{}:{}", self.limit, query))
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|e| ResonateError::ProcessSpawn(format!("yt-dlp: {e}")))?;
+
+        if !output.status.success() {
+            return Err(ResonateError::ProcessSpawn(format!("yt-dlp exited with {} while searching", output.status)));
+        }
+
+        let value: Value = serde_json::from_slice(&output.stdout).map_err(|e| ResonateError::Scrape(e.to_string()))?;
+        // Anything without a duration is a channel or playlist rather than a video
+        Ok(parse_ytdlp_entries(&value).into_iter().filter(|result| result.duration > 0).collect())
+    }
+}
+
+/// Extracts videos from the output of `yt-dlp --flat-playlist -J`. Durations that yt-dlp does not
+/// report are left as zero.
+fn parse_ytdlp_entries(value: &Value) -> Vec<SearchResult> {
+    value.get("entries")
+        .and_then(Value::as_array)
+        .map(|entries| entries.iter().filter_map(|entry| {
+            let id = entry.get("id").and_then(Value::as_str)?.to_string();
+            let title = entry.get("title").and_then(Value::as_str)?.to_string();
+            let duration = entry.get("duration").and_then(Value::as_f64).map(|duration| duration.round() as usize).unwrap_or(0);
+            let artist = entry.get("artist")
+                .or_else(|| entry.get("channel"))
+                .or_else(|| entry.get("uploader"))
+                .and_then(Value::as_str)
+                .map(|artist| artist.to_string());
+            let thumbnail = entry.get("thumbnails")
+                .and_then(Value::as_array)
+                .and_then(|thumbnails| thumbnails.last())
+                .and_then(|thumbnail| thumbnail.get("url"))
+                .and_then(Value::as_str)
+                .map(|url| url.to_string());
+            Some(SearchResult {
+                sql_id: 0,
+                id,
+                title,
+                artists: artist.into_iter().collect(),
+                album: entry.get("album").and_then(Value::as_str).unwrap_or_default().to_string(),
+                duration,
+                thumbnail,
+                explicit: false,
+                file: None
+            })
+        }).collect())
+        .unwrap_or_default()
+}
+
+/// Whether text pasted into a search bar is a link rather than a query.
+pub fn is_url(text: &str) -> bool {
+    let text = text.trim();
+    text.starts_with("https://") || text.starts_with("http://")
+}
+
+/// Lists the tracks of a YouTube Music playlist or album, returning its name, its tracks in order
+/// and whether it is an album.
+pub fn fetch_remote_playlist(url: &str) -> ResonateResult<(String, Vec<SearchResult>, bool)> {
+    let output = Command::new("yt-dlp")
+        .arg("--flat-playlist")
+        .arg("-J")
+        .arg(url.trim())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| ResonateError::ProcessSpawn(format!("yt-dlp: {e}")))?;
+
+    if !output.status.success() {
+        return Err(ResonateError::Scrape(format!("yt-dlp could not read {} ({})", url.trim(), output.status)));
+    }
+
+    let value: Value = serde_json::from_slice(&output.stdout).map_err(|e| ResonateError::Scrape(e.to_string()))?;
+    let mut results = parse_ytdlp_entries(&value);
+    if results.is_empty() {
+        return Err(ResonateError::Scrape(format!("{} is not a playlist or album", url.trim())));
+    }
+
+    // YouTube Music names albums "Album - <name>" and leaves the album off their tracks
+    let title = value.get("title").and_then(Value::as_str).unwrap_or("Imported Playlist").to_string();
+    match title.strip_prefix("Album - ") {
+        Some(album) => {
+            let album = album.to_string();
+            results.iter_mut().filter(|result| result.album.is_empty()).for_each(|result| result.album = album.clone());
+            Ok((album, results, true))
+        }
+        None => Ok((title, results, false))
+    }
+}
+
+/// The set of search providers and whether each one is used for searches.
+pub struct ProviderRegistry {
+    providers: Vec<(Arc<dyn SearchProvider>, bool)>
+}
+
+impl ProviderRegistry {
+    pub fn new(database: AM<Database>) -> Self {
+        let mut registry = Self { providers: Vec::new() };
+        registry.register(Arc::new(LibraryProvider::new(database)), true);
+        if let Some(root) = FolderProvider::default_root() {
+            // Walking the folder and reading tags is slow on large collections, so it is opt in
+            registry.register(Arc::new(FolderProvider::new(root)), false);
+        }
+        match InnerTubeProvider::new() {
+            Ok(provider) => registry.register(Arc::new(provider), true),
+            Err(e) => println!("[SEARCH] Could not create InnerTube client: {e}")
+        }
+        registry.register(Arc::new(YtDlpProvider::new()), false);
+        registry
+    }
+
+    pub fn register(&mut self, provider: Arc<dyn SearchProvider>, enabled: bool) {
+        self.providers.push((provider, enabled));
+    }
+
+    pub fn set_enabled(&mut self, source: SearchSource, enabled: bool) {
+        self.providers.iter_mut()
+            .filter(|(provider, _)| provider.source() == source)
+            .for_each(|(_, is_enabled)| *is_enabled = enabled);
+    }
+
+    /// Every registered source and whether it is enabled, for the search bar toggles.
+    pub fn sources(&self) -> Vec<(SearchSource, bool)> {
+        self.providers.iter().map(|(provider, enabled)| (provider.source(), *enabled)).collect()
+    }
+
+    pub fn enabled(&self) -> Vec<Arc<dyn SearchProvider>> {
+        self.providers.iter().filter(|(_, enabled)| *enabled).map(|(provider, _)| provider.clone()).collect()
+    }
+}
+
+/// Extracts songs from an InnerTube search response. Items that are missing an id, a title or a
+/// duration are skipped rather than failing the whole search.
+pub fn parse_search_response(response: &Value) -> Vec<SearchResult> {
+    let tabs = response.pointer("/contents/tabbedSearchResultsRenderer/tabs").and_then(Value::as_array);
+
+    // Filtered searches nest the shelves inside the selected tab, unfiltered ones may not have tabs
+    let sections = match tabs {
+        Some(tabs) => tabs.iter()
+            .filter_map(|tab| tab.pointer("/tabRenderer/content/sectionListRenderer/contents").and_then(Value::as_array))
+            .flatten()
+            .collect::<Vec<&Value>>(),
+        None => response.pointer("/contents/sectionListRenderer/contents")
+            .and_then(Value::as_array)
+            .map(|contents| contents.iter().collect())
+            .unwrap_or_default()
+    };
+
+    sections.into_iter()
+        .filter_map(|section| section.pointer("/musicShelfRenderer/contents").and_then(Value::as_array))
+        .flatten()
+        .filter_map(|item| item.get("musicResponsiveListItemRenderer"))
+        .filter_map(parse_list_item)
+        .collect()
+}
+
+fn runs(column: &Value) -> Vec<&Value> {
+    column.pointer("/musicResponsiveListItemFlexColumnRenderer/text/runs")
+        .and_then(Value::as_array)
+        .map(|runs| runs.iter().collect())
+        .unwrap_or_default()
+}
+
+fn run_text(run: &Value) -> &str {
+    run.get("text").and_then(Value::as_str).unwrap_or("")
+}
+
+fn page_type(run: &Value) -> Option<&str> {
+    run.pointer("/navigationEndpoint/browseEndpoint/browseEndpointContextSupportedConfigs/browseEndpointContextMusicConfig/pageType")
+        .and_then(Value::as_str)
+}
+
+/// Parses "m:ss" or "h:mm:ss" into seconds.
+fn parse_duration(text: &str) -> Option<usize> {
+    if !text.contains(':') { return None; }
+    text.split(':').try_fold(0usize, |total, part| part.trim().parse::<usize>().ok().map(|value| total * 60 + value))
+}
+
+fn parse_list_item(item: &Value) -> Option<SearchResult> {
+    let columns = item.get("flexColumns").and_then(Value::as_array)?;
+    let title_runs = runs(columns.first()?);
+    let title = title_runs.first().map(|run| run_text(run).to_string())?;
+
+    let id = item.pointer("/playlistItemData/videoId")
+        .or_else(|| title_runs.first()?.pointer("/navigationEndpoint/watchEndpoint/videoId"))
+        .and_then(Value::as_str)?
+        .to_string();
+
+    // The second column reads like "Artist, Artist • Album • 3:45", split into segments on the dots
+    let detail_runs = columns.get(1).map(runs).unwrap_or_default();
+    let mut segments: Vec<Vec<&Value>> = vec![Vec::new()];
+    for run in detail_runs {
+        if run_text(run).trim() == "•" {
+            segments.push(Vec::new());
+        } else if let Some(segment) = segments.last_mut() {
+            segment.push(run);
+        }
+    }
+
+    let all_runs = segments.iter().flatten().copied().collect::<Vec<&Value>>();
+    let duration = all_runs.iter().rev().find_map(|run| parse_duration(run_text(run)))
+        .or_else(|| item.pointer("/fixedColumns/0/musicResponsiveListItemFixedColumnRenderer/text/runs/0/text")
+            .and_then(Value::as_str)
+            .and_then(parse_duration))?;
+
+    let mut artists = all_runs.iter()
+        .filter(|run| page_type(run) == Some("MUSIC_PAGE_TYPE_ARTIST"))
+        .map(|run| run_text(run).to_string())
+        .collect::<Vec<String>>();
+
+    // Some artists aren't linked, in which case the first segment is the artist list
+    if artists.is_empty() {
+        artists = segments.first()
+            .map(|segment| segment.iter()
+                .map(|run| run_text(run).trim())
+                .filter(|text| !text.is_empty() && *text != "," && *text != "&")
+                .map(|text| text.to_string())
+                .collect())
+            .unwrap_or_default();
+    }
+
+    let album = all_runs.iter()
+        .find(|run| page_type(run) == Some("MUSIC_PAGE_TYPE_ALBUM"))
+        .map(|run| run_text(run).to_string())
+        .or_else(|| segments.get(1)
+            .and_then(|segment| segment.first())
+            .map(|run| run_text(run))
+            .filter(|text| parse_duration(text).is_none())
+            .map(|text| text.to_string()))
+        .unwrap_or_default();
+
+    let thumbnail = item.pointer("/thumbnail/musicThumbnailRenderer/thumbnail/thumbnails")
+        .and_then(Value::as_array)
+        .and_then(|thumbnails| thumbnails.last())
+        .and_then(|thumbnail| thumbnail.get("url"))
+        .and_then(Value::as_str)
+        .map(|url| url.to_string());
+
+    let explicit = item.get("badges")
+        .and_then(Value::as_array)
+        .map(|badges| badges.iter().any(|badge|
+            badge.pointer("/musicInlineBadgeRenderer/icon/iconType").and_then(Value::as_str) == Some("MUSIC_EXPLICIT_BADGE")))
+        .unwrap_or(false);
+
+    Some(SearchResult { sql_id: 0, id, title, artists, album, duration, thumbnail, explicit, file: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    use super::*;
+
+    // A filtered search for songs, with a song by two artists, a single with no album, an explicit
+    // song and an artist that has no video to play
+    const SONGS_RESPONSE: &str = include_str!("../tests/fixtures/innertube_search_songs.json");
+
+    fn parse_fixture() -> Vec<SearchResult> {
+        parse_search_response(&serde_json::from_str(SONGS_RESPONSE).unwrap())
+    }
+
+    /// Answers a single request with `body`, handing back the body of the request.
+    fn serve_once(body: &'static str) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/youtubei/v1/search", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let header_end = loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") { break end + 4 }
+            };
+            let length = String::from_utf8_lossy(&request[..header_end]).to_lowercase().lines()
+                .find_map(|line| line.strip_prefix("content-length:").and_then(|length| length.trim().parse::<usize>().ok()))
+                .unwrap_or(0);
+            while request.len() < header_end + length {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+            String::from_utf8_lossy(&request[header_end..]).to_string()
+        });
+        (endpoint, handle)
+    }
+
+    #[test]
+    fn skips_items_without_a_video() {
+        let ids = parse_fixture().into_iter().map(|result| result.id).collect::<Vec<String>>();
+        assert_eq!(ids, vec!["a01QQZyl-_I", "Kd9JDZt7o4o", "uelHwf8o7_U"]);
+    }
+
+    #[test]
+    fn parses_a_song_with_several_artists() {
+        let result = &parse_fixture()[0];
+        assert_eq!(result.title, "Under Pressure");
+        assert_eq!(result.artists, vec!["Queen", "David Bowie"]);
+        assert_eq!(result.album, "Hot Space");
+        assert_eq!(result.duration, 249);
+        assert_eq!(result.thumbnail.as_deref(), Some("https://lh3.googleusercontent.com/T5uf2UbgeFkVd3KN8=w120-h120-l90-rj"));
+        assert!(!result.explicit);
+        assert_eq!(result.sql_id, 0);
+        assert_eq!(result.file, None);
+    }
+
+    #[test]
+    fn parses_a_song_with_no_album() {
+        let result = &parse_fixture()[1];
+        assert_eq!(result.title, "Midnight Drive");
+        assert_eq!(result.artists, vec!["Lena Hart"]);
+        assert_eq!(result.album, "");
+        assert_eq!(result.duration, 207);
+        assert_eq!(result.thumbnail.as_deref(), Some("https://lh3.googleusercontent.com/q4Zc7nBf0Ew2hLrQx=w120-h120-l90-rj"));
+        assert!(!result.explicit);
+    }
+
+    #[test]
+    fn parses_an_explicit_song() {
+        let result = &parse_fixture()[2];
+        assert_eq!(result.title, "Love The Way You Lie");
+        assert_eq!(result.artists, vec!["Eminem", "Rihanna"]);
+        assert_eq!(result.album, "Recovery");
+        assert_eq!(result.duration, 264);
+        assert_eq!(result.thumbnail.as_deref(), Some("https://lh3.googleusercontent.com/Y9mRk2vWb3xqHnTzL=w120-h120-l90-rj"));
+        assert!(result.explicit);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("3:27"), Some(207));
+        assert_eq!(parse_duration("1:02:03"), Some(3723));
+        assert_eq!(parse_duration("38.4M monthly audience"), None);
+    }
+
+    #[test]
+    fn searches_for_songs_through_the_endpoint() {
+        let (endpoint, server) = serve_once(SONGS_RESPONSE);
+        let results = InnerTubeProvider::with_endpoint(endpoint).unwrap().search("under pressure").unwrap();
+        assert_eq!(results, parse_fixture());
+
+        let request: Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(request["query"], "under pressure");
+        assert_eq!(request["params"], SONGS_FILTER);
+        assert_eq!(request["context"]["client"]["clientName"], INNERTUBE_CLIENT_NAME);
+    }
+}
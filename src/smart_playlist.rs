@@ -0,0 +1,435 @@
+use std::fmt;
+
+use rusqlite::types::Value;
+
+use crate::error::{ResonateError, ResonateResult};
+
+/// Plays in this many days count towards `SmartSort::RecentPlays`.
+pub const RECENT_PLAYS_DAYS: usize = 30;
+
+const LAST_PLAYED: &str = "(SELECT MAX(started_at) FROM Plays WHERE Plays.song_id = Songs.id)";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleField {
+    Title,
+    Artist,
+    Album,
+    Duration,
+    Plays,
+    LastPlayed,
+    Added,
+    Downloaded,
+    Rating,
+    Liked
+}
+
+impl RuleField {
+    pub const ALL: [RuleField; 10] = [
+        Self::Title, Self::Artist, Self::Album, Self::Duration, Self::Plays, Self::LastPlayed, Self::Added, Self::Downloaded, Self::Rating, Self::Liked
+    ];
+
+    fn key(&self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Artist => "artist",
+            Self::Album => "album",
+            Self::Duration => "duration",
+            Self::Plays => "plays",
+            Self::LastPlayed => "last_played",
+            Self::Added => "added",
+            Self::Downloaded => "downloaded",
+            Self::Rating => "rating",
+            Self::Liked => "liked"
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.key() == key)
+    }
+
+    /// The operators that make sense for this field, the first being the default.
+    pub fn operators(&self) -> &'static [RuleOperator] {
+        use RuleOperator::*;
+        match self {
+            Self::Title | Self::Artist | Self::Album => &[Contains, NotContains, Is, IsNot],
+            Self::Duration | Self::Plays | Self::Rating => &[AtLeast, AtMost],
+            Self::LastPlayed | Self::Added => &[WithinDays, NotWithinDays],
+            Self::Downloaded | Self::Liked => &[Is, IsNot]
+        }
+    }
+
+    /// Whether rules on this field take a value at all.
+    pub fn has_value(&self) -> bool {
+        !matches!(self, Self::Downloaded | Self::Liked)
+    }
+}
+
+impl fmt::Display for RuleField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::Title => "Title",
+            Self::Artist => "Artist",
+            Self::Album => "Album",
+            Self::Duration => "Duration (seconds)",
+            Self::Plays => "Play count",
+            Self::LastPlayed => "Last played",
+            Self::Added => "Date added",
+            Self::Downloaded => "Downloaded",
+            Self::Rating => "Rating (stars)",
+            Self::Liked => "Liked"
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleOperator {
+    Contains,
+    NotContains,
+    Is,
+    IsNot,
+    AtLeast,
+    AtMost,
+    WithinDays,
+    NotWithinDays
+}
+
+impl RuleOperator {
+    const ALL: [RuleOperator; 8] = [Self::Contains, Self::NotContains, Self::Is, Self::IsNot, Self::AtLeast, Self::AtMost, Self::WithinDays, Self::NotWithinDays];
+
+    fn key(&self) -> &'static str {
+        match self {
+            Self::Contains => "contains",
+            Self::NotContains => "not_contains",
+            Self::Is => "is",
+            Self::IsNot => "is_not",
+            Self::AtLeast => "at_least",
+            Self::AtMost => "at_most",
+            Self::WithinDays => "within_days",
+            Self::NotWithinDays => "not_within_days"
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|operator| operator.key() == key)
+    }
+}
+
+impl fmt::Display for RuleOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::Contains => "contains",
+            Self::NotContains => "does not contain",
+            Self::Is => "is",
+            Self::IsNot => "is not",
+            Self::AtLeast => "is at least",
+            Self::AtMost => "is at most",
+            Self::WithinDays => "in the last (days)",
+            Self::NotWithinDays => "not in the last (days)"
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub field: RuleField,
+    pub operator: RuleOperator,
+    pub value: String
+}
+
+impl Rule {
+    pub fn new(field: RuleField) -> Self {
+        Self { field, operator: field.operators()[0], value: String::new() }
+    }
+
+    pub fn keys(&self) -> (&'static str, &'static str) {
+        (self.field.key(), self.operator.key())
+    }
+
+    fn number(&self) -> ResonateResult<i64> {
+        self.value.trim().parse::<i64>()
+            .ok()
+            .filter(|number| *number >= 0)
+            .ok_or(ResonateError::InvalidRule(format!("\"{}\" is not a valid number for {}", self.value, self.field)))
+    }
+
+    /// The rule as an SQL condition on a `Songs` row, along with its parameters.
+    fn to_sql(&self) -> ResonateResult<(String, Vec<Value>)> {
+        use RuleOperator::*;
+        let text = || Value::Text(self.value.trim().to_string());
+
+        let (sql, params) = match (self.field, self.operator) {
+            (RuleField::Title | RuleField::Album, operator) => {
+                let column = if self.field == RuleField::Title { "name" } else { "album" };
+                let sql = match operator {
+                    Contains => format!("{column} LIKE '%' || ? || '%'"),
+                    NotContains => format!("{column} NOT LIKE '%' || ? || '%'"),
+                    IsNot => format!("{column} != ? COLLATE NOCASE"),
+                    _ => format!("{column} = ? COLLATE NOCASE")
+                };
+                (sql, vec![text()])
+            }
+
+            // Exact matches go through the artist links, so "is Queen" matches "Queen, David Bowie"
+            (RuleField::Artist, Contains) => (String::from("artist LIKE '%' || ? || '%'"), vec![text()]),
+            (RuleField::Artist, NotContains) => (String::from("artist NOT LIKE '%' || ? || '%'"), vec![text()]),
+            (RuleField::Artist, operator) => {
+                let exists = "EXISTS (SELECT 1 FROM SongArtists JOIN Artists ON Artists.id = SongArtists.artist_id WHERE SongArtists.song_id = Songs.id AND Artists.name = ?)";
+                let sql = if operator == IsNot { format!("NOT {exists}") } else { exists.to_string() };
+                (sql, vec![text()])
+            }
+
+            (RuleField::Duration | RuleField::Plays | RuleField::Rating, operator) => {
+                let column = match self.field {
+                    RuleField::Duration => "duration_s",
+                    RuleField::Plays => "play_count",
+                    _ => "rating"
+                };
+                let comparison = if operator == AtMost { "<=" } else { ">=" };
+                (format!("{column} {comparison} ?"), vec![Value::Integer(self.number()?)])
+            }
+
+            // Songs that were never played have not been played in any period
+            (RuleField::LastPlayed, NotWithinDays) => (format!("IFNULL({LAST_PLAYED}, 0) < unixepoch() - ? * 86400"), vec![Value::Integer(self.number()?)]),
+            (RuleField::LastPlayed, _) => (format!("{LAST_PLAYED} >= unixepoch() - ? * 86400"), vec![Value::Integer(self.number()?)]),
+
+            (RuleField::Added, NotWithinDays) => (String::from("IFNULL(added_at, '') < datetime('now', '-' || ? || ' days')"), vec![Value::Integer(self.number()?)]),
+            (RuleField::Added, _) => (String::from("added_at >= datetime('now', '-' || ? || ' days')"), vec![Value::Integer(self.number()?)]),
+
+            (RuleField::Downloaded, IsNot) => (String::from("downloaded = 0"), Vec::new()),
+            (RuleField::Downloaded, _) => (String::from("downloaded = 1"), Vec::new()),
+
+            (RuleField::Liked, IsNot) => (String::from("favourite = 0"), Vec::new()),
+            (RuleField::Liked, _) => (String::from("favourite = 1"), Vec::new())
+        };
+        Ok((sql, params))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmartSort {
+    Title,
+    Artist,
+    Album,
+    Duration,
+    Added,
+    Plays,
+    RecentPlays,
+    LastPlayed,
+    Rating,
+    Random
+}
+
+impl SmartSort {
+    pub const ALL: [SmartSort; 10] = [
+        Self::Title, Self::Artist, Self::Album, Self::Duration, Self::Added, Self::Plays, Self::RecentPlays, Self::LastPlayed, Self::Rating, Self::Random
+    ];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Artist => "artist",
+            Self::Album => "album",
+            Self::Duration => "duration",
+            Self::Added => "added",
+            Self::Plays => "plays",
+            Self::RecentPlays => "recent_plays",
+            Self::LastPlayed => "last_played",
+            Self::Rating => "rating",
+            Self::Random => "random"
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|sort| sort.key() == key)
+    }
+
+    fn to_sql(self) -> String {
+        match self {
+            Self::Title => String::from("name COLLATE NOCASE"),
+            Self::Artist => String::from("artist COLLATE NOCASE"),
+            Self::Album => String::from("album COLLATE NOCASE"),
+            Self::Duration => String::from("duration_s"),
+            Self::Added => String::from("added_at"),
+            Self::Plays => String::from("play_count"),
+            Self::RecentPlays => format!(
+                "(SELECT COUNT(*) FROM Plays WHERE Plays.song_id = Songs.id AND started_at >= unixepoch() - {} * 86400)",
+                RECENT_PLAYS_DAYS
+            ),
+            Self::LastPlayed => String::from(LAST_PLAYED),
+            Self::Rating => String::from("rating"),
+            Self::Random => String::from("random()")
+        }
+    }
+}
+
+impl fmt::Display for SmartSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Title => write!(f, "Title"),
+            Self::Artist => write!(f, "Artist"),
+            Self::Album => write!(f, "Album"),
+            Self::Duration => write!(f, "Duration"),
+            Self::Added => write!(f, "Date added"),
+            Self::Plays => write!(f, "Play count"),
+            Self::RecentPlays => write!(f, "Plays in the last {RECENT_PLAYS_DAYS} days"),
+            Self::LastPlayed => write!(f, "Last played"),
+            Self::Rating => write!(f, "Rating"),
+            Self::Random => write!(f, "Random")
+        }
+    }
+}
+
+/// A playlist whose songs are whichever songs match its rules when it is loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmartPlaylist {
+    // Whether every rule has to match rather than any of them
+    pub match_all: bool,
+    pub rules: Vec<Rule>,
+    pub sort: SmartSort,
+    pub descending: bool,
+    pub limit: Option<usize>
+}
+
+impl Default for SmartPlaylist {
+    fn default() -> Self {
+        Self { match_all: true, rules: Vec::new(), sort: SmartSort::Artist, descending: false, limit: None }
+    }
+}
+
+impl SmartPlaylist {
+    /// The rules behind the Liked Songs playlist.
+    pub fn liked() -> Self {
+        Self { rules: vec![Rule { field: RuleField::Liked, operator: RuleOperator::Is, value: String::new() }], ..Self::default() }
+    }
+
+    /// Checks every rule's value, so that bad rules are caught when they are saved rather than
+    /// every time the playlist is opened.
+    pub fn validate(&self) -> ResonateResult<()> {
+        self.to_sql().map(|_| ())
+    }
+
+    /// A query selecting the ids of the matching songs in order, along with its parameters.
+    pub fn to_sql(&self) -> ResonateResult<(String, Vec<Value>)> {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for rule in self.rules.iter() {
+            let (condition, rule_params) = rule.to_sql()?;
+            conditions.push(format!("({condition})"));
+            params.extend(rule_params);
+        }
+
+        let mut sql = String::from("SELECT id FROM Songs");
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(if self.match_all { " AND " } else { " OR " }));
+        }
+        // Ties, and songs without a value for the sort, fall back to insertion order
+        sql.push_str(&format!(" ORDER BY {} {}, id", self.sort.to_sql(), if self.descending { "DESC" } else { "ASC" }));
+        if let Some(limit) = self.limit {
+            sql.push_str(" LIMIT ?");
+            params.push(Value::Integer(limit as i64));
+        }
+        Ok((sql, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::audio::Play;
+    use crate::filemanager::Database;
+    use crate::music::{Playlist, Song};
+
+    fn rule(field: RuleField, operator: RuleOperator, value: &str) -> Rule {
+        Rule { field, operator, value: value.to_string() }
+    }
+
+    fn smart(match_all: bool, rules: Vec<Rule>, limit: Option<usize>) -> SmartPlaylist {
+        SmartPlaylist { match_all, rules, sort: SmartSort::Title, descending: false, limit }
+    }
+
+    #[test]
+    fn joins_rules_with_and_or_or() {
+        let rules = vec![rule(RuleField::Title, RuleOperator::Contains, " love "), rule(RuleField::Plays, RuleOperator::AtLeast, "3")];
+
+        let (sql, params) = smart(true, rules.clone(), None).to_sql().unwrap();
+        assert_eq!(sql, "SELECT id FROM Songs WHERE (name LIKE '%' || ? || '%') AND (play_count >= ?) ORDER BY name COLLATE NOCASE ASC, id");
+        assert_eq!(params, vec![Value::Text(String::from("love")), Value::Integer(3)]);
+
+        let (sql, params) = smart(false, rules, None).to_sql().unwrap();
+        assert_eq!(sql, "SELECT id FROM Songs WHERE (name LIKE '%' || ? || '%') OR (play_count >= ?) ORDER BY name COLLATE NOCASE ASC, id");
+        assert_eq!(params, vec![Value::Text(String::from("love")), Value::Integer(3)]);
+    }
+
+    #[test]
+    fn limits_come_last() {
+        let playlist = SmartPlaylist { descending: true, ..smart(true, vec![rule(RuleField::Liked, RuleOperator::Is, "")], Some(25)) };
+        let (sql, params) = playlist.to_sql().unwrap();
+        assert_eq!(sql, "SELECT id FROM Songs WHERE (favourite = 1) ORDER BY name COLLATE NOCASE DESC, id LIMIT ?");
+        assert_eq!(params, vec![Value::Integer(25)]);
+
+        let (sql, params) = smart(true, Vec::new(), None).to_sql().unwrap();
+        assert_eq!(sql, "SELECT id FROM Songs ORDER BY name COLLATE NOCASE ASC, id");
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn not_played_recently_includes_songs_never_played() {
+        let (sql, params) = smart(true, vec![rule(RuleField::LastPlayed, RuleOperator::NotWithinDays, "30")], None).to_sql().unwrap();
+        assert_eq!(sql, format!("SELECT id FROM Songs WHERE (IFNULL({LAST_PLAYED}, 0) < unixepoch() - ? * 86400) ORDER BY name COLLATE NOCASE ASC, id"));
+        assert_eq!(params, vec![Value::Integer(30)]);
+    }
+
+    #[test]
+    fn rejects_invalid_numbers() {
+        for value in ["", "abc", "-1"] {
+            assert!(smart(true, vec![rule(RuleField::Duration, RuleOperator::AtLeast, value)], None).validate().is_err());
+        }
+    }
+
+    #[test]
+    fn every_rule_runs_against_the_database() {
+        let database = Database::in_memory().unwrap();
+        let mut playlist = database.create_playlist(String::from("Smart")).unwrap();
+        for field in RuleField::ALL {
+            for operator in field.operators() {
+                for sort in SmartSort::ALL {
+                    let rules = vec![rule(field, *operator, "1")];
+                    let result = database.save_smart_playlist(&mut playlist, SmartPlaylist { sort, ..smart(true, rules, Some(10)) });
+                    assert!(result.is_ok(), "{field} {operator} sorted by {sort} gave {result:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn selects_songs_not_played_in_30_days() {
+        let database = Database::in_memory().unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let mut songs = ["Recent", "Old", "Never"].map(|name| Song::new(0, name.to_string(), String::from("Artist"), String::new(), name.to_string(), 100, None));
+        songs.iter_mut().for_each(|song| database.add_song_to_cache(song).unwrap());
+        for (song, days_ago) in [(&songs[0], 2), (&songs[1], 60)] {
+            database.record_play(&Play { song: song.clone(), started: now - days_ago * 86400, listened: 100f32, skipped: false }).unwrap();
+        }
+
+        let names = |playlist: &Playlist| playlist.songs.as_ref().unwrap().iter().map(|song| song.name.clone()).collect::<Vec<String>>();
+        let mut playlist = database.create_playlist(String::from("Smart")).unwrap();
+
+        database.save_smart_playlist(&mut playlist, smart(true, vec![rule(RuleField::LastPlayed, RuleOperator::NotWithinDays, "30")], None)).unwrap();
+        assert_eq!(names(&playlist), vec!["Never", "Old"]);
+
+        database.save_smart_playlist(&mut playlist, smart(true, vec![rule(RuleField::LastPlayed, RuleOperator::WithinDays, "30")], None)).unwrap();
+        assert_eq!(names(&playlist), vec!["Recent"]);
+
+        // Either rule matching is enough, and the limit applies after sorting
+        let rules = vec![rule(RuleField::Title, RuleOperator::Is, "never"), rule(RuleField::Plays, RuleOperator::AtLeast, "1")];
+        database.save_smart_playlist(&mut playlist, smart(false, rules.clone(), None)).unwrap();
+        assert_eq!(names(&playlist), vec!["Never", "Old", "Recent"]);
+        database.save_smart_playlist(&mut playlist, smart(false, rules.clone(), Some(2))).unwrap();
+        assert_eq!(names(&playlist), vec!["Never", "Old"]);
+        database.save_smart_playlist(&mut playlist, smart(true, rules, None)).unwrap();
+        assert!(names(&playlist).is_empty());
+    }
+}
@@ -0,0 +1,37 @@
+use crate::music::Song;
+
+/// How many entries each ranking on the statistics screen shows.
+pub const STATISTICS_LIMIT: usize = 10;
+/// How many days of listening time the statistics screen shows.
+pub const LISTENING_DAYS: usize = 14;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecentPlay {
+    pub song: Song,
+    // Local time as "YYYY-MM-DD HH:MM:SS"
+    pub played_at: String,
+    pub skipped: bool
+}
+
+/// Everything shown on the statistics screen, read from the play history in one go.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Statistics {
+    pub total_plays: usize,
+    // Seconds
+    pub total_listened: f32,
+    pub top_songs: Vec<(Song, usize)>,
+    pub top_artists: Vec<(String, usize)>,
+    pub recent: Vec<RecentPlay>,
+    // Local dates as "YYYY-MM-DD" with seconds listened, oldest first. Days without plays are
+    // included so the chart has no gaps.
+    pub daily: Vec<(String, f32)>
+}
+
+/// Formats a number of seconds as e.g. "3h 25m" or "12m".
+pub fn format_listening_time(seconds: f32) -> String {
+    let minutes = (seconds / 60f32).round() as usize;
+    match minutes / 60 {
+        0 => format!("{minutes}m"),
+        hours => format!("{hours}h {}m", minutes % 60)
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use rodio::Source;
+use rodio::source::SeekError;
+
+use crate::utility::*;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2f32;
+/// The furthest pitch can be shifted either way, in semitones.
+pub const MAX_PITCH: f32 = 12f32;
+
+/// Frames in each window, 40ms at the mix rate.
+const WINDOW: usize = 1764;
+const HOP: usize = WINDOW / 2;
+/// How far, in frames, a window can be moved from where it would nominally be taken so that it
+/// lines up with the one before.
+const TOLERANCE: usize = 441;
+/// The search for where a window lines up best first tries every this many positions, comparing
+/// every this many frames, and then looks closely around the best of those.
+const SEARCH_STRIDE: usize = 4;
+/// How often, in frames, speed and pitch changes are picked up.
+const REFRESH_INTERVAL: usize = 2048;
+
+/// Changes tempo and pitch independently of each other. Tempo is changed with WSOLA, which takes
+/// overlapping windows from the source further apart or closer together than they are played,
+/// nudging each one to where it best continues the one before so that the waveform has no seams.
+/// Pitch is then shifted by resampling, and the tempo is adjusted to make up for how resampling
+/// speeds things up or slows them down. At normal speed and pitch the source is passed through.
+pub struct TimeStretch<S: Source<Item = f32>> {
+    source: S,
+    channels: usize,
+    speed: AM<f32>,
+    pitch: AM<f32>,
+    // Source frames used per stretched frame, and stretched frames used per frame played
+    tempo: f64,
+    rate: f64,
+    until_refresh: usize,
+    // Interleaved frames read from the source that later windows may still be taken from
+    input: Vec<f32>,
+    exhausted: bool,
+    // Positions in `input`, in frames, of where the next window would nominally be taken from and
+    // of the natural continuation of the last window, which the next one should sound like
+    analysis: f64,
+    target: Option<usize>,
+    // The second half of the last window, faded out, waiting for the next window to fade in over it
+    overlap: Vec<f32>,
+    // Interleaved frames that have been stretched but not resampled, with how far between the
+    // first two of them resampling has got
+    stretched: VecDeque<f32>,
+    phase: f64,
+    // Interleaved samples ready to be played
+    ready: VecDeque<f32>,
+    // A Hann window, which adds up to 1 when overlapped by half
+    window: Vec<f32>
+}
+
+impl<S: Source<Item = f32>> TimeStretch<S> {
+    pub fn new(source: S, speed: AM<f32>, pitch: AM<f32>) -> Self {
+        let channels = source.channels() as usize;
+        Self {
+            source,
+            channels,
+            speed,
+            pitch,
+            tempo: 1f64,
+            rate: 1f64,
+            until_refresh: 0,
+            input: Vec::new(),
+            exhausted: false,
+            analysis: 0f64,
+            target: None,
+            overlap: vec![0f32; HOP * channels],
+            stretched: VecDeque::new(),
+            phase: 0f64,
+            ready: VecDeque::new(),
+            window: (0..WINDOW).map(|n| 0.5 - 0.5 * (2f32 * PI * n as f32 / WINDOW as f32).cos()).collect()
+        }
+    }
+
+    fn is_bypassed(&self) -> bool {
+        self.tempo == 1f64 && self.rate == 1f64
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.exhausted = false;
+        self.analysis = 0f64;
+        self.target = None;
+        self.overlap.fill(0f32);
+        self.stretched.clear();
+        self.phase = 0f64;
+        self.ready.clear();
+    }
+
+    fn refresh(&mut self) {
+        let was_bypassed = self.is_bypassed();
+        let speed = self.speed.lock().unwrap().clamp(MIN_SPEED, MAX_SPEED) as f64;
+        self.rate = 2f64.powf(self.pitch.lock().unwrap().clamp(-MAX_PITCH, MAX_PITCH) as f64 / 12f64);
+        self.tempo = speed / self.rate;
+
+        match (was_bypassed, self.is_bypassed()) {
+            // Whatever was read ahead for the next window is played as it is
+            (false, true) => {
+                let start = (self.analysis as usize * self.channels).min(self.input.len());
+                let rest: Vec<f32> = self.input.drain(start..).collect();
+                self.reset();
+                self.ready.extend(rest);
+            }
+            (true, false) => self.reset(),
+            _ => {}
+        }
+    }
+
+    /// A sample from `input`, silent past the end of the source.
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        self.input.get(frame * self.channels + channel).copied().unwrap_or(0f32)
+    }
+
+    fn mono(&self, frame: usize) -> f32 {
+        (0..self.channels).map(|channel| self.sample(frame, channel)).sum()
+    }
+
+    /// How well the half window starting at `candidate` continues on from the one at `target`.
+    fn similarity(&self, candidate: usize, target: usize, stride: usize) -> f32 {
+        let (dot, energy) = (0..HOP).step_by(stride).fold((0f32, 0f32), |(dot, energy), frame| {
+            let sample = self.mono(candidate + frame);
+            (dot + sample * self.mono(target + frame), energy + sample * sample)
+        });
+        // Normalised so that louder candidates are not favoured just for being loud
+        dot / energy.sqrt().max(f32::EPSILON)
+    }
+
+    /// Where within the tolerance of `nominal` a window best continues from `target`.
+    fn best_start(&self, nominal: usize, target: usize) -> usize {
+        let (low, high) = (nominal.saturating_sub(TOLERANCE), nominal + TOLERANCE);
+        let best = |candidates: Vec<usize>, stride: usize| candidates.into_iter()
+            .map(|candidate| (candidate, self.similarity(candidate, target, stride)))
+            .fold((nominal, f32::MIN), |best, candidate| if candidate.1 > best.1 { candidate } else { best })
+            .0;
+        let coarse = best((low..=high).step_by(SEARCH_STRIDE).collect(), SEARCH_STRIDE);
+        best((coarse.saturating_sub(SEARCH_STRIDE).max(low)..=(coarse + SEARCH_STRIDE).min(high)).collect(), 1)
+    }
+
+    /// Overlaps the next window onto the last one, adding `HOP` frames to `stretched`. Returns
+    /// false once the source has been used up.
+    fn stretch_window(&mut self) -> bool {
+        let nominal = self.analysis as usize;
+        let target = self.target;
+        let needed = (nominal + TOLERANCE + WINDOW).max(target.map_or(0, |target| target + HOP));
+        while !self.exhausted && self.input.len() < needed * self.channels {
+            match self.source.next() {
+                Some(sample) => self.input.push(sample),
+                None => self.exhausted = true
+            }
+        }
+        if self.exhausted && nominal * self.channels >= self.input.len() { return false }
+
+        let start = match target {
+            Some(target) => self.best_start(nominal, target),
+            None => nominal
+        };
+        for frame in 0..HOP {
+            for channel in 0..self.channels {
+                let faded_in = self.sample(start + frame, channel) * self.window[frame];
+                self.stretched.push_back(self.overlap[frame * self.channels + channel] + faded_in);
+                self.overlap[frame * self.channels + channel] = self.sample(start + HOP + frame, channel) * self.window[HOP + frame];
+            }
+        }
+        self.analysis += self.tempo * HOP as f64;
+
+        // Nothing before this can be reached by a later window
+        let used = (self.analysis as usize).saturating_sub(TOLERANCE).min(start + HOP);
+        self.input.drain(..(used * self.channels).min(self.input.len()));
+        self.analysis -= used as f64;
+        self.target = Some(start + HOP - used);
+        true
+    }
+
+    /// Resamples the next frame to play out of `stretched` into `ready`.
+    fn resample_frame(&mut self) {
+        let channels = self.channels;
+        while self.stretched.len() < (self.phase as usize + 2) * channels {
+            if !self.stretch_window() { break }
+        }
+        while self.phase >= 1f64 && !self.stretched.is_empty() {
+            self.stretched.drain(..channels.min(self.stretched.len()));
+            self.phase -= 1f64;
+        }
+        if self.stretched.len() < channels { return }
+
+        let fraction = self.phase as f32;
+        for channel in 0..channels {
+            let current = self.stretched[channel];
+            let next = self.stretched.get(channels + channel).copied().unwrap_or(current);
+            self.ready.push_back(current + (next - current) * fraction);
+        }
+        self.phase += self.rate;
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for TimeStretch<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(sample) = self.ready.pop_front() { return Some(sample) }
+
+        if self.until_refresh == 0 {
+            self.until_refresh = REFRESH_INTERVAL;
+            self.refresh();
+            if let Some(sample) = self.ready.pop_front() { return Some(sample) }
+        }
+        self.until_refresh -= 1;
+
+        match self.is_bypassed() {
+            true => self.ready.extend(self.source.by_ref().take(self.channels)),
+            false => self.resample_frame()
+        }
+        self.ready.pop_front()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for TimeStretch<S> {
+    fn current_frame_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { self.source.channels() }
+    fn sample_rate(&self) -> u32 { self.source.sample_rate() }
+    fn total_duration(&self) -> Option<Duration> { None }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(position)?;
+        // Anything read ahead came from before the seek
+        self.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use crate::dsp::test_signal::{sine, SAMPLE_RATE};
+
+    use super::*;
+
+    fn stretch(input: Vec<f32>, speed: f32, pitch: f32) -> Vec<f32> {
+        TimeStretch::new(SamplesBuffer::new(2, SAMPLE_RATE, input), sync(speed), sync(pitch)).collect()
+    }
+
+    /// The frequency of the left channel, from how often it crosses zero on the way up. The first
+    /// and last windows are left out, since they fade in and out.
+    fn frequency(samples: &[f32]) -> f32 {
+        let left = samples.iter().step_by(2).copied().collect::<Vec<f32>>();
+        let middle = &left[WINDOW..left.len() - WINDOW];
+        let crossings = middle.windows(2).filter(|pair| pair[0] < 0f32 && pair[1] >= 0f32).count();
+        crossings as f32 * SAMPLE_RATE as f32 / middle.len() as f32
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= expected * tolerance, "expected about {expected}, got {actual}");
+    }
+
+    #[test]
+    fn passes_through_at_normal_speed_and_pitch() {
+        let input = sine(220f32, 2 * SAMPLE_RATE as usize);
+        assert_eq!(stretch(input.clone(), 1f32, 0f32), input);
+    }
+
+    #[test]
+    fn double_speed_halves_the_length() {
+        let input = sine(220f32, 4 * SAMPLE_RATE as usize);
+        let output = stretch(input.clone(), 2f32, 0f32);
+        assert_close(output.len() as f32, input.len() as f32 / 2f32, 0.02);
+        assert_close(frequency(&output), 220f32, 0.02);
+    }
+
+    #[test]
+    fn half_speed_doubles_the_length() {
+        let input = sine(220f32, 4 * SAMPLE_RATE as usize);
+        let output = stretch(input.clone(), 0.5, 0f32);
+        assert_close(output.len() as f32, input.len() as f32 * 2f32, 0.02);
+        assert_close(frequency(&output), 220f32, 0.02);
+    }
+
+    #[test]
+    fn octave_up_doubles_the_frequency() {
+        let input = sine(220f32, 4 * SAMPLE_RATE as usize);
+        let output = stretch(input.clone(), 1f32, 12f32);
+        assert_close(output.len() as f32, input.len() as f32, 0.02);
+        assert_close(frequency(&output), 440f32, 0.02);
+    }
+
+    #[test]
+    fn octave_down_halves_the_frequency() {
+        let input = sine(440f32, 4 * SAMPLE_RATE as usize);
+        let output = stretch(input.clone(), 1f32, -12f32);
+        assert_close(output.len() as f32, input.len() as f32, 0.02);
+        assert_close(frequency(&output), 220f32, 0.02);
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::Path;
+
+use id3::frame::{Picture, PictureType};
+use id3::{no_tag_ok, Tag, TagLike, Version};
+
+use crate::error::{ResonateError, ResonateResult};
+use crate::music::Song;
+
+/// Reads the existing tag so that frames resonate does not manage are kept, e.g. lyrics or
+/// anything another player wrote.
+fn read_tag(file: &Path) -> ResonateResult<Tag> {
+    Ok(no_tag_ok(Tag::read_from_path(file))?.unwrap_or_default())
+}
+
+fn set_cover(tag: &mut Tag, directory: &Path, id: &str) -> ResonateResult<bool> {
+    let thumbnail = directory.join(format!("{id}.png"));
+    if !thumbnail.exists() { return Ok(false); }
+
+    tag.remove_picture_by_type(PictureType::CoverFront);
+    tag.add_frame(Picture {
+        mime_type: String::from("image/png"),
+        picture_type: PictureType::CoverFront,
+        description: String::new(),
+        data: std::fs::read(thumbnail)?
+    });
+    Ok(true)
+}
+
+/// Writes the song's metadata, and its thumbnail if one has been downloaded, into the song's file
+/// so that the library is usable by other players.
+pub fn write_tags(directory: &Path, song: &Song) -> ResonateResult<()> {
+    let file = match song.file.as_ref() {
+        Some(file) => file,
+        None => return Err(ResonateError::Tag(format!("{} is not downloaded", song.name)))
+    };
+
+    let mut tag = read_tag(file)?;
+    tag.set_title(song.name.as_str());
+    tag.set_artist(song.artist.as_str());
+    tag.set_album(song.album.as_str());
+    // TLEN is in milliseconds
+    tag.set_duration((song.duration * 1000) as u32);
+    set_cover(&mut tag, directory, &song.id)?;
+
+    tag.write_to_path(file, Version::Id3v24)?;
+    Ok(())
+}
+
+/// Embeds a freshly downloaded thumbnail into an existing file without touching its other tags.
+pub fn embed_cover(directory: &Path, id: &str) -> ResonateResult<()> {
+    let file = directory.join(format!("{id}.mp3"));
+    if !file.exists() { return Ok(()); }
+
+    let mut tag = read_tag(&file)?;
+    if set_cover(&mut tag, directory, id)? {
+        tag.write_to_path(&file, Version::Id3v24)?;
+    }
+    Ok(())
+}
+
+/// Re-tags every downloaded song, returning how many were written. Individual failures do not
+/// stop the rest of the library from being tagged.
+pub fn retag_library(directory: &Path, songs: Vec<Song>) -> (usize, Vec<ResonateError>) {
+    let mut tagged = 0;
+    let mut errors = Vec::new();
+    for song in songs.iter().filter(|song| song.file.as_ref().is_some_and(|file| file.exists())) {
+        match write_tags(directory, song) {
+            Ok(()) => tagged += 1,
+            Err(e) => {
+                println!("[TAGGER] Failed to tag {}: {e}", song.name);
+                errors.push(e);
+            }
+        }
+    }
+    (tagged, errors)
+}
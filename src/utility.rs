@@ -1,7 +1,10 @@
 use std::{collections::VecDeque, sync::{Arc, Mutex}};
 
 pub type AM<T> = Arc<Mutex<T>>;
+#[allow(clippy::upper_case_acronyms)]
 pub type AMV<T> = Arc<Mutex<Vec<T>>>;
+#[allow(clippy::upper_case_acronyms)]
 pub type AMQ<T> = Arc<Mutex<VecDeque<T>>>;
+#[allow(clippy::upper_case_acronyms)]
 pub type AMO<T> = Arc<Mutex<Option<T>>>;
 pub fn sync<T>(obj: T) -> AM<T> { Arc::new(Mutex::new(obj)) }
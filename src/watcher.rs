@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+
+use crate::error::{ResonateError, ResonateResult};
+use crate::filemanager::Database;
+use crate::importer::{collect_audio_files, import_file, ImportOutcome};
+use crate::search::is_audio_file;
+use crate::utility::*;
+
+/// Bursts of events for the same file, e.g. a file manager copying an album, are collapsed into a
+/// single event once things have been quiet for this long.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+pub const WATCH_FOLDERS_SETTING: &str = "watch_folders";
+
+enum WatchTask {
+    // Bring the database in line with everything currently in a folder
+    Scan(PathBuf),
+    Changed(Vec<PathBuf>)
+}
+
+/// What the watcher has done since startup, for display.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WatchStatus {
+    pub imported: usize,
+    pub updated: usize,
+    // Songs whose source file disappeared
+    pub lost: usize,
+    pub is_busy: bool
+}
+
+/// Keeps the library in sync with a set of folders. File events are debounced on notify's thread
+/// and then handled on a worker thread, so neither ever blocks the UI.
+pub struct FolderWatcher {
+    debouncer: Debouncer<RecommendedWatcher>,
+    tasks: Sender<WatchTask>,
+    folders: Vec<PathBuf>,
+    status: AM<WatchStatus>,
+    errors: AMQ<ResonateError>
+}
+
+impl FolderWatcher {
+    pub fn new(database: AM<Database>) -> ResonateResult<Self> {
+        let (tasks, receiver) = channel::<WatchTask>();
+        let status = sync(WatchStatus::default());
+        let errors = sync(Default::default());
+
+        let sender = tasks.clone();
+        let debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| match result {
+            Ok(events) => { let _ = sender.send(WatchTask::Changed(events.into_iter().map(|event| event.path).collect())); }
+            Err(e) => println!("[WATCHER] {e}")
+        }).map_err(|e| ResonateError::Io(e.to_string()))?;
+
+        let worker_status = status.clone();
+        let worker_errors = errors.clone();
+        std::thread::spawn(move || watch_worker(database, receiver, worker_status, worker_errors));
+
+        Ok(Self { debouncer, tasks, folders: Vec::new(), status, errors })
+    }
+
+    pub fn get_folders(&self) -> Vec<PathBuf> {
+        self.folders.clone()
+    }
+
+    pub fn get_status(&self) -> WatchStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts watching a folder and scans it for changes made while it was not being watched.
+    pub fn watch(&mut self, folder: PathBuf) -> ResonateResult<()> {
+        if self.folders.contains(&folder) { return Ok(()); }
+        self.debouncer.watcher().watch(&folder, RecursiveMode::Recursive).map_err(|e| ResonateError::Io(format!("Could not watch {}: {e}", folder.display())))?;
+        println!("[WATCHER] Watching {}", folder.display());
+        let _ = self.tasks.send(WatchTask::Scan(folder.clone()));
+        self.folders.push(folder);
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, folder: &Path) -> ResonateResult<()> {
+        self.folders.retain(|watched| watched != folder);
+        self.debouncer.watcher().unwatch(folder).map_err(|e| ResonateError::Io(e.to_string()))
+    }
+
+    pub fn take_errors(&self) -> Vec<ResonateError> {
+        self.errors.lock().unwrap().drain(..).collect()
+    }
+}
+
+fn watch_worker(database: AM<Database>, receiver: Receiver<WatchTask>, status: AM<WatchStatus>, errors: AMQ<ResonateError>) {
+    // Ends once the watcher, and with it the sender, is dropped
+    for task in receiver {
+        status.lock().unwrap().is_busy = true;
+        let paths = match task {
+            WatchTask::Scan(folder) => {
+                // Sources that no longer exist are treated like deletions
+                let mut paths = collect_audio_files(&folder);
+                match database.lock().unwrap().sources_under(&folder) {
+                    Ok(sources) => paths.extend(sources.into_iter().filter(|source| !source.exists())),
+                    Err(e) => errors.lock().unwrap().push_back(e)
+                }
+                paths
+            }
+            WatchTask::Changed(paths) => paths.into_iter()
+                .flat_map(|path| if path.is_dir() { collect_audio_files(&path) } else { vec![path] })
+                .collect()
+        };
+
+        for path in paths {
+            if let Err(e) = sync_path(&database, &path, &status) {
+                println!("[WATCHER] Failed to sync {}: {e}", path.display());
+                errors.lock().unwrap().push_back(e);
+            }
+        }
+        status.lock().unwrap().is_busy = false;
+    }
+}
+
+/// Imports or updates a file that appeared or changed, or marks songs missing if it disappeared.
+fn sync_path(database: &AM<Database>, path: &Path, status: &AM<WatchStatus>) -> ResonateResult<()> {
+    if !path.exists() {
+        let missing = database.lock().unwrap().mark_source_missing(path)?;
+        if missing > 0 { println!("[WATCHER] {missing} songs lost their source at {}", path.display()); }
+        status.lock().unwrap().lost += missing;
+        return Ok(());
+    }
+
+    if !is_audio_file(path) { return Ok(()); }
+
+    match import_file(database, path)? {
+        ImportOutcome::Imported(song) => {
+            println!("[WATCHER] Imported {}", song.name);
+            status.lock().unwrap().imported += 1;
+        }
+        ImportOutcome::Updated(song) => {
+            println!("[WATCHER] Updated {}", song.name);
+            status.lock().unwrap().updated += 1;
+        }
+        ImportOutcome::Skipped(_) => {}
+    }
+    Ok(())
+}
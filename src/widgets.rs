@@ -1,12 +1,13 @@
 use std::ops::RangeInclusive;
 use std::path::PathBuf;
 
-use iced::widget::{slider, ProgressBar, Scrollable, image};
-use iced::alignment::Vertical;
+use iced::widget::{pick_list, progress_bar, slider, vertical_slider, Scrollable, image};
+use iced::alignment::{Horizontal, Vertical};
 
 pub struct ResonateColour;
 impl ResonateColour {
 
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(r: u8, g: u8, b: u8) -> Color {
         Color::from_rgb(r as f32 / 255f32, g as f32 / 255f32, b as f32 / 255f32)
     }
@@ -25,15 +26,56 @@ impl ResonateColour {
 }
 
 use iced::widget::{
-    button, container, text, text_input, toggler, Column, Container, Row
+    button, container, text, text_input, toggler, Column, Container, Row, Space
 };
 
 use iced::{
     Background, Border, Color, Element, Length, Shadow, Theme
 };
 
+use crate::audio::{RepeatMode, MAX_CROSSFADE};
+use crate::loudness::Normalization;
+use crate::stretch::{MAX_PITCH, MAX_SPEED, MIN_SPEED};
+use crate::equalizer::{EqualizerSettings, PresetChoice, BAND_FREQUENCIES, MAX_BAND_GAIN};
+use crate::error::ResonateError;
 use crate::music::Playlist;
-use crate::{application::Message, music::Song};
+use crate::search::{is_url, SearchSource};
+use crate::importer::ImportProgress;
+use crate::watcher::WatchStatus;
+use crate::playlist_file::PlaylistEntry;
+use crate::library::{DownloadFilter, LibraryEntry, LibraryView, SortColumn};
+use crate::statistics::{format_listening_time, Statistics};
+use crate::smart_playlist::{RuleField, SmartPlaylist, SmartSort};
+use crate::download_manager::{DownloadState, DownloadStatus, MAX_DOWNLOAD_LIMIT};
+use crate::{application::Message, music::{Song, MAX_RATING}};
+
+pub fn error_widget(index: usize, error: &ResonateError) -> Element<'static, Message> {
+    let row = Row::new()
+        .spacing(20)
+        .align_y(Vertical::Center)
+        .push(text(error.to_string()).color(ResonateColour::text_emphasis()).width(Length::Fill))
+        .push(button("Dismiss")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::accent()))),
+                    _ => Some(Background::Color(ResonateColour::accent()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+            .on_press(Message::DismissError(index)));
+
+    Container::new(row)
+        .padding(10)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::red()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
 
 pub fn playlist_widget(playlist: Playlist) -> Element<'static, Message> {
     let playlist_clone = playlist.clone();
@@ -63,9 +105,12 @@ pub fn playlist_widget(playlist: Playlist) -> Element<'static, Message> {
         .into()
 }
 
-pub fn display_song_widget(song: Song, is_playing: bool, is_paused: bool, image_path: PathBuf) -> Element<'static, Message> {
+pub fn display_song_widget(song: Song, index: usize, count: usize, is_playing: bool, is_paused: bool, is_smart: bool, image_path: PathBuf) -> Element<'static, Message> {
     let song_clone = song.clone();
     let second_song_clone = song.clone();
+    let third_song_clone = song.clone();
+    let fourth_song_clone = song.clone();
+    let preference = preference_widget(&song);
 
     let button_colour = match is_playing && !is_paused {
         true => ResonateColour::red(),
@@ -101,6 +146,49 @@ pub fn display_song_widget(song: Song, is_playing: bool, is_paused: bool, image_
         })
         .on_press(Message::Queue(second_song_clone));
 
+    let play_next_button = button("Play Next")
+        .style(move |_theme: &Theme, style| button::Style {
+            background: match style {
+                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
+                _ => Some(Background::Color(ResonateColour::blue()))
+            },
+            border: Border::default().rounded(10),
+            shadow: Shadow::default(),
+            text_color: ResonateColour::text_emphasis(),
+        })
+        .on_press(Message::PlayNext(fourth_song_clone));
+
+    let move_style = |_theme: &Theme, style: button::Status| button::Style {
+        background: match style {
+            button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::accent()))),
+            button::Status::Disabled => Some(Background::Color(ResonateColour::foreground())),
+            _ => Some(Background::Color(ResonateColour::accent()))
+        },
+        border: Border::default().rounded(10),
+        shadow: Shadow::default(),
+        text_color: ResonateColour::text_emphasis(),
+    };
+
+    let up_button = button("Up")
+        .style(move_style)
+        .on_press_maybe(if index > 0 { Some(Message::MoveSongInPlaylist(index, index - 1)) } else { None });
+
+    let down_button = button("Down")
+        .style(move_style)
+        .on_press_maybe(if index + 1 < count { Some(Message::MoveSongInPlaylist(index, index + 1)) } else { None });
+
+    let remove_button = button("Remove")
+        .style(move |_theme: &Theme, style| button::Style {
+            background: match style {
+                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::red()))),
+                _ => Some(Background::Color(ResonateColour::red()))
+            },
+            border: Border::default().rounded(10),
+            shadow: Shadow::default(),
+            text_color: ResonateColour::text_emphasis(),
+        })
+        .on_press(Message::RemoveSongFromPlaylist(third_song_clone));
+
     let title = text(song.name).color(ResonateColour::text_emphasis()).size(25);
     let artist = text(song.artist).color(ResonateColour::text());
     let album = text(song.album).color(ResonateColour::text());
@@ -117,10 +205,20 @@ pub fn display_song_widget(song: Song, is_playing: bool, is_paused: bool, image_
         )
         .push(album.width(Length::FillPortion(2)))
         .push(duration.width(Length::FillPortion(1)))
+        .push(preference)
         .push(play_button.width(Length::FillPortion(1)))
+        .push(play_next_button.width(Length::FillPortion(1)))
         .push(queue_widget.width(Length::FillPortion(1)))
         .align_y(Vertical::Center);
 
+    // A smart playlist's songs are chosen and ordered by its rules
+    let row = match is_smart {
+        true => row,
+        false => row
+            .push(Column::new().spacing(5).push(up_button).push(down_button))
+            .push(remove_button)
+    };
+
     Container::new(row)
         .padding(20)
         .width(Length::Fill)
@@ -134,9 +232,10 @@ pub fn display_song_widget(song: Song, is_playing: bool, is_paused: bool, image_
         .into()
 }
 
-pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, progress: f32, volume: f32, is_looping: bool) -> Element<'static, Message> {
+pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, progress: f32, volume: f32, repeat: RepeatMode, is_shuffling: bool) -> Element<'static, Message> {
 
     let current_clone = current.clone();
+    let preference = current.as_ref().map(preference_widget);
     let (name, artist, album, duration) = match current {
         Some(song) => (song.name, song.artist, song.album, song.duration),
         None => (String::from("Current song will appear here"), String::from("-"), String::from("-"), 0)
@@ -161,7 +260,7 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
             }
         );
 
-    let skip_button = button("Skip")
+    let previous_button = button("Previous")
         .style(move |_theme: &Theme, style| button::Style {
             background: match style {
                 button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
@@ -172,24 +271,10 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
             text_color: ResonateColour::text_emphasis(),
         })
         .on_press(
-            Message::Skip
+            Message::Previous
         );
 
-    let slow_button = button("Slow")
-        .style(move |_theme: &Theme, style| button::Style {
-            background: match style {
-                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
-                _ => Some(Background::Color(ResonateColour::blue()))
-            },
-            border: Border::default().rounded(10),
-            shadow: Shadow::default(),
-            text_color: ResonateColour::text_emphasis(),
-        })
-        .on_press(
-            Message::Slow
-        );
-
-    let normal_button = button("Normal")
+    let skip_button = button("Skip")
         .style(move |_theme: &Theme, style| button::Style {
             background: match style {
                 button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
@@ -200,22 +285,13 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
             text_color: ResonateColour::text_emphasis(),
         })
         .on_press(
-            Message::Normal
+            Message::Skip
         );
 
-    let fast_button = button("Fast")
-        .style(move |_theme: &Theme, style| button::Style {
-            background: match style {
-                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
-                _ => Some(Background::Color(ResonateColour::blue()))
-            },
-            border: Border::default().rounded(10),
-            shadow: Shadow::default(),
-            text_color: ResonateColour::text_emphasis(),
-        })
-        .on_press(
-            Message::Fast
-        );
+    let repeat_colour = match repeat {
+        RepeatMode::Off => ResonateColour::red(),
+        _ => ResonateColour::green()
+    };
 
     let title = text(name).color(ResonateColour::text_emphasis()).size(25);
     let artist = text(artist).color(ResonateColour::text());
@@ -234,6 +310,7 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
             )
             .push(album.width(Length::FillPortion(2)))
             .push(duration.width(Length::FillPortion(1)))
+            .push_maybe(preference)
             .align_y(Vertical::Top))
             .width(Length::Fill)
             .style(|_theme: &Theme| {
@@ -246,24 +323,40 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
             .height(Length::Shrink))
             .push(
                 match current_clone {
-                    Some(song) => ProgressBar::new(RangeInclusive::new(0f32, song.duration as f32), progress),
-                    None => ProgressBar::new(RangeInclusive::new(0f32, 1f32), 0f32)
+                    Some(song) => slider(RangeInclusive::new(0f32, song.duration as f32), progress.min(song.duration as f32), Message::SeekDrag)
+                        .on_release(Message::SeekRelease)
+                        .step(0.5f32),
+                    None => slider(RangeInclusive::new(0f32, 1f32), 0f32, Message::SeekDrag)
                 }
             )
             .push(Row::new()
                 .spacing(10)
+                .push(previous_button)
                 .push(pause_button)
                 .push(skip_button)
-                .push(slow_button)
-                .push(normal_button)
-                .push(fast_button)
-                .push(toggler(is_looping)
-                    .on_toggle(Message::SetLooping)
+                .push(button(match repeat {
+                        RepeatMode::Off => "Repeat: Off",
+                        RepeatMode::One => "Repeat: One",
+                        RepeatMode::All => "Repeat: All"
+                    })
+                    .style(move |_theme: &Theme, style| button::Style {
+                        background: match style {
+                            button::Status::Hovered => Some(Background::Color(ResonateColour::darken(repeat_colour))),
+                            _ => Some(Background::Color(repeat_colour))
+                        },
+                        border: Border::default().rounded(10),
+                        shadow: Shadow::default(),
+                        text_color: ResonateColour::text_emphasis(),
+                    })
+                    .on_press(Message::SetRepeat(repeat.next())))
+                .push(toggler(is_shuffling)
+                    .label("Shuffle")
+                    .on_toggle(Message::SetShuffle)
                 .style(move |_theme: &Theme, _style| toggler::Style {
                     background: ResonateColour::background(),
                     background_border_width: 0f32,
                     background_border_color: ResonateColour::background(),
-                    foreground: if is_looping { ResonateColour::green() } else { ResonateColour::red() },
+                    foreground: if is_shuffling { ResonateColour::green() } else { ResonateColour::red() },
                     foreground_border_width: 0f32,
                     foreground_border_color: ResonateColour::foreground()
                 })
@@ -273,15 +366,25 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
         .push(slider(RangeInclusive::new(0f32, 100f32), volume, Message::SetVolume));
 
     let mut queue_col = Column::new()
-        .spacing(20);
+        .spacing(20)
+        .push(Row::new()
+            .spacing(20)
+            .align_y(Vertical::Center)
+            .push(text("Up Next").color(ResonateColour::text_emphasis()).size(20).width(Length::Fill))
+            .push(queue_button("Clear", ResonateColour::red(), (!queue.is_empty()).then_some(Message::ClearQueue))));
 
-    for song in queue {
+    let count = queue.len();
+    for (index, song) in queue.into_iter().enumerate() {
         let display = Container::new(Row::new()
             .spacing(20)
             .align_y(Vertical::Center)
             .push(text(song.name).color(ResonateColour::text_emphasis()))
             .push(text(song.artist).color(ResonateColour::text()))
-            .push(text(song.album).color(ResonateColour::text())))
+            .push(text(song.album).color(ResonateColour::text()).width(Length::Fill))
+            .push(queue_button("Play", ResonateColour::green(), Some(Message::JumpToQueued(index))))
+            .push(queue_button("Up", ResonateColour::accent(), (index > 0).then(|| Message::MoveInQueue(index, index - 1))))
+            .push(queue_button("Down", ResonateColour::accent(), (index + 1 < count).then(|| Message::MoveInQueue(index, index + 1))))
+            .push(queue_button("Remove", ResonateColour::red(), Some(Message::RemoveFromQueue(index)))))
             .style(|_theme: &Theme| {
                 container::Style::default()
                     .background(Background::Color(Color::from_rgb(0.15f32, 0.15f32, 0.15f32)))
@@ -298,7 +401,256 @@ pub fn queue_widget(current: Option<Song>, queue: Vec<Song>, is_paused: bool, pr
         .into()
 }
 
-pub fn download_song_widget(song: Song, directory: PathBuf, is_downloading: bool, is_queued: bool, playlist: Playlist) -> Element<'static, Message> {
+/// Settings for how songs are played back.
+pub fn playback_widget(crossfade: f32, normalization: Normalization, is_scanning: bool, speed: f32, pitch: f32) -> Element<'static, Message> {
+    let crossfade_label = match crossfade > 0f32 {
+        true => format!("Crossfade: {crossfade:.0}s"),
+        false => String::from("Crossfade: Off")
+    };
+
+    let normalization_row = Normalization::ALL.into_iter().fold(
+        Row::new().spacing(10).align_y(Vertical::Center).push(text("Normalise").color(ResonateColour::text()).width(Length::Fixed(120f32))),
+        |row, option| row.push(selectable_button(option.label().to_string(), normalization == option, Message::SetNormalization(option)))
+    );
+    let normalization_row = match is_scanning {
+        true => normalization_row.push(text("Analysing loudness...").color(ResonateColour::text()).size(14)),
+        false => normalization_row
+    };
+
+    Container::new(Column::new()
+        .spacing(10)
+        .push(text("Playback").color(ResonateColour::text_emphasis()).size(20))
+        .push(Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(crossfade_label).color(ResonateColour::text()).width(Length::Fixed(120f32)))
+            .push(slider(RangeInclusive::new(0f32, MAX_CROSSFADE), crossfade, Message::SetCrossfade).step(1f32)))
+        .push(normalization_row)
+        .push(Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(format!("Speed: {speed:.2}x")).color(ResonateColour::text()).width(Length::Fixed(120f32)))
+            .push(slider(RangeInclusive::new(MIN_SPEED, MAX_SPEED), speed, Message::SetSpeed).step(0.05f32)))
+        .push(Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(format!("Pitch: {pitch:+.0}")).color(ResonateColour::text()).width(Length::Fixed(120f32)))
+            .push(slider(RangeInclusive::new(-MAX_PITCH, MAX_PITCH), pitch, Message::SetPitch).step(1f32))
+            .push(queue_button("Reset", ResonateColour::accent(), (speed != 1f32 || pitch != 0f32).then_some(Message::ResetSpeed)))))
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+pub fn downloads_widget(entries: Vec<(Song, DownloadState)>, limit: usize) -> Element<'static, Message> {
+    let header = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text("Downloads").color(ResonateColour::text_emphasis()).size(20).width(Length::Fill))
+        .push(text(format!("{limit} at once")).color(ResonateColour::text()))
+        .push(queue_button("-", ResonateColour::accent(), if limit > 1 { Some(Message::SetDownloadLimit(limit - 1)) } else { None }))
+        .push(queue_button("+", ResonateColour::accent(), if limit < MAX_DOWNLOAD_LIMIT { Some(Message::SetDownloadLimit(limit + 1)) } else { None }))
+        .push(queue_button("Clear", ResonateColour::red(), Some(Message::ClearDownloads)));
+
+    let mut list = Column::new().spacing(10);
+    for (song, state) in entries {
+        let (status, colour) = match &state.status {
+            DownloadStatus::Queued => (String::from("Queued"), ResonateColour::text()),
+            DownloadStatus::Downloading => (match &state.progress {
+                Some(progress) => format!("{:.1}%{}{}",
+                    progress.percent,
+                    progress.speed.as_ref().map(|speed| format!(" at {speed}")).unwrap_or_default(),
+                    progress.eta.as_ref().map(|eta| format!(", {eta} left")).unwrap_or_default()),
+                None => String::from("Starting")
+            }, ResonateColour::yellow()),
+            DownloadStatus::Retrying(seconds) => (format!("Attempt {} failed, retrying in {seconds}s", state.attempt), ResonateColour::yellow()),
+            DownloadStatus::Finished => (String::from("Done"), ResonateColour::green()),
+            DownloadStatus::Failed(e) => (e.to_string(), ResonateColour::red()),
+            DownloadStatus::Cancelled => (String::from("Cancelled"), ResonateColour::text())
+        };
+
+        let action = if state.status.is_pending() {
+            queue_button("Cancel", ResonateColour::red(), Some(Message::CancelDownload(song.clone())))
+        } else if matches!(state.status, DownloadStatus::Failed(_) | DownloadStatus::Cancelled) {
+            queue_button("Retry", ResonateColour::blue(), Some(Message::RetryDownload(song.clone())))
+        } else {
+            Row::new().into()
+        };
+
+        let mut details = Column::new()
+            .spacing(5)
+            .push(text(song.name).color(ResonateColour::text_emphasis()))
+            .push(text(status).size(12).color(colour));
+
+        if let (DownloadStatus::Downloading, Some(progress)) = (&state.status, &state.progress) {
+            details = details.push(progress_bar(0f32..=100f32, progress.percent).height(6)
+                .style(|_theme: &Theme| progress_bar::Style {
+                    background: Background::Color(ResonateColour::background()),
+                    bar: Background::Color(ResonateColour::green()),
+                    border: Border::default().rounded(3)
+                }));
+        }
+
+        list = list.push(Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(details.width(Length::Fill))
+            .push(action));
+    }
+
+    Container::new(Column::new().spacing(10).push(header).push(Scrollable::new(list).height(Length::Fixed(200f32))))
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+/// Entries of an imported playlist file that matched nothing in the library, each with a button to
+/// search for it.
+pub fn unmatched_entries_widget(entries: &[PlaylistEntry]) -> Element<'static, Message> {
+    let header = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text(format!("{} songs could not be found", entries.len())).color(ResonateColour::text_emphasis()).size(20).width(Length::Fill))
+        .push(queue_button("Dismiss", ResonateColour::accent(), Some(Message::DismissUnmatched)));
+
+    let rows = entries.iter().fold(Column::new().spacing(5), |column, entry| column.push(
+        Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(entry.describe()).size(12).color(ResonateColour::text()).width(Length::Fill))
+            .push(queue_button("Search", ResonateColour::blue(), Some(Message::SearchUnmatched(entry.clone()))))
+    ));
+
+    Container::new(Column::new().spacing(5).push(header).push(Scrollable::new(rows).height(Length::Fixed(150f32))))
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+pub fn import_widget(progress: ImportProgress) -> Element<'static, Message> {
+    let title = if progress.finished { "Import finished" } else { "Importing" };
+    let mut header = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text(title).color(ResonateColour::text_emphasis()).size(20).width(Length::Fill))
+        .push(text(format!("{} / {}", progress.processed, progress.total)).color(ResonateColour::text()));
+    if progress.finished {
+        header = header.push(queue_button("Dismiss", ResonateColour::accent(), Some(Message::DismissImport)));
+    }
+
+    let fraction = if progress.total == 0 { 0f32 } else { progress.processed as f32 / progress.total as f32 };
+    let mut column = Column::new()
+        .spacing(5)
+        .push(header)
+        .push(progress_bar(0f32..=1f32, fraction).height(6)
+            .style(|_theme: &Theme| progress_bar::Style {
+                background: Background::Color(ResonateColour::background()),
+                bar: Background::Color(ResonateColour::green()),
+                border: Border::default().rounded(3)
+            }))
+        .push(text(format!("{} imported, {} already in library, {} failed", progress.imported, progress.skipped, progress.failed.len()))
+            .size(12)
+            .color(ResonateColour::text()));
+
+    if let Some(current) = progress.current.as_ref() {
+        column = column.push(text(current.to_string_lossy().to_string()).size(12).color(ResonateColour::text()));
+    }
+
+    if !progress.failed.is_empty() {
+        let failures = progress.failed.iter().fold(Column::new().spacing(5), |column, (file, e)| column.push(
+            text(format!("{}: {e}", file.to_string_lossy())).size(12).color(ResonateColour::red())
+        ));
+        column = column.push(Scrollable::new(failures).height(Length::Fixed(100f32)));
+    }
+
+    Container::new(column)
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+pub fn watch_folders_widget(folders: Vec<PathBuf>, status: WatchStatus, missing: usize) -> Element<'static, Message> {
+    let header = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text("Watched Folders").color(ResonateColour::text_emphasis()).size(20).width(Length::Fill))
+        .push(queue_button("Add", ResonateColour::blue(), Some(Message::WatchFolder)));
+
+    let summary = if status.is_busy { String::from("Syncing...") } else {
+        format!("{} imported, {} updated and {} lost this session, {} songs missing their source file", status.imported, status.updated, status.lost, missing)
+    };
+
+    let column = folders.into_iter().fold(Column::new().spacing(10).push(header), |column, folder| column.push(
+        Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(folder.to_string_lossy().to_string()).color(ResonateColour::text()).width(Length::Fill))
+            .push(queue_button("Stop", ResonateColour::red(), Some(Message::UnwatchFolder(folder))))
+    )).push(text(summary).size(12).color(ResonateColour::text()));
+
+    Container::new(column)
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+const STAR_LABELS: [&str; MAX_RATING as usize] = ["1", "2", "3", "4", "5"];
+
+/// A song's like button and star rating. Pressing the rating a song already has clears it.
+fn preference_widget(song: &Song) -> Element<'static, Message> {
+    let like = match song.favourite {
+        true => queue_button("Liked", ResonateColour::red(), Some(Message::FavouriteSong(song.clone(), false))),
+        false => queue_button("Like", ResonateColour::accent(), Some(Message::FavouriteSong(song.clone(), true)))
+    };
+    (1..=MAX_RATING).fold(Row::new().spacing(2).align_y(Vertical::Center).push(like), |row, star| {
+        let colour = if star <= song.rating { ResonateColour::yellow() } else { ResonateColour::accent() };
+        let rating = if star == song.rating { 0 } else { star };
+        row.push(queue_button(STAR_LABELS[star as usize - 1], colour, Some(Message::RateSong(song.clone(), rating))))
+    }).into()
+}
+
+fn queue_button(label: &'static str, colour: Color, on_press: Option<Message>) -> Element<'static, Message> {
+    button(text(label).size(12))
+        .style(move |_theme: &Theme, style| button::Style {
+            background: match style {
+                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(colour))),
+                button::Status::Disabled => Some(Background::Color(ResonateColour::foreground())),
+                _ => Some(Background::Color(colour))
+            },
+            border: Border::default().rounded(10),
+            shadow: Shadow::default(),
+            text_color: ResonateColour::text_emphasis(),
+        })
+        .on_press_maybe(on_press)
+        .into()
+}
+
+pub fn download_song_widget(song: Song, sources: &[SearchSource], status: Option<DownloadStatus>, playlist: Playlist) -> Element<'static, Message> {
     let song_clone = song.clone();
 
     let add_button = button("Add to Playlist")
@@ -311,28 +663,32 @@ pub fn download_song_widget(song: Song, directory: PathBuf, is_downloading: bool
             shadow: Shadow::default(),
             text_color: ResonateColour::text_emphasis(),
         })
-        .on_press_with(move || Message::Download(song_clone.clone(), directory.clone(), playlist.clone()));
-
-    let downloaded = text(match song.file.clone() {
-        Some(p) => p.to_string_lossy().to_string(),
-        None => if is_downloading { String::from("DOWNLOADING") } else if is_queued { String::from("QUEUED") } else { String::from("Not downloaded.") }
-    })
-        .color(match song.file {
-            Some(_) => ResonateColour::green(),
-            None => if is_downloading { ResonateColour::yellow() } else if is_queued { ResonateColour::red() } else { ResonateColour::text() }
-        });
+        .on_press_with(move || Message::Download(song_clone.clone(), playlist.clone()));
+
+    let downloaded = match song.file.clone() {
+        Some(p) => text(p.to_string_lossy().to_string()).color(ResonateColour::green()),
+        None => match status {
+            Some(DownloadStatus::Queued) => text("QUEUED").color(ResonateColour::red()),
+            Some(DownloadStatus::Downloading) | Some(DownloadStatus::Retrying(_)) => text("DOWNLOADING").color(ResonateColour::yellow()),
+            Some(DownloadStatus::Failed(_)) => text("FAILED").color(ResonateColour::red()),
+            _ => text("Not downloaded.").color(ResonateColour::text())
+        }
+    };
 
     let title = text(song.name).color(ResonateColour::text_emphasis()).size(25);
     let artist = text(song.artist).color(ResonateColour::text());
     let album = text(song.album).color(ResonateColour::text());
     let duration = text(format!("{} seconds", song.duration)).color(ResonateColour::text());
 
+    let badges = sources.iter().fold(Row::new().spacing(5), |row, source| row.push(source_badge(*source)));
+
     let row = Row::new()
         .spacing(30)
         .push(
             Column::new()
                 .push(title)
                 .push(artist)
+                .push(badges)
                 .width(Length::FillPortion(3))
         )
         .push(album.width(Length::FillPortion(2)))
@@ -354,7 +710,28 @@ pub fn download_song_widget(song: Song, directory: PathBuf, is_downloading: bool
         .into()
 }
 
-pub fn playlist_search_bar(prompt: String, content: &String) -> Element<'static, Message> {
+fn source_colour(source: SearchSource) -> Color {
+    match source {
+        SearchSource::Library => ResonateColour::green(),
+        SearchSource::Folder => ResonateColour::yellow(),
+        SearchSource::YouTubeMusic => ResonateColour::red(),
+        SearchSource::YtDlp => ResonateColour::blue()
+    }
+}
+
+fn source_badge(source: SearchSource) -> Element<'static, Message> {
+    Container::new(text(source.label()).size(12).color(ResonateColour::text_emphasis()))
+        .padding([2, 8])
+        .style(move |_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::darken(source_colour(source))))
+                .border(Border::default().rounded(10))
+        })
+        .into()
+}
+
+pub fn playlist_search_bar(prompt: String, content: &str, is_retagging: bool, is_importing_link: bool) -> Element<'static, Message> {
+    let can_import_link = is_url(content) && !is_importing_link;
     let widget = Row::new()
         .spacing(20)
         .push(text_input(prompt.as_str(), content)
@@ -369,6 +746,50 @@ pub fn playlist_search_bar(prompt: String, content: &String) -> Element<'static,
                 value: ResonateColour::text_emphasis(),
                 selection: ResonateColour::red()
             }))
+        .push(button("Library")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::green()))),
+                    _ => Some(Background::Color(ResonateColour::green()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+        .on_press(Message::OpenLibrary))
+        .push(button("Statistics")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::green()))),
+                    _ => Some(Background::Color(ResonateColour::green()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+        .on_press(Message::OpenStatistics))
+        .push(button("Equalizer")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::green()))),
+                    _ => Some(Background::Color(ResonateColour::green()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+        .on_press(Message::OpenEqualizer))
+        .push(button("Liked Songs")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::green()))),
+                    _ => Some(Background::Color(ResonateColour::green()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+        .on_press(Message::OpenPlaylist(Playlist::liked())))
         .push(button("New Playlist")
             .style(|_theme: &Theme, style| button::Style {
                 background: match style {
@@ -380,6 +801,51 @@ pub fn playlist_search_bar(prompt: String, content: &String) -> Element<'static,
                 text_color: ResonateColour::text(),
             })
         .on_press(Message::NewPlaylist))
+        .push(button("Import Folder")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
+                    _ => Some(Background::Color(ResonateColour::blue()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text(),
+            })
+        .on_press(Message::ImportFolder))
+        .push(button("Import Playlist")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
+                    _ => Some(Background::Color(ResonateColour::blue()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text(),
+            })
+        .on_press(Message::ImportPlaylistFile))
+        .push(button(if is_importing_link { "Importing..." } else { "Import Link" })
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
+                    _ => Some(Background::Color(ResonateColour::blue()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text(),
+            })
+        .on_press_maybe(if can_import_link { Some(Message::ImportLink) } else { None }))
+        .push(button(if is_retagging { "Re-tagging..." } else { "Re-tag Files" })
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
+                    button::Status::Disabled => Some(Background::Color(ResonateColour::accent())),
+                    _ => Some(Background::Color(ResonateColour::blue()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text(),
+            })
+        .on_press_maybe(if is_retagging { None } else { Some(Message::RetagLibrary) }))
         .align_y(Vertical::Center);
     
     Container::new(widget)
@@ -393,7 +859,7 @@ pub fn playlist_search_bar(prompt: String, content: &String) -> Element<'static,
         .into()
 }
 
-pub fn search_bar(prompt: String, content: &String, toggle: bool) -> Element<'static, Message> {
+pub fn search_bar(prompt: String, content: &str, providers: Vec<(SearchSource, bool)>) -> Element<'static, Message> {
     let widget = Row::new()
         .spacing(20)
         .push(text_input(prompt.as_str(), content)
@@ -419,19 +885,21 @@ pub fn search_bar(prompt: String, content: &String, toggle: bool) -> Element<'st
                 text_color: ResonateColour::text(),
             })
             .on_press(Message::DumpDB))
-        .push(toggler(toggle)
-            .size(30)
-            .on_toggle(Message::ToggleYTSearch)
-            .style(move |_theme: &Theme, _style| toggler::Style {
-                background: ResonateColour::background(),
-                background_border_width: 0f32,
-                background_border_color: ResonateColour::background(),
-                foreground: if toggle { ResonateColour::green() } else { ResonateColour::red() },
-                foreground_border_width: 0f32,
-                foreground_border_color: ResonateColour::foreground()
-            })
-            .width(Length::FillPortion(1))
-        )
+        .push(providers.into_iter().fold(Column::new().spacing(5), |column, (source, enabled)| column.push(
+            toggler(enabled)
+                .label(source.label())
+                .size(20)
+                .text_size(14)
+                .on_toggle(move |enabled| Message::ToggleProvider(source, enabled))
+                .style(move |_theme: &Theme, _style| toggler::Style {
+                    background: ResonateColour::background(),
+                    background_border_width: 0f32,
+                    background_border_color: ResonateColour::background(),
+                    foreground: if enabled { ResonateColour::green() } else { ResonateColour::red() },
+                    foreground_border_width: 0f32,
+                    foreground_border_color: ResonateColour::foreground()
+                })
+        )).width(Length::FillPortion(2)))
         .push(button("Upload File")
         .style(|_theme: &Theme, style| button::Style {
                 background: Some(Background::Color(match style {
@@ -456,12 +924,12 @@ pub fn search_bar(prompt: String, content: &String, toggle: bool) -> Element<'st
         .into()
 }
 
-pub fn playlist_name_widget(prompt: String, content: &String) -> Element<'static, Message> {
+pub fn playlist_name_widget(prompt: String, content: &str, action: &str, submit: Message) -> Element<'static, Message> {
     let widget = Row::new()
         .spacing(20)
         .push(text_input(prompt.as_str(), content)
             .on_input(Message::SearchBarInput)
-            .on_submit(Message::CreateNewPlaylist)
+            .on_submit(submit.clone())
             .width(Length::FillPortion(1))
             .style(|_theme: &Theme, _style| text_input::Style {
                 background: Background::Color(ResonateColour::accent()),
@@ -471,7 +939,7 @@ pub fn playlist_name_widget(prompt: String, content: &String) -> Element<'static
                 value: ResonateColour::text_emphasis(),
                 selection: ResonateColour::red()
             }))
-        .push(button("Create")
+        .push(button(text(action.to_string()))
             .style(|_theme: &Theme, style| button::Style {
                 background: match style {
                     button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::blue()))),
@@ -481,7 +949,7 @@ pub fn playlist_name_widget(prompt: String, content: &String) -> Element<'static
                 shadow: Shadow::default(),
                 text_color: ResonateColour::text(),
             })
-        .on_press(Message::CreateNewPlaylist))
+        .on_press(submit))
         .align_y(Vertical::Center);
     
     Container::new(widget)
@@ -521,3 +989,407 @@ pub fn upload_album_entry(content: String) -> Element<'static, Message> {
         .on_input(Message::AlbumChanged)
         .into()
 }
+
+fn format_duration(seconds: usize) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Shared by the library's column headers and rows so that they line up
+const LIBRARY_ACTIONS_WIDTH: f32 = 260f32;
+const RATING_WIDTH: f32 = 170f32;
+
+fn library_column_width(column: SortColumn) -> Length {
+    match column {
+        SortColumn::Title => Length::FillPortion(3),
+        SortColumn::Artist | SortColumn::Album => Length::FillPortion(2),
+        SortColumn::Duration | SortColumn::Added | SortColumn::Plays => Length::FillPortion(1),
+        SortColumn::Rating => Length::Fixed(RATING_WIDTH)
+    }
+}
+
+fn selectable_button(label: String, selected: bool, on_press: Message) -> button::Button<'static, Message> {
+    let colour = if selected { ResonateColour::blue() } else { ResonateColour::accent() };
+    button(text(label).size(14))
+        .style(move |_theme: &Theme, style| button::Style {
+            background: match style {
+                button::Status::Hovered => Some(Background::Color(ResonateColour::darken(colour))),
+                _ => Some(Background::Color(colour))
+            },
+            border: Border::default().rounded(10),
+            shadow: Shadow::default(),
+            text_color: ResonateColour::text_emphasis(),
+        })
+        .on_press(on_press)
+}
+
+/// The filter box, download filter and the sortable column headers.
+pub fn library_controls(view: &LibraryView, count: usize) -> Element<'static, Message> {
+    let filters = DownloadFilter::ALL.into_iter().fold(Row::new().spacing(10), |row, filter| row.push(
+        selectable_button(filter.label().to_string(), view.downloaded == filter, Message::SetDownloadFilter(filter))
+    ));
+
+    let top = Row::new()
+        .spacing(20)
+        .align_y(Vertical::Center)
+        .push(button("Home").on_press(Message::Homepage))
+        .push(text_input("Filter by title, artist or album, or rating:4 and is:liked...", &view.filter)
+            .on_input(Message::LibraryFilterInput)
+            .width(Length::Fill)
+            .style(|_theme: &Theme, _style| text_input::Style {
+                background: Background::Color(ResonateColour::accent()),
+                border: Border::default().rounded(10),
+                icon: ResonateColour::accent(),
+                placeholder: ResonateColour::text(),
+                value: ResonateColour::text_emphasis(),
+                selection: ResonateColour::red()
+            }))
+        .push(filters)
+        .push(text(format!("{count} songs")).color(ResonateColour::text()));
+
+    let headers = SortColumn::ALL.into_iter().fold(Row::new().spacing(10), |row, column| {
+        let arrow = match (view.sort == column, view.descending) {
+            (true, true) => " v",
+            (true, false) => " ^",
+            (false, _) => ""
+        };
+        row.push(selectable_button(format!("{}{arrow}", column.label()), view.sort == column, Message::SortLibrary(column))
+            .width(library_column_width(column)))
+    }).push(Space::with_width(Length::Fixed(LIBRARY_ACTIONS_WIDTH)));
+
+    Container::new(Column::new().spacing(10).push(top).push(headers.padding([0, 20])))
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+fn browser_column<K: PartialEq>(title: &'static str, items: Vec<(K, String, usize)>, selected: Option<&K>, on_select: fn(Option<K>) -> Message) -> Element<'static, Message> {
+    let list = items.into_iter().fold(
+        Column::new().spacing(5).push(selectable_button(format!("All {title}"), selected.is_none(), on_select(None)).width(Length::Fill)),
+        |column, (key, label, count)| {
+            let is_selected = selected == Some(&key);
+            column.push(selectable_button(format!("{label} ({count})"), is_selected, on_select(Some(key))).width(Length::Fill))
+        });
+    Scrollable::new(list).width(Length::FillPortion(1)).height(Length::Fixed(200f32)).into()
+}
+
+/// Artist and album columns for narrowing the library down to a single album.
+pub fn library_browser(view: &LibraryView, artists: Vec<(String, String, usize)>, albums: Vec<(usize, String, usize)>) -> Element<'static, Message> {
+    Row::new()
+        .spacing(20)
+        .push(browser_column("Artists", artists, view.artist.as_ref(), Message::BrowseArtist))
+        .push(browser_column("Albums", albums, view.album.as_ref(), Message::BrowseAlbum))
+        .into()
+}
+
+pub fn library_song_widget(entry: LibraryEntry, is_playing: bool, is_paused: bool, status: Option<DownloadStatus>) -> Element<'static, Message> {
+    let song = entry.song;
+    let play = match (is_playing, is_paused) {
+        (true, true) => queue_button("Resume", ResonateColour::green(), Some(Message::Resume)),
+        (true, false) => queue_button("Pause", ResonateColour::red(), Some(Message::Pause)),
+        (false, _) => queue_button("Play", ResonateColour::green(), song.file.as_ref().map(|_| Message::Play(song.clone())))
+    };
+
+    let actions = match (song.file.is_some(), status) {
+        (true, _) => Row::new().spacing(10)
+            .push(play)
+            .push(queue_button("Play Next", ResonateColour::blue(), Some(Message::PlayNext(song.clone()))))
+            .push(queue_button("Queue", ResonateColour::blue(), Some(Message::Queue(song.clone())))),
+        (false, Some(status)) if status.is_pending() => Row::new().push(text("Downloading...").size(12).color(ResonateColour::text())),
+        (false, _) => Row::new().push(queue_button("Download", ResonateColour::blue(), Some(Message::QueueDownload(song.clone()))))
+    };
+
+    let cell = |value: String, column: SortColumn, emphasis: bool| text(value)
+        .size(14)
+        .color(if emphasis { ResonateColour::text_emphasis() } else { ResonateColour::text() })
+        .width(library_column_width(column));
+
+    let row = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(cell(song.name.clone(), SortColumn::Title, true))
+        .push(cell(song.artist.clone(), SortColumn::Artist, false))
+        .push(cell(song.album.clone(), SortColumn::Album, false))
+        .push(cell(format_duration(song.duration), SortColumn::Duration, false))
+        .push(cell(entry.added.as_ref().map(|added| added.chars().take(10).collect()).unwrap_or_else(|| String::from("-")), SortColumn::Added, false))
+        .push(cell(entry.plays.to_string(), SortColumn::Plays, false))
+        .push(Container::new(preference_widget(&song)).width(library_column_width(SortColumn::Rating)))
+        .push(Container::new(actions.align_y(Vertical::Center)).width(Length::Fixed(LIBRARY_ACTIONS_WIDTH)));
+
+    Container::new(row)
+        .padding([10, 20])
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(Color::from_rgb(0.15f32, 0.15f32, 0.15f32)))
+                .border(Border::default().rounded(10))
+        })
+        .into()
+}
+
+fn statistics_panel(title: &'static str, content: Column<'static, Message>) -> Element<'static, Message> {
+    Container::new(Column::new().spacing(10).push(text(title).size(20).color(ResonateColour::text_emphasis())).push(content))
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
+
+fn ranking_row(rank: usize, label: String, detail: String, plays: usize) -> Row<'static, Message> {
+    Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text(format!("{rank}.")).size(14).color(ResonateColour::text()).width(Length::Fixed(30f32)))
+        .push(Column::new()
+            .push(text(label).size(14).color(ResonateColour::text_emphasis()))
+            .push(text(detail).size(12).color(ResonateColour::text()))
+            .width(Length::Fill))
+        .push(text(format!("{plays} plays")).size(14).color(ResonateColour::text()))
+}
+
+/// Listening totals, the most played songs and artists, recent plays and listening time per day.
+pub fn statistics_widget(statistics: &Statistics) -> Column<'static, Message> {
+    let summary = Row::new()
+        .spacing(20)
+        .align_y(Vertical::Center)
+        .push(button("Home").on_press(Message::Homepage))
+        .push(text(format!("{} plays", statistics.total_plays)).size(25).color(ResonateColour::text_emphasis()))
+        .push(text(format!("{} listened", format_listening_time(statistics.total_listened))).size(25).color(ResonateColour::text_emphasis()));
+
+    let top_songs = statistics.top_songs.iter().enumerate().fold(Column::new().spacing(5), |column, (index, (song, plays))| {
+        column.push(ranking_row(index + 1, song.name.clone(), song.artist.clone(), *plays))
+    });
+
+    let top_artists = statistics.top_artists.iter().enumerate().fold(Column::new().spacing(5), |column, (index, (artist, plays))| {
+        column.push(ranking_row(index + 1, artist.clone(), String::new(), *plays))
+    });
+
+    let most_listened = statistics.daily.iter().map(|(_, seconds)| *seconds).fold(0f32, f32::max);
+    let daily = statistics.daily.iter().fold(Column::new().spacing(5), |column, (day, seconds)| column.push(
+        Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(day.clone()).size(12).color(ResonateColour::text()).width(Length::Fixed(90f32)))
+            .push(progress_bar(0f32..=most_listened.max(1f32), *seconds).height(10)
+                .style(|_theme: &Theme| progress_bar::Style {
+                    background: Background::Color(ResonateColour::background()),
+                    bar: Background::Color(ResonateColour::green()),
+                    border: Border::default().rounded(5)
+                }))
+            .push(text(format_listening_time(*seconds)).size(12).color(ResonateColour::text()).width(Length::Fixed(60f32)))
+    ));
+
+    let recent = statistics.recent.iter().fold(Column::new().spacing(5), |column, play| column.push(
+        Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(play.played_at.clone()).size(12).color(ResonateColour::text()).width(Length::Fixed(130f32)))
+            .push(text(format!("{} - {}", play.song.artist, play.song.name)).size(14).color(ResonateColour::text_emphasis()).width(Length::Fill))
+            .push(text(if play.skipped { "Skipped" } else { "" }).size(12).color(ResonateColour::text()))
+    ));
+
+    Column::new()
+        .spacing(10)
+        .push(summary)
+        .push(Scrollable::new(Column::new()
+            .spacing(10)
+            .push(Row::new()
+                .spacing(10)
+                .push(statistics_panel("Most Played Songs", top_songs))
+                .push(statistics_panel("Most Played Artists", top_artists)))
+            .push(statistics_panel("Listening Time", daily))
+            .push(statistics_panel("Recently Played", recent))))
+}
+
+/// Picks the preset that overrides the equalizer for a song or playlist.
+pub fn preset_picker(equalizer: &EqualizerSettings, preset: Option<String>, on_select: impl Fn(PresetChoice) -> Message + 'static) -> Element<'static, Message> {
+    pick_list(PresetChoice::options(equalizer), Some(PresetChoice::from_override(preset)), on_select).into()
+}
+
+/// The equalizer's bands and presets, and the preset override of the song that is playing.
+pub fn equalizer_widget(equalizer: &EqualizerSettings, preset_name: &str, current: Option<Song>) -> Column<'static, Message> {
+    let header = Row::new()
+        .spacing(20)
+        .align_y(Vertical::Center)
+        .push(button("Home").on_press(Message::Homepage))
+        .push(text("Equalizer").size(25).color(ResonateColour::text_emphasis()))
+        .push(toggler(equalizer.enabled)
+            .label("Enabled")
+            .on_toggle(Message::SetEqualizerEnabled));
+
+    let selected = equalizer.current_preset().map(|preset| preset.name.clone());
+    let presets = equalizer.presets.chunks(4).fold(Column::new().spacing(10), |column, presets| column.push(
+        presets.iter().fold(Row::new().spacing(10).align_y(Vertical::Center), |row, preset| {
+            let row = row.push(selectable_button(preset.name.clone(), selected.as_ref() == Some(&preset.name), Message::ApplyPreset(preset.name.clone())));
+            match preset.built_in {
+                true => row,
+                false => row.push(button(text("x").size(14))
+                    .style(|_theme: &Theme, style| button::Style {
+                        background: match style {
+                            button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::red()))),
+                            _ => Some(Background::Color(ResonateColour::red()))
+                        },
+                        border: Border::default().rounded(10),
+                        shadow: Shadow::default(),
+                        text_color: ResonateColour::text_emphasis(),
+                    })
+                    .on_press(Message::DeletePreset(preset.name.clone())))
+            }
+        })
+    ));
+
+    let bands = BAND_FREQUENCIES.iter().zip(equalizer.gains).enumerate().fold(Row::new().spacing(20), |row, (band, (frequency, gain))| {
+        let label = match *frequency >= 1000f32 {
+            true => format!("{}k", frequency / 1000f32),
+            false => format!("{}", frequency.round())
+        };
+        row.push(Column::new()
+            .spacing(10)
+            .align_x(Horizontal::Center)
+            .push(text(format!("{gain:+.0}")).size(14).color(ResonateColour::text_emphasis()))
+            .push(vertical_slider(RangeInclusive::new(-MAX_BAND_GAIN, MAX_BAND_GAIN), gain, move |gain| Message::SetBandGain(band, gain))
+                .step(1f32)
+                .height(Length::Fixed(200f32)))
+            .push(text(label).size(12).color(ResonateColour::text())))
+    });
+
+    // Built-in presets cannot be overwritten
+    let can_save = !preset_name.trim().is_empty() && !equalizer.preset(preset_name.trim()).is_some_and(|preset| preset.built_in);
+    let save = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text_input("Preset name", preset_name)
+            .on_input(Message::PresetNameInput)
+            .on_submit(Message::SavePreset)
+            .width(Length::Fixed(250f32))
+            .style(|_theme: &Theme, _style| text_input::Style {
+                background: Background::Color(ResonateColour::accent()),
+                border: Border::default().rounded(10),
+                icon: ResonateColour::accent(),
+                placeholder: ResonateColour::text(),
+                value: ResonateColour::text_emphasis(),
+                selection: ResonateColour::red()
+            }))
+        .push(button("Save Preset")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::green()))),
+                    _ => Some(Background::Color(ResonateColour::green()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+            .on_press_maybe(can_save.then_some(Message::SavePreset)));
+
+    let song = match current {
+        Some(song) => Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(text(format!("{} - {}", song.artist, song.name)).color(ResonateColour::text_emphasis()).width(Length::Fill))
+            .push(preset_picker(equalizer, song.equalizer.clone(), move |choice| Message::SetSongPreset(song.clone(), choice))),
+        None => Row::new().push(text("Nothing is playing.").color(ResonateColour::text()))
+    };
+
+    Column::new()
+        .spacing(10)
+        .push(header)
+        .push(Scrollable::new(Column::new()
+            .spacing(10)
+            .push(statistics_panel("Presets", presets.push(save)))
+            .push(statistics_panel("Bands", Column::new().push(bands)))
+            .push(statistics_panel("Now Playing", Column::new().push(song)))))
+}
+
+/// The rules, sort and limit of a smart playlist, edited as a draft until they are saved.
+pub fn smart_playlist_editor(draft: &SmartPlaylist, limit: &str) -> Element<'static, Message> {
+    let input_style = |_theme: &Theme, _style| text_input::Style {
+        background: Background::Color(ResonateColour::accent()),
+        border: Border::default().rounded(10),
+        icon: ResonateColour::accent(),
+        placeholder: ResonateColour::text(),
+        value: ResonateColour::text_emphasis(),
+        selection: ResonateColour::red()
+    };
+
+    let matching = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text("Match").color(ResonateColour::text_emphasis()))
+        .push(selectable_button(String::from("All"), draft.match_all, Message::SetRuleMatch(true)))
+        .push(selectable_button(String::from("Any"), !draft.match_all, Message::SetRuleMatch(false)))
+        .push(text("of these rules").color(ResonateColour::text_emphasis()));
+
+    let rules = draft.rules.iter().enumerate().fold(Column::new().spacing(10), |column, (index, rule)| {
+        let row = Row::new()
+            .spacing(10)
+            .align_y(Vertical::Center)
+            .push(pick_list(RuleField::ALL, Some(rule.field), move |field| Message::SetRuleField(index, field)).width(Length::FillPortion(2)))
+            .push(pick_list(rule.field.operators(), Some(rule.operator), move |operator| Message::SetRuleOperator(index, operator)).width(Length::FillPortion(2)));
+        let row = match rule.field.has_value() {
+            true => row.push(text_input("Value", &rule.value)
+                .on_input(move |value| Message::SetRuleValue(index, value))
+                .width(Length::FillPortion(3))
+                .style(input_style)),
+            false => row.push(Space::with_width(Length::FillPortion(3)))
+        };
+        column.push(row.push(button("Remove")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::red()))),
+                    _ => Some(Background::Color(ResonateColour::red()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+            .on_press(Message::RemoveRule(index))))
+    });
+
+    let ordering = Row::new()
+        .spacing(10)
+        .align_y(Vertical::Center)
+        .push(text("Sort by").color(ResonateColour::text_emphasis()))
+        .push(pick_list(SmartSort::ALL, Some(draft.sort), Message::SetSmartSort))
+        .push(toggler(draft.descending)
+            .label("Descending")
+            .on_toggle(Message::SetSmartDescending))
+        .push(text("Limit").color(ResonateColour::text_emphasis()))
+        .push(text_input("No limit", limit)
+            .on_input(Message::SetSmartLimit)
+            .width(Length::Fixed(100f32))
+            .style(input_style));
+
+    let actions = Row::new()
+        .spacing(20)
+        .push(button("Add Rule").on_press(Message::AddRule))
+        .push(button("Save Rules")
+            .style(|_theme: &Theme, style| button::Style {
+                background: match style {
+                    button::Status::Hovered => Some(Background::Color(ResonateColour::darken(ResonateColour::green()))),
+                    _ => Some(Background::Color(ResonateColour::green()))
+                },
+                border: Border::default().rounded(10),
+                shadow: Shadow::default(),
+                text_color: ResonateColour::text_emphasis(),
+            })
+            .on_press(Message::SaveRules));
+
+    Container::new(Column::new().spacing(15).push(matching).push(rules).push(ordering).push(actions))
+        .padding(20)
+        .width(Length::Fill)
+        .style(|_theme: &Theme| {
+            container::Style::default()
+                .background(Background::Color(ResonateColour::foreground()))
+                .border(Border::default().rounded(15))
+        })
+        .into()
+}
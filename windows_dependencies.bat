@@ -1,14 +1,10 @@
 @echo off
 echo Installing Dependencies...
-winget install winget install --id Google.Chrome --exact --silent --accept-package-agreements --accept-source-agreements
-echo CHROME installed [1/5]
-winget install Chromium.ChromeDriver
-echo CHROMEDRIVER installed [2/5]
 winget install SQLite.SQlite
-echo SQLITE installed [3/4]
+echo SQLITE installed [1/3]
 winget install yt-dlp
-echo YTDLP installed [4/5]
+echo YTDLP installed [2/3]
 winget install Gyan.FFmpeg
-echo FFMPEG installed [5/5]
+echo FFMPEG installed [3/3]
 echo FINISHED
 pause